@@ -1,8 +1,9 @@
 use async_trait::async_trait;
 use mongodb::Client;
-use ply_jobs::{schedule, Job, JobConfig, JobError, JobManager, MongoRepo};
+use ply_jobs::{
+    schedule, InstanceId, Job, JobConfig, JobContext, JobError, JobManager, JobOutput, MongoRepo,
+};
 use serde::{Deserialize, Serialize};
-use std::process;
 use tokio::time::{sleep, Duration};
 
 #[tokio::main]
@@ -24,7 +25,7 @@ async fn main() {
     // );
     // let repo = PickleDbRepo::new(db_client);
 
-    let mut manager = JobManager::new(process::id().to_string(), repo);
+    let mut manager = JobManager::new(InstanceId::generate(), repo);
 
     let job = CountJob {
         client: reqwest::Client::new(),
@@ -34,7 +35,7 @@ async fn main() {
 
     manager.register(config, job);
 
-    let _ = manager.start_all();
+    manager.start_all();
     sleep(Duration::from_secs(120)).await;
 
     // manager
@@ -52,19 +53,20 @@ struct CountJob {
     client: reqwest::Client,
 }
 
-#[derive(Clone, Serialize, Deserialize, Debug, Default)]
-struct Counter(i32);
-
 #[async_trait]
 impl Job for CountJob {
-    async fn call(&mut self, state: Vec<u8>) -> std::result::Result<Vec<u8>, JobError> {
-        let mut data: State = if state.len() == 0 {
+    async fn call(
+        &mut self,
+        ctx: &JobContext,
+        state: Vec<u8>,
+    ) -> std::result::Result<JobOutput, JobError> {
+        let mut data: State = if state.is_empty() {
             State(0)
         } else {
             serde_json::from_slice(&state).unwrap()
         };
 
-        println!("Count: {}", data.0);
+        ctx.logger().info(format!("Count: {}", data.0));
 
         sleep(Duration::from_secs(1)).await;
 
@@ -81,8 +83,10 @@ impl Job for CountJob {
             .await
         {
             Ok(body) => {
-                println!("Time in London: {:?}", body.lines().take(3).last().unwrap());
-                Ok(serde_json::to_vec(&data).unwrap())
+                let time = body.lines().take(3).last().unwrap();
+                ctx.logger().info(format!("Time in London: {:?}", time));
+                Ok(JobOutput::new(serde_json::to_vec(&data).unwrap())
+                    .with_result(time.as_bytes().to_vec()))
             }
             Err(e) => Err(JobError::any(e)),
         }
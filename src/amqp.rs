@@ -0,0 +1,211 @@
+//! Triggering jobs from an AMQP queue, e.g. on RabbitMQ. Every message on the queue requests a
+//! run of the job named in its `job` header, like [`crate::JobManager::trigger`] does, and an
+//! optional `traceparent` header joins the run to the trace of the producer. Competing
+//! consumers on the same queue share the messages, so that every message is handled by one
+//! instance only.
+//!
+//! ```rust,ignore
+//!     let trigger = AmqpTrigger::new("amqp://rabbitmq:5672/%2f", "job-triggers");
+//!     let (stop, stopped) = oneshot::channel();
+//!     tokio::spawn(trigger.run(instance_id.clone(), repo.clone(), stopped));
+//! ```
+//!
+//! The body of a message is not passed on, since jobs take no run parameters. The queue is
+//! not declared by the trigger and has to exist, as part of the topology of the broker.
+use crate::backoff::Backoff;
+use crate::error::{Error, Result};
+use crate::job::RunRequest;
+use crate::manager::valid_trace_parent;
+use crate::repos::Repo;
+use crate::{InstanceId, JobName};
+use futures::StreamExt;
+use lapin::message::Delivery;
+use lapin::options::{
+    BasicAckOptions, BasicConsumeOptions, BasicQosOptions, BasicRejectOptions, QueueDeclareOptions,
+};
+use lapin::types::FieldTable;
+use lapin::{Connection, ConnectionProperties};
+use log::{trace, warn};
+use std::convert::Infallible;
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tokio::time::sleep;
+
+const PREFETCH: u16 = 16;
+
+/// Consumer of an AMQP queue requesting runs of the jobs named in its messages. A message is
+/// acknowledged once the run is requested in the repo. Messages naming no job or an unknown
+/// job are rejected without requeueing, so that a dead letter exchange of the queue can
+/// collect them. If the repo fails, the consumer reconnects after a delay and the broker
+/// redelivers the messages that were not acknowledged yet.
+///
+/// Messages for a job that arrive before its requested run started coalesce into a single run.
+pub struct AmqpTrigger {
+    url: String,
+    queue: String,
+    prefetch: u16,
+}
+
+impl AmqpTrigger {
+    /// Consume the queue named `queue` of the broker at `url`.
+    pub fn new(url: impl Into<String>, queue: impl Into<String>) -> Self {
+        AmqpTrigger {
+            url: url.into(),
+            queue: queue.into(),
+            prefetch: PREFETCH,
+        }
+    }
+    /// Deliver at most `count` messages that are not acknowledged yet. Defaults to 16.
+    pub fn with_prefetch(mut self, count: u16) -> Self {
+        self.prefetch = count;
+        self
+    }
+
+    /// Consume the queue with consumer tag `instance` until `stop` fires, requesting the runs
+    /// in `repo`, the repo of the managers running the jobs. Failures to reach the broker or
+    /// the repo are logged and retried with a growing delay.
+    #[allow(private_bounds)]
+    pub async fn run<R: Repo>(
+        self,
+        instance: impl Into<InstanceId>,
+        mut repo: R,
+        mut stop: oneshot::Receiver<()>,
+    ) -> Result<()> {
+        let consumer = instance.into().0;
+        let mut backoff = Backoff::new(Duration::from_millis(500), Duration::from_secs(30));
+        loop {
+            let consumed = tokio::select! {
+                res = self.consume(&consumer, &mut repo, &mut backoff) => res,
+                _ = &mut stop => return Ok(()),
+            };
+            let Err(e) = consumed;
+            let delay = backoff.next_delay();
+            warn!(
+                "consuming queue {} failed, retrying in {}ms: {}",
+                self.queue,
+                delay.as_millis(),
+                e
+            );
+            tokio::select! {
+                _ = sleep(delay) => {}
+                _ = &mut stop => return Ok(()),
+            }
+        }
+    }
+
+    // Handle the messages of the queue on one connection until the broker or the repo fails.
+    // Closing the connection hands the messages that were not acknowledged back to the broker.
+    async fn consume<R: Repo>(
+        &self,
+        consumer: &str,
+        repo: &mut R,
+        backoff: &mut Backoff,
+    ) -> Result<Infallible> {
+        let connection = Connection::connect(&self.url, ConnectionProperties::default())
+            .await
+            .map_err(repo_error)?;
+        let channel = connection.create_channel().await.map_err(repo_error)?;
+        channel
+            .basic_qos(self.prefetch, BasicQosOptions::default())
+            .await
+            .map_err(repo_error)?;
+        let passive = QueueDeclareOptions {
+            passive: true,
+            ..QueueDeclareOptions::default()
+        };
+        channel
+            .queue_declare(&self.queue, passive, FieldTable::default())
+            .await
+            .map_err(repo_error)?;
+        let mut deliveries = channel
+            .basic_consume(
+                &self.queue,
+                consumer,
+                BasicConsumeOptions::default(),
+                FieldTable::default(),
+            )
+            .await
+            .map_err(repo_error)?;
+        backoff.reset();
+        while let Some(delivery) = deliveries.next().await {
+            self.handle(repo, delivery.map_err(repo_error)?).await?;
+        }
+        Err(Error::Repo(format!(
+            "consumer of queue {} canceled",
+            self.queue
+        )))
+    }
+
+    // Request the run named by the message, acknowledging the message once the run is
+    // requested.
+    async fn handle<R: Repo>(&self, repo: &mut R, delivery: Delivery) -> Result<()> {
+        let tag = delivery.delivery_tag;
+        let Some(job) = header(&delivery, "job") else {
+            warn!(
+                "message {} of queue {} names no job, rejecting it",
+                tag, self.queue
+            );
+            return reject(&delivery).await;
+        };
+        let trace_parent = header(&delivery, "traceparent").filter(|tp| {
+            let valid = valid_trace_parent(tp);
+            if !valid {
+                warn!(
+                    "message {} of queue {} has an invalid traceparent",
+                    tag, self.queue
+                );
+            }
+            valid
+        });
+        let request = RunRequest {
+            triggered: true,
+            trace_parent,
+            ..RunRequest::default()
+        };
+        match repo.request_run(JobName(job.clone()), Some(request)).await {
+            Ok(()) => trace!(
+                "message {} of queue {} triggered job {}",
+                tag,
+                self.queue,
+                job
+            ),
+            Err(Error::NotFound(_)) => {
+                warn!(
+                    "message {} of queue {} names unknown job {}, rejecting it",
+                    tag, self.queue, job
+                );
+                return reject(&delivery).await;
+            }
+            Err(e) => {
+                return Err(Error::Repo(format!(
+                    "triggering job {} from message {} failed: {}",
+                    job, tag, e
+                )))
+            }
+        }
+        delivery
+            .ack(BasicAckOptions::default())
+            .await
+            .map_err(repo_error)
+    }
+}
+
+// A string header of the message, as long or short string.
+fn header(delivery: &Delivery, key: &str) -> Option<String> {
+    let value = delivery.properties.headers().as_ref()?.inner().get(key)?;
+    value
+        .as_long_string()
+        .map(|s| String::from_utf8_lossy(s.as_bytes()).into_owned())
+        .or_else(|| value.as_short_string().map(|s| s.as_str().to_owned()))
+}
+
+async fn reject(delivery: &Delivery) -> Result<()> {
+    delivery
+        .reject(BasicRejectOptions { requeue: false })
+        .await
+        .map_err(repo_error)
+}
+
+fn repo_error(e: lapin::Error) -> Error {
+    Error::Repo(e.to_string())
+}
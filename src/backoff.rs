@@ -0,0 +1,79 @@
+use rand::Rng;
+use std::time::Duration;
+
+/// How a [`Backoff`] randomizes its exponentially growing delays, so that many clients
+/// retrying the same resource do not retry in lockstep.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Jitter {
+    /// The plain exponential delay, useful where the delay has to be predictable.
+    #[default]
+    None,
+    /// A random delay between zero and the exponential delay.
+    Full,
+    /// A random delay between half the exponential delay and the exponential delay.
+    Equal,
+    /// A random delay between the base delay and three times the previous delay.
+    Decorrelated,
+}
+
+/// Exponential backoff, doubling from `base` up to `max` with every attempt.
+/// ```rust,ignore
+///     let mut backoff = Backoff::new(Duration::from_millis(100), Duration::from_secs(10))
+///         .with_jitter(Jitter::Full);
+///     while let Err(e) = call_flaky_service().await {
+///         ctx.logger().warn(format!("attempt {} failed: {}", backoff.attempts() + 1, e));
+///         tokio::time::sleep(backoff.next_delay()).await;
+///     }
+/// ```
+#[derive(Clone, Debug)]
+pub struct Backoff {
+    base: Duration,
+    max: Duration,
+    jitter: Jitter,
+    attempts: u32,
+    last: Duration,
+}
+
+impl Backoff {
+    pub fn new(base: Duration, max: Duration) -> Self {
+        Backoff {
+            base,
+            max: max.max(base),
+            jitter: Jitter::None,
+            attempts: 0,
+            last: base,
+        }
+    }
+    pub fn with_jitter(mut self, jitter: Jitter) -> Self {
+        self.jitter = jitter;
+        self
+    }
+    /// Delay before the next attempt.
+    pub fn next_delay(&mut self) -> Duration {
+        let exponential = self
+            .base
+            .saturating_mul(1 << self.attempts.min(31))
+            .min(self.max);
+        self.attempts = self.attempts.saturating_add(1);
+        let mut rng = rand::thread_rng();
+        let delay = match self.jitter {
+            Jitter::None => exponential,
+            Jitter::Full => rng.gen_range(Duration::ZERO..=exponential),
+            Jitter::Equal => rng.gen_range(exponential / 2..=exponential),
+            Jitter::Decorrelated => rng
+                .gen_range(self.base..=self.last.saturating_mul(3).max(self.base))
+                .min(self.max),
+        };
+        self.last = delay;
+        delay
+    }
+    /// Number of delays handed out since the last reset.
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+    /// Start over from the base delay, e.g. after a successful attempt.
+    pub fn reset(&mut self) {
+        self.attempts = 0;
+        self.last = self.base;
+    }
+}
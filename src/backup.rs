@@ -0,0 +1,356 @@
+use crate::error::Error;
+use crate::job::JobData;
+use crate::schedule::Schedule;
+use crate::{JobMetadata, JobName, RunOutcome, RunRecord};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::io::{self, Write};
+use std::str::FromStr;
+#[cfg(feature = "parquet")]
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Backend independent copy of the scheduler store, serializable e.g. to JSON. Locks are not
+/// part of a snapshot; restored jobs are unlocked.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub jobs: Vec<JobSnapshot>,
+    pub runs: Vec<RunRecord>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct JobSnapshot {
+    pub name: JobName,
+    pub check_interval: Duration,
+    pub lock_ttl: Duration,
+    pub state: Vec<u8>,
+    pub state_version: u32,
+    pub schedule: String,
+    pub enabled: bool,
+    pub last_run: DateTime<Utc>,
+    #[serde(default)]
+    pub metadata: JobMetadata,
+    #[serde(default)]
+    pub archived: bool,
+}
+
+impl Snapshot {
+    /// Write the run history as CSV with a header row, one row per run, for offline analysis
+    /// of e.g. run durations. Logs, events and results are left out.
+    pub fn write_runs_csv<W: Write>(&self, mut out: W) -> io::Result<()> {
+        writeln!(
+            out,
+            "run_id,name,instance,started,finished,duration_ms,outcome,reason,dry_run,as_of,trace_parent"
+        )?;
+        for run in &self.runs {
+            let reason = match &run.outcome {
+                RunOutcome::Failed(msg) | RunOutcome::Interrupted(msg) => msg.clone(),
+                RunOutcome::Skipped(reason) => reason.to_string(),
+                RunOutcome::Succeeded | RunOutcome::Aborted => String::new(),
+            };
+            write_csv_row(
+                &mut out,
+                &[
+                    run.run_id.0.clone(),
+                    run.name.0.clone(),
+                    run.instance.clone(),
+                    run.started.to_rfc3339(),
+                    run.finished.to_rfc3339(),
+                    (run.finished - run.started).num_milliseconds().to_string(),
+                    run.outcome.as_str().to_owned(),
+                    reason,
+                    run.dry_run.to_string(),
+                    run.as_of
+                        .map(|as_of| as_of.to_rfc3339())
+                        .unwrap_or_default(),
+                    run.trace_parent.clone().unwrap_or_default(),
+                ],
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Write the configs of the jobs as CSV with a header row, one row per job. Labels are
+    /// joined as `key=value` pairs separated by `;`.
+    pub fn write_jobs_csv<W: Write>(&self, mut out: W) -> io::Result<()> {
+        writeln!(
+            out,
+            "name,schedule,enabled,check_interval_ms,lock_ttl_ms,last_run,state_version,state_size,description,owner_team,labels"
+        )?;
+        for job in &self.jobs {
+            let labels: Vec<String> = job
+                .metadata
+                .labels
+                .iter()
+                .map(|(key, value)| format!("{}={}", key, value))
+                .collect();
+            write_csv_row(
+                &mut out,
+                &[
+                    job.name.0.clone(),
+                    job.schedule.clone(),
+                    job.enabled.to_string(),
+                    job.check_interval.as_millis().to_string(),
+                    job.lock_ttl.as_millis().to_string(),
+                    job.last_run.to_rfc3339(),
+                    job.state_version.to_string(),
+                    job.state.len().to_string(),
+                    job.metadata.description.clone().unwrap_or_default(),
+                    job.metadata.owner_team.clone().unwrap_or_default(),
+                    labels.join(";"),
+                ],
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "parquet")]
+impl Snapshot {
+    /// Write the run history as Parquet with the columns of [`Snapshot::write_runs_csv`], the
+    /// times as UTC timestamps in milliseconds and the durations as milliseconds.
+    pub fn write_runs_parquet<W: Write + Send>(&self, out: W) -> io::Result<()> {
+        use arrow_array::{BooleanArray, Int64Array, StringArray, TimestampMillisecondArray};
+        let runs = &self.runs;
+        let reasons: Vec<Option<String>> = runs
+            .iter()
+            .map(|run| match &run.outcome {
+                RunOutcome::Failed(msg) | RunOutcome::Interrupted(msg) => Some(msg.clone()),
+                RunOutcome::Skipped(reason) => Some(reason.to_string()),
+                RunOutcome::Succeeded | RunOutcome::Aborted => None,
+            })
+            .collect();
+        let times = |time: fn(&RunRecord) -> Option<DateTime<Utc>>| {
+            TimestampMillisecondArray::from(
+                runs.iter()
+                    .map(|run| time(run).map(|time| time.timestamp_millis()))
+                    .collect::<Vec<_>>(),
+            )
+            .with_timezone("UTC")
+        };
+        let strings = |field: fn(&RunRecord) -> Option<&str>| {
+            StringArray::from(runs.iter().map(field).collect::<Vec<_>>())
+        };
+        write_parquet(
+            out,
+            vec![
+                (
+                    "run_id",
+                    Arc::new(strings(|run| Some(run.run_id.0.as_str()))),
+                    false,
+                ),
+                (
+                    "name",
+                    Arc::new(strings(|run| Some(run.name.as_str()))),
+                    false,
+                ),
+                (
+                    "instance",
+                    Arc::new(strings(|run| Some(run.instance.as_str()))),
+                    false,
+                ),
+                ("started", Arc::new(times(|run| Some(run.started))), false),
+                ("finished", Arc::new(times(|run| Some(run.finished))), false),
+                (
+                    "duration_ms",
+                    Arc::new(Int64Array::from_iter_values(
+                        runs.iter()
+                            .map(|run| (run.finished - run.started).num_milliseconds()),
+                    )),
+                    false,
+                ),
+                (
+                    "outcome",
+                    Arc::new(strings(|run| Some(run.outcome.as_str()))),
+                    false,
+                ),
+                ("reason", Arc::new(StringArray::from(reasons)), true),
+                (
+                    "dry_run",
+                    Arc::new(BooleanArray::from(
+                        runs.iter().map(|run| run.dry_run).collect::<Vec<_>>(),
+                    )),
+                    false,
+                ),
+                ("as_of", Arc::new(times(|run| run.as_of)), true),
+                (
+                    "trace_parent",
+                    Arc::new(strings(|run| run.trace_parent.as_deref())),
+                    true,
+                ),
+            ],
+        )
+    }
+
+    /// Write the configs of the jobs as Parquet with the columns of
+    /// [`Snapshot::write_jobs_csv`], the last run as UTC timestamp in milliseconds.
+    pub fn write_jobs_parquet<W: Write + Send>(&self, out: W) -> io::Result<()> {
+        use arrow_array::{
+            BooleanArray, Int64Array, StringArray, TimestampMillisecondArray, UInt32Array,
+            UInt64Array,
+        };
+        let jobs = &self.jobs;
+        let strings = |field: fn(&JobSnapshot) -> Option<&str>| {
+            StringArray::from(jobs.iter().map(field).collect::<Vec<_>>())
+        };
+        let millis = |field: fn(&JobSnapshot) -> Duration| {
+            Int64Array::from_iter_values(jobs.iter().map(|job| field(job).as_millis() as i64))
+        };
+        let labels: Vec<String> = jobs
+            .iter()
+            .map(|job| {
+                let labels: Vec<String> = job
+                    .metadata
+                    .labels
+                    .iter()
+                    .map(|(key, value)| format!("{}={}", key, value))
+                    .collect();
+                labels.join(";")
+            })
+            .collect();
+        write_parquet(
+            out,
+            vec![
+                (
+                    "name",
+                    Arc::new(strings(|job| Some(job.name.as_str()))),
+                    false,
+                ),
+                (
+                    "schedule",
+                    Arc::new(strings(|job| Some(job.schedule.as_str()))),
+                    false,
+                ),
+                (
+                    "enabled",
+                    Arc::new(BooleanArray::from(
+                        jobs.iter().map(|job| job.enabled).collect::<Vec<_>>(),
+                    )),
+                    false,
+                ),
+                (
+                    "check_interval_ms",
+                    Arc::new(millis(|job| job.check_interval)),
+                    false,
+                ),
+                ("lock_ttl_ms", Arc::new(millis(|job| job.lock_ttl)), false),
+                (
+                    "last_run",
+                    Arc::new(
+                        TimestampMillisecondArray::from_iter_values(
+                            jobs.iter().map(|job| job.last_run.timestamp_millis()),
+                        )
+                        .with_timezone("UTC"),
+                    ),
+                    false,
+                ),
+                (
+                    "state_version",
+                    Arc::new(UInt32Array::from_iter_values(
+                        jobs.iter().map(|job| job.state_version),
+                    )),
+                    false,
+                ),
+                (
+                    "state_size",
+                    Arc::new(UInt64Array::from_iter_values(
+                        jobs.iter().map(|job| job.state.len() as u64),
+                    )),
+                    false,
+                ),
+                (
+                    "description",
+                    Arc::new(strings(|job| job.metadata.description.as_deref())),
+                    true,
+                ),
+                (
+                    "owner_team",
+                    Arc::new(strings(|job| job.metadata.owner_team.as_deref())),
+                    true,
+                ),
+                ("labels", Arc::new(StringArray::from(labels)), false),
+            ],
+        )
+    }
+}
+
+// Write the columns, given by name, values and whether they have nulls, as a single row group.
+#[cfg(feature = "parquet")]
+fn write_parquet<W: Write + Send>(
+    out: W,
+    columns: Vec<(&str, arrow_array::ArrayRef, bool)>,
+) -> io::Result<()> {
+    use arrow_array::{Array, RecordBatch};
+    use arrow_schema::{Field, Schema};
+    use parquet::arrow::ArrowWriter;
+    let fields: Vec<Field> = columns
+        .iter()
+        .map(|(name, values, nullable)| Field::new(*name, values.data_type().clone(), *nullable))
+        .collect();
+    let values = columns.into_iter().map(|(_, values, _)| values).collect();
+    let batch =
+        RecordBatch::try_new(Arc::new(Schema::new(fields)), values).map_err(io::Error::other)?;
+    let mut writer = ArrowWriter::try_new(out, batch.schema(), None).map_err(io::Error::other)?;
+    writer.write(&batch).map_err(io::Error::other)?;
+    writer.close().map_err(io::Error::other)?;
+    Ok(())
+}
+
+// Quote the fields that need it as of RFC 4180.
+fn write_csv_row<W: Write>(out: &mut W, fields: &[String]) -> io::Result<()> {
+    let row: Vec<String> = fields
+        .iter()
+        .map(|field| {
+            if field.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field.clone()
+            }
+        })
+        .collect();
+    writeln!(out, "{}", row.join(","))
+}
+
+impl From<JobData> for JobSnapshot {
+    fn from(value: JobData) -> Self {
+        Self {
+            name: value.name,
+            check_interval: value.check_interval,
+            lock_ttl: value.lock_ttl,
+            state: value.state,
+            state_version: value.state_version,
+            schedule: value.schedule.into(),
+            enabled: value.enabled,
+            last_run: value.last_run,
+            metadata: value.metadata,
+            archived: value.archived,
+        }
+    }
+}
+
+impl TryFrom<JobSnapshot> for JobData {
+    type Error = Error;
+
+    fn try_from(value: JobSnapshot) -> std::result::Result<Self, Self::Error> {
+        let schedule = Schedule::from_str(value.schedule.as_str())?;
+        Ok(Self {
+            name: value.name,
+            check_interval: value.check_interval,
+            lock_ttl: value.lock_ttl,
+            state: value.state,
+            state_version: value.state_version,
+            next_run: schedule.next_after(&value.last_run),
+            schedule,
+            enabled: value.enabled,
+            last_run: value.last_run,
+            owner: String::default(),
+            expires: DateTime::default(),
+            metadata: value.metadata,
+            approval: None,
+            requested: None,
+            last_heartbeat: None,
+            archived: value.archived,
+            pin: None,
+        })
+    }
+}
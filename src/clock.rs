@@ -0,0 +1,140 @@
+use crate::error::Result;
+use crate::repos::Repo;
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use log::{trace, warn};
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::time::sleep;
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// What the executors do while the clock of this instance is off from the clock of the store
+/// by more than the maximum, see [`crate::JobManager::with_max_clock_skew`].
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum OnClockSkew {
+    /// Log a warning and keep running jobs.
+    #[default]
+    Warn,
+    /// Skip due runs as [`crate::SkipReason::ClockSkew`] until the clocks agree again.
+    Refuse,
+}
+
+// Where the executors take the current time from, the system clock unless a test replaces it.
+#[derive(Clone)]
+pub(crate) struct TimeSource(Arc<dyn Fn() -> DateTime<Utc> + Send + Sync>);
+
+impl TimeSource {
+    pub(crate) fn system() -> Self {
+        TimeSource(Arc::new(Utc::now))
+    }
+    // Time starting at `start` and advancing with the tokio clock, so that it jumps ahead
+    // together with the paused tokio time of a test.
+    #[cfg(feature = "test-util")]
+    pub(crate) fn tokio(start: DateTime<Utc>) -> Self {
+        let started = tokio::time::Instant::now();
+        TimeSource(Arc::new(move || {
+            start + ChronoDuration::from_std(started.elapsed()).unwrap_or_default()
+        }))
+    }
+    pub(crate) fn now(&self) -> DateTime<Utc> {
+        (self.0)()
+    }
+}
+
+// How far the clock of this instance is ahead of the clock of the store, `None` if the store
+// has no clock of its own.
+pub(crate) async fn measure_skew<R: Repo>(repo: &mut R) -> Result<Option<ChronoDuration>> {
+    let before = Utc::now();
+    let Some(store_time) = repo.server_time().await? else {
+        return Ok(None);
+    };
+    // The store read its clock somewhere within the round trip, most likely halfway.
+    let local_time: DateTime<Utc> = before + (Utc::now() - before) / 2;
+    Ok(Some(local_time - store_time))
+}
+
+// Latest measured offset of the clock of this instance from the clock of the store.
+pub(crate) struct ClockSkew {
+    skew_ms: AtomicI64,
+    measured: AtomicBool,
+    max: Duration,
+    on_skew: OnClockSkew,
+}
+
+impl ClockSkew {
+    pub(crate) fn new(max: Duration, on_skew: OnClockSkew) -> Self {
+        ClockSkew {
+            skew_ms: AtomicI64::new(0),
+            measured: AtomicBool::new(false),
+            max,
+            on_skew,
+        }
+    }
+
+    // How far this instance's clock is ahead of the store's, `None` before the first
+    // measurement or when the store has no clock of its own.
+    pub(crate) fn skew(&self) -> Option<ChronoDuration> {
+        self.measured
+            .load(Ordering::Relaxed)
+            .then(|| ChronoDuration::milliseconds(self.skew_ms.load(Ordering::Relaxed)))
+    }
+
+    fn exceeded(&self) -> bool {
+        self.skew()
+            .is_some_and(|skew| skew.abs().to_std().unwrap_or_default() > self.max)
+    }
+
+    pub(crate) fn max(&self) -> Duration {
+        self.max
+    }
+
+    // Whether due runs are to be skipped because of the skew.
+    pub(crate) fn refuses(&self) -> bool {
+        self.on_skew == OnClockSkew::Refuse && self.exceeded()
+    }
+
+    async fn measure<R: Repo>(&self, repo: &mut R) {
+        let skew = match measure_skew(repo).await {
+            Ok(Some(skew)) => skew,
+            Ok(None) => return,
+            Err(e) => {
+                warn!("reading the time of the store failed: {}", e);
+                return;
+            }
+        };
+        self.skew_ms
+            .store(skew.num_milliseconds(), Ordering::Relaxed);
+        self.measured.store(true, Ordering::Relaxed);
+        if self.exceeded() {
+            warn!(
+                "clock is {}ms off from the clock of the store, more than the maximum of {}ms",
+                skew.num_milliseconds(),
+                self.max.as_millis()
+            );
+        } else {
+            trace!("clock is {}ms off from the store", skew.num_milliseconds());
+        }
+    }
+
+    // Measure the skew right away and then periodically until the manager drains or is
+    // dropped.
+    pub(crate) async fn monitor<R: Repo>(&self, mut repo: R, mut draining: watch::Receiver<bool>) {
+        match repo.capabilities().await {
+            Ok(capabilities) if !capabilities.clock => {
+                warn!("the store has no clock of its own, the clock skew is not monitored");
+                return;
+            }
+            Ok(_) => {}
+            Err(e) => warn!("reading the capabilities of the store failed: {}", e),
+        }
+        loop {
+            self.measure(&mut repo).await;
+            tokio::select! {
+                _ = sleep(CHECK_INTERVAL) => {}
+                _ = draining.wait_for(|draining| *draining) => return,
+            }
+        }
+    }
+}
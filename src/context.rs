@@ -0,0 +1,193 @@
+use crate::clock::TimeSource;
+use crate::{JobError, JobName, RunEvent, RunId};
+use chrono::{DateTime, Utc};
+use log::Level;
+use serde::Serialize;
+use std::fmt::Display;
+use std::sync::{Arc, Mutex};
+use tokio::sync::watch;
+
+/// Default cap of the log output captured per run.
+pub const DEFAULT_MAX_LOG_SIZE: usize = 64 * 1024;
+
+/// Context of a single run, handed to [`crate::Job::call`].
+pub struct JobContext {
+    name: JobName,
+    run_id: RunId,
+    dry_run: bool,
+    as_of: DateTime<Utc>,
+    // Time the run was scheduled for, or requested as of.
+    fire_time: DateTime<Utc>,
+    trace_parent: Option<String>,
+    logger: RunLogger,
+    events: Mutex<Vec<RunEvent>>,
+    heartbeat: Mutex<Option<DateTime<Utc>>>,
+    time: TimeSource,
+    canceled: watch::Receiver<bool>,
+}
+
+impl JobContext {
+    pub(crate) fn new(
+        name: JobName,
+        run_id: RunId,
+        dry_run: bool,
+        max_log_size: usize,
+        canceled: watch::Receiver<bool>,
+    ) -> Self {
+        JobContext {
+            logger: RunLogger::new(name.clone(), max_log_size),
+            name,
+            run_id,
+            dry_run,
+            as_of: Utc::now(),
+            fire_time: Utc::now(),
+            trace_parent: None,
+            events: Mutex::default(),
+            heartbeat: Mutex::default(),
+            time: TimeSource::system(),
+            canceled,
+        }
+    }
+    pub(crate) fn with_as_of(mut self, as_of: DateTime<Utc>) -> Self {
+        self.as_of = as_of;
+        self
+    }
+    pub(crate) fn with_fire_time(mut self, fire_time: DateTime<Utc>) -> Self {
+        self.fire_time = fire_time;
+        self
+    }
+    pub(crate) fn with_time(mut self, time: TimeSource) -> Self {
+        self.time = time;
+        self
+    }
+    pub(crate) fn with_trace_parent(mut self, trace_parent: Option<String>) -> Self {
+        self.trace_parent = trace_parent;
+        self
+    }
+    pub fn name(&self) -> &JobName {
+        &self.name
+    }
+    pub fn run_id(&self) -> &RunId {
+        &self.run_id
+    }
+    /// Whether this is a dry run, see [`crate::JobManager::dry_run`]. The state the job returns
+    /// is discarded, but the job has to avoid other side effects itself.
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+    /// Time the run computes for, to be used instead of the wall clock for anything that
+    /// depends on the date, e.g. which day a daily report covers. It is the time requested for
+    /// a backfill, see [`crate::JobManager::backfill`], and the start of the run otherwise.
+    pub fn as_of(&self) -> DateTime<Utc> {
+        self.as_of
+    }
+    /// Key identifying the run by the job's name and the time the run was scheduled for, or
+    /// the as-of time of a backfill, e.g. `report@1767225600`. A run repeated because the state
+    /// of the first attempt was not saved gets the same key, so passing it to external APIs
+    /// that deduplicate requests, e.g. for payments or emails, keeps the effects of the run
+    /// from happening twice. Dry runs get a key of their own.
+    pub fn idempotency_key(&self) -> String {
+        format!("{}@{}", self.name.as_str(), self.fire_time.timestamp())
+    }
+    /// W3C traceparent of the request this run was made for, see
+    /// [`crate::JobManager::dry_run_traced`], to continue the trace of the request in the run,
+    /// possibly on another instance than the one the request was made on.
+    pub fn trace_parent(&self) -> Option<&str> {
+        self.trace_parent.as_deref()
+    }
+    /// Whether the run has been canceled, e.g. because the job is stopped. The job should
+    /// return soon, before the deadline set with [`crate::JobConfig::with_cancel_deadline`].
+    pub fn is_canceled(&self) -> bool {
+        *self.canceled.borrow()
+    }
+    /// Completes once the run has been canceled, to be selected on in long-running jobs.
+    pub async fn canceled(&self) {
+        let mut canceled = self.canceled.clone();
+        // The sender is dropped only after the run, which cannot be canceled then anymore.
+        if canceled.wait_for(|canceled| *canceled).await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    }
+    /// Logger whose output is stored with the run record in the run history.
+    pub fn logger(&self) -> &RunLogger {
+        &self.logger
+    }
+    /// Append an event to the run's outbox. The events are written together with the state
+    /// the run returns and show up in the run record, so they are kept if and only if the run
+    /// succeeds.
+    pub fn emit(&self, kind: impl Into<String>, payload: &impl Serialize) -> Result<(), JobError> {
+        let payload = serde_json::to_value(payload).map_err(JobError::any)?;
+        self.events
+            .lock()
+            .expect("run events lock poisoned")
+            .push(RunEvent {
+                kind: kind.into(),
+                payload,
+            });
+        Ok(())
+    }
+    /// Signal that the run is making progress, e.g. after each processed batch. The latest
+    /// heartbeat is written to the repo every half lock TTL and shown in
+    /// [`crate::JobStatus::last_heartbeat`], which tells a run that stopped making progress
+    /// from a slow one while both keep their lock refreshed.
+    pub fn heartbeat(&self) {
+        *self.heartbeat.lock().expect("heartbeat lock poisoned") = Some(self.time.now());
+    }
+    pub(crate) fn last_heartbeat(&self) -> Option<DateTime<Utc>> {
+        *self.heartbeat.lock().expect("heartbeat lock poisoned")
+    }
+    pub(crate) fn take_events(&self) -> Vec<RunEvent> {
+        std::mem::take(&mut *self.events.lock().expect("run events lock poisoned"))
+    }
+}
+
+/// Logs to the `log` facade and captures the output of the run in a size-capped buffer.
+/// Lines that no longer fit into the buffer are only passed on to the `log` facade.
+#[derive(Clone)]
+pub struct RunLogger {
+    name: JobName,
+    buffer: Arc<Mutex<LogBuffer>>,
+}
+
+struct LogBuffer {
+    output: String,
+    max_size: usize,
+    truncated: bool,
+}
+
+impl RunLogger {
+    fn new(name: JobName, max_size: usize) -> Self {
+        RunLogger {
+            name,
+            buffer: Arc::new(Mutex::new(LogBuffer {
+                output: String::new(),
+                max_size,
+                truncated: false,
+            })),
+        }
+    }
+    pub fn info(&self, msg: impl Display) {
+        self.log(Level::Info, msg)
+    }
+    pub fn warn(&self, msg: impl Display) {
+        self.log(Level::Warn, msg)
+    }
+    pub fn error(&self, msg: impl Display) {
+        self.log(Level::Error, msg)
+    }
+    pub fn log(&self, level: Level, msg: impl Display) {
+        log::log!(level, "job {}: {}", self.name.as_str(), msg);
+        let line = format!("{} {} {}\n", Utc::now().to_rfc3339(), level, msg);
+        let mut buffer = self.buffer.lock().expect("run log buffer lock poisoned");
+        if buffer.output.len() + line.len() <= buffer.max_size {
+            buffer.output.push_str(&line);
+        } else {
+            buffer.truncated = true;
+        }
+    }
+    /// Captured output so far, and whether lines had to be dropped.
+    pub(crate) fn output(&self) -> (String, bool) {
+        let buffer = self.buffer.lock().expect("run log buffer lock poisoned");
+        (buffer.output.clone(), buffer.truncated)
+    }
+}
@@ -1,26 +1,81 @@
 use crate::schedule::InvalidCronExpression;
-use crate::JobName;
+use crate::{JobName, RunId};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
-pub(crate) enum Error {
+pub enum Error {
     // #[error("data store disconnected")]
     // Disconnect(#[from] io::Error),
     // #[error("the data for key `{0}` is not available")]
     // Redaction(String),
     #[error(transparent)]
     InvalidCronExpression(#[from] InvalidCronExpression),
-    // #[error("Job is missing: {0:?}")]
-    // JobNotFound(JobName),
+    #[error("Job is missing: {0:?}")]
+    NotFound(JobName),
+    #[error("Job state is corrupt: {0}")]
+    CorruptState(String),
+    #[error("Serialization error: {0}")]
+    Serialization(String),
     #[error("Repository error: {0}")]
     Repo(String),
     #[error("Loack refresh failed: {0}")]
     LockRefreshFailed(String),
     #[error("canceling job {0:?} failed")]
     CancelFailed(JobName),
+    #[error("purging job {0:?} was not confirmed")]
+    PurgeNotConfirmed(JobName),
+    #[error("job {0:?} is running on this instance")]
+    JobRunning(JobName),
+    #[error("job {0:?} is locked by a running instance")]
+    JobLocked(JobName),
+    #[error("job {0:?} exists already")]
+    JobExists(JobName),
+    #[error("run {1:?} of job {0:?} is not pending approval")]
+    NotPendingApproval(JobName, RunId),
+    #[error("no job factory registered for kind {0:?}")]
+    UnknownJobKind(String),
+    #[error("constructing job {0:?} failed: {1}")]
+    JobFactoryFailed(JobName, String),
+    #[error("invalid traceparent {0:?}")]
+    InvalidTraceParent(String),
+    #[error("state of job {0:?} is {1} bytes, more than the maximum of {2} bytes")]
+    StateTooLarge(JobName, usize, usize),
+    #[error("{} invalid job(s): {}", .0.len(), describe_invalid_jobs(.0))]
+    InvalidJobs(Vec<(JobName, String)>),
+}
+
+fn describe_invalid_jobs(invalid: &[(JobName, String)]) -> String {
+    invalid
+        .iter()
+        .map(|(name, problem)| format!("{:?} {}", name.as_str(), problem))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Classification of an [`Error`], e.g. to tell data corruption from absence in events.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ErrorKind {
+    InvalidCronExpression,
+    NotFound,
+    CorruptState,
+    Serialization,
+    Repo,
+    LockRefreshFailed,
+    Other,
+}
 
-    #[error("TODO")]
-    TODO,
+impl Error {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::InvalidCronExpression(_) => ErrorKind::InvalidCronExpression,
+            Error::NotFound(_) => ErrorKind::NotFound,
+            Error::CorruptState(_) => ErrorKind::CorruptState,
+            Error::Serialization(_) => ErrorKind::Serialization,
+            Error::Repo(_) => ErrorKind::Repo,
+            Error::LockRefreshFailed(_) => ErrorKind::LockRefreshFailed,
+            _ => ErrorKind::Other,
+        }
+    }
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
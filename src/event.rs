@@ -0,0 +1,122 @@
+use crate::{ErrorKind, JobName, RunId, SkipReason};
+use futures::Stream;
+use log::warn;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+
+/// Lifecycle events emitted by the executors of a JobManager.
+#[derive(Clone, Debug, PartialEq)]
+pub enum JobEvent {
+    /// The executor of the job has been spawned.
+    Started { name: JobName },
+    /// This instance acquired the lock and is about to call the job.
+    RunStarted { name: JobName, run_id: RunId },
+    /// The job returned successfully and its state has been saved.
+    RunSucceeded {
+        name: JobName,
+        run_id: RunId,
+        duration: Duration,
+    },
+    /// The job returned an error.
+    RunFailed {
+        name: JobName,
+        run_id: RunId,
+        error: String,
+    },
+    /// This instance did not run the job although it was due. Emitted once per due time.
+    RunSkipped { name: JobName, reason: SkipReason },
+    /// The run lasted at least half the lock TTL, the interval at which the lock is refreshed,
+    /// so a single slow or failed refresh may let another instance take over the job. Raise
+    /// the job's lock TTL well above its typical run duration.
+    LockTtlTight {
+        name: JobName,
+        lock_ttl: Duration,
+        run_duration: Duration,
+    },
+    /// The run made no progress, i.e. neither started nor sent a heartbeat, for longer than
+    /// the job's stuck factor times the 95th percentile of its recent run durations, see
+    /// [`crate::JobConfig::with_stuck_run_detection`]. Emitted once per run.
+    RunStuck {
+        name: JobName,
+        run_id: RunId,
+        stalled: Duration,
+        p95: Duration,
+    },
+    /// The job is due but requires approval before the run with the given id starts.
+    ApprovalRequested { name: JobName, run_id: RunId },
+    /// The run was canceled and did not return before the job's cancel deadline, so it was
+    /// aborted and its lock released.
+    RunAborted { name: JobName, run_id: RunId },
+    /// Refreshing the lock failed while the job was running.
+    LockFailed { name: JobName, error: String },
+    /// Saving the job state after a successful run failed.
+    SaveFailed { name: JobName, error: String },
+    /// Reading the job's data from the repo failed, e.g. because it is corrupt.
+    RepoFailed {
+        name: JobName,
+        kind: ErrorKind,
+        error: String,
+    },
+    /// The job's document was deleted from the repo by someone else.
+    DeletedExternally { name: JobName },
+    /// The executor task of the job panicked or was aborted. It is restarted unless the
+    /// manager's restart limit is used up, in which case [`JobEvent::Stopped`] follows.
+    ExecutorPanicked { name: JobName, error: String },
+    /// The executor of the job exited.
+    Stopped { name: JobName },
+}
+
+impl JobEvent {
+    pub fn name(&self) -> &JobName {
+        match self {
+            JobEvent::Started { name }
+            | JobEvent::RunStarted { name, .. }
+            | JobEvent::RunSucceeded { name, .. }
+            | JobEvent::RunFailed { name, .. }
+            | JobEvent::RunSkipped { name, .. }
+            | JobEvent::LockTtlTight { name, .. }
+            | JobEvent::RunStuck { name, .. }
+            | JobEvent::ApprovalRequested { name, .. }
+            | JobEvent::RunAborted { name, .. }
+            | JobEvent::LockFailed { name, .. }
+            | JobEvent::SaveFailed { name, .. }
+            | JobEvent::RepoFailed { name, .. }
+            | JobEvent::DeletedExternally { name }
+            | JobEvent::ExecutorPanicked { name, .. }
+            | JobEvent::Stopped { name } => name,
+        }
+    }
+}
+
+/// Sending side shared by the manager and all executors. Sending never blocks the
+/// executors; when there are no subscribers events are simply dropped.
+#[derive(Clone)]
+pub(crate) struct Events(broadcast::Sender<JobEvent>);
+
+impl Events {
+    pub(crate) fn new(capacity: usize) -> Self {
+        let (tx, _) = broadcast::channel(capacity);
+        Events(tx)
+    }
+
+    pub(crate) fn emit(&self, event: JobEvent) {
+        let _ = self.0.send(event);
+    }
+
+    /// Subscribe to all events emitted from now on. A subscriber that falls more than the
+    /// channel capacity behind skips the oldest events instead of slowing down the executors.
+    pub(crate) fn stream(&self) -> impl Stream<Item = JobEvent> + Send + 'static {
+        futures_util::stream::unfold(self.0.subscribe(), |mut rx| async move {
+            loop {
+                match rx.recv().await {
+                    Ok(event) => return Some((event, rx)),
+                    Err(RecvError::Lagged(missed)) => {
+                        warn!("event subscriber lagging behind, skipped {} events", missed)
+                    }
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        })
+    }
+}
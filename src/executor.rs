@@ -1,31 +1,384 @@
+use crate::backoff::{Backoff, Jitter};
+use crate::clock::{ClockSkew, TimeSource};
 use crate::error::{Error, Result};
-use crate::job::JobData;
+use crate::event::Events;
+use crate::history::p95_duration;
+use crate::hooks::Hooks;
+use crate::job::{lock_ttl_tight, Approval, JobData, Outbox, RunRequest};
+use crate::logging::{job_log, log_transition, with_job_log_level, LogFormat};
+use crate::metrics::Transitions;
+use crate::quota::{Quota, QuotaWait};
 use crate::repos::{LockStatus, Repo};
-use crate::{Job, JobConfig, JobName};
-use chrono::Utc;
-use log::{error, info, trace};
+use crate::schedule::Schedule;
+use crate::{
+    GateKeeper, InstanceId, Job, JobConfig, JobContext, JobError, JobEvent, JobName, OnDeleted,
+    OnOversizedState, OnStuckRun, RunId, RunOutcome, RunRecord, SkipReason,
+};
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use log::{Level, LevelFilter};
+use std::collections::HashMap;
+use std::convert::Infallible;
 use std::fmt::{Debug, Formatter};
+use std::future::Future;
+use std::sync::Arc;
 use tokio::sync::oneshot::Receiver;
-use tokio::time::{sleep, Duration};
+use tokio::sync::{watch, OwnedSemaphorePermit};
+use tokio::time::{sleep, Duration, Instant};
 
-struct Shared<R> {
-    instance: String,
-    name: JobName,
+const SAVE_ATTEMPTS: u32 = 5;
+const SAVE_RETRY_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_REPO_BACKOFF: Duration = Duration::from_secs(300);
+const MAX_LOCK_BACKOFF: Duration = Duration::from_secs(300);
+// Recent runs whose durations tell how long a run is expected to take, and how many of them
+// have to be successful runs before runs are checked for being stuck.
+const STUCK_HISTORY: usize = 20;
+const MIN_STUCK_SAMPLES: usize = 5;
+// Shortest time without progress for which a run is reported as stuck.
+const MIN_STUCK_LIMIT: Duration = Duration::from_secs(1);
+// Check intervals a pin lasts without being renewed, see `JobConfig::with_pinning`.
+const PIN_CHECKS: u32 = 3;
+
+/// Settings the manager hands to every executor it spawns.
+#[derive(Clone)]
+pub(crate) struct Settings {
+    pub instance: String,
+    pub events: Events,
+    pub log_format: LogFormat,
+    // Log level of the jobs without one of their own.
+    pub log_level: LevelFilter,
+    pub zone: Option<String>,
+    // Capacity of this instance relative to the others, at least 1.
+    pub weight: u32,
+    // Limits of concurrently running jobs per group on this instance.
+    pub quotas: HashMap<String, Arc<Quota>>,
+    // How often a panicked or aborted executor is respawned.
+    pub restart_limit: u32,
+    // Turns true once the manager drains, see `JobManager::drain`.
+    pub draining: watch::Receiver<bool>,
+    pub hooks: Hooks,
+    pub gate_keeper: Option<Arc<dyn GateKeeper>>,
+    // Skew of the clock of this instance from the store's, if monitored.
+    pub clock: Option<Arc<ClockSkew>>,
+    pub time: TimeSource,
+    // State transitions of the executors of this instance.
+    pub transitions: Arc<Transitions>,
+}
+
+impl Settings {
+    pub(crate) fn new(
+        instance: String,
+        events: Events,
+        restart_limit: u32,
+        draining: watch::Receiver<bool>,
+    ) -> Self {
+        Settings {
+            instance,
+            events,
+            log_format: LogFormat::default(),
+            log_level: LevelFilter::Trace,
+            zone: None,
+            weight: 1,
+            quotas: HashMap::new(),
+            restart_limit,
+            draining,
+            hooks: Hooks::default(),
+            gate_keeper: None,
+            clock: None,
+            time: TimeSource::system(),
+            transitions: Default::default(),
+        }
+    }
+}
+
+struct Shared<R: Repo> {
+    settings: Settings,
+    config: JobConfig,
     repo: R,
+    // Handle of the repo for writing heartbeats while the run holds on to `repo`.
+    heartbeats: R,
     cancel: Receiver<()>,
     action: Box<dyn Job + Send>,
+    // Quota slot of the job's group, held from lock acquisition until the run is over.
+    permit: Option<OwnedSemaphorePermit>,
+    // Registration as waiting for a quota slot while the job is due but not admitted.
+    quota_wait: Option<QuotaWait>,
+    // Backs off from an unavailable repo over consecutive failures reading the job data.
+    repo_backoff: Backoff,
+    // Spreads the attempts of instances contending for a lock held by another instance.
+    lock_backoff: Backoff,
+    // Why this instance has not run the current due time yet, reported once the due time is
+    // over without a run here.
+    skipped: Option<SkipReason>,
+    // Due time last reported as skipped for a reason that keeps the job due, so that it is
+    // reported only once.
+    reported_due: Option<DateTime<Utc>>,
+    // Request of the run about to start if it was requested outside of the schedule.
+    requested: Option<RunRequest>,
+    // Lock kept after a run for the next time the schedule fires, see
+    // `JobConfig::with_fast_path`.
+    held: Option<R::Lock>,
+}
+
+impl<R: Repo> Shared<R> {
+    // Take a slot of the job's group quota. Jobs without a group or without a quota for
+    // their group are always admitted. A job that is not admitted stays registered as waiting
+    // until it is admitted or no longer due.
+    fn admit(&mut self) -> Option<Option<OwnedSemaphorePermit>> {
+        let Some(quota) = self
+            .config
+            .group
+            .as_ref()
+            .and_then(|group| self.settings.quotas.get(group))
+        else {
+            return Some(None);
+        };
+        match quota.try_admit(self.config.priority) {
+            Some(permit) => {
+                self.quota_wait = None;
+                Some(Some(permit))
+            }
+            None => {
+                if self.quota_wait.is_none() {
+                    self.quota_wait = Some(quota.wait(self.config.priority));
+                }
+                None
+            }
+        }
+    }
+
+    // Whether the group quota of the job has been saturated for so long that due runs of the
+    // job are shed, see `JobManager::with_load_shedding`.
+    fn shed(&self) -> bool {
+        self.config
+            .group
+            .as_ref()
+            .and_then(|group| self.settings.quotas.get(group))
+            .is_some_and(|quota| quota.sheds(self.config.priority))
+    }
+
+    // Whether the job is due as seen by this executor, allowing for the job's due tolerance.
+    fn due(&self, jdata: &JobData, now: DateTime<Utc>) -> bool {
+        jdata.due_within(now, self.config.due_tolerance)
+    }
+
+    // Delay until the job is checked again, shortened to the next time the schedule fires for
+    // jobs with the fast path.
+    fn next_check(&self, schedule: &Schedule, check_interval: Duration) -> Duration {
+        if !self.config.fast_path {
+            return check_interval;
+        }
+        let now = self.settings.time.now();
+        schedule
+            .next_after(&now)
+            .and_then(|next| (next - now).to_std().ok())
+            .map_or(check_interval, |until| until.min(check_interval))
+    }
+
+    fn draining(&self) -> bool {
+        *self.settings.draining.borrow()
+    }
+
+    // Whether the job prefers a zone other than the zone of this instance.
+    fn outside_preferred_zone(&self) -> bool {
+        match &self.config.preferred_zone {
+            Some(zone) => self.settings.zone.as_ref() != Some(zone),
+            None => false,
+        }
+    }
+
+    // Pin the job to this instance, or renew the pin, unless another instance holds it.
+    // Returns whether this instance may run the job. A pin stays with an instance that holds
+    // the lock for a run longer than the pin lasts.
+    async fn hold_pin(&mut self, jdata: &JobData) -> bool {
+        let now = self.settings.time.now();
+        let instance = self.settings.instance.as_str();
+        let running = jdata
+            .pin
+            .as_ref()
+            .filter(|pin| jdata.locked_by(now) == Some(pin.owner.as_str()))
+            .map(|pin| pin.owner.as_str());
+        if let Some(owner) = jdata.pinned_to(now).or(running) {
+            if owner != instance {
+                job_log!(Level::Trace, "pinned to instance {}", owner);
+                return false;
+            }
+        }
+        let ttl = ChronoDuration::from_std(jdata.check_interval * PIN_CHECKS).unwrap_or_default();
+        match self
+            .repo
+            .pin(jdata.name.clone(), instance.to_owned(), now + ttl)
+            .await
+        {
+            Ok(held) => held,
+            Err(e) => {
+                job_log!(Level::Warn, "renewing the pin failed: {}", e);
+                jdata.pinned_to(now) == Some(instance)
+            }
+        }
+    }
+
+    // Release the lock kept for the next run, so that a stopping executor leaves no lock
+    // behind even if the runtime shuts down before a dropped lock is released.
+    async fn release_lock(&mut self) {
+        if self.held.take().is_none() {
+            return;
+        }
+        let instance = self.settings.instance.clone();
+        if let Err(e) = self.repo.unlock(self.config.name.clone(), instance).await {
+            job_log!(Level::Warn, "releasing the kept lock failed: {}", e);
+        }
+    }
+
+    // The job data under the kept lock. A kept lock this instance no longer holds counts as
+    // held elsewhere, it is acquired anew at the next attempt, once dropping it released it.
+    async fn kept_lock(&mut self, lock: R::Lock) -> Result<LockStatus<R::Lock>> {
+        let now = self.settings.time.now();
+        match self.repo.get(self.config.name.clone()).await? {
+            Some(jdata) if jdata.locked_by(now) == Some(self.settings.instance.as_str()) => {
+                Ok(LockStatus::Acquired(jdata, lock))
+            }
+            Some(_) => {
+                job_log!(Level::Warn, "kept lock lost");
+                Ok(LockStatus::AlreadyLocked)
+            }
+            None => Err(Error::NotFound(self.config.name.clone())),
+        }
+    }
+
+    // Let another instance take over the pinned job right away.
+    async fn release_pin(&mut self) {
+        let now = self.settings.time.now();
+        let instance = self.settings.instance.clone();
+        if let Err(e) = self.repo.pin(self.config.name.clone(), instance, now).await {
+            job_log!(Level::Warn, "releasing the pin failed: {}", e);
+        }
+    }
+
+    async fn report_skip(&mut self, reason: SkipReason) {
+        job_log!(Level::Info, "due run skipped: {}", reason);
+        self.settings.events.emit(JobEvent::RunSkipped {
+            name: self.config.name.clone(),
+            reason,
+        });
+        if !self.config.record_skips {
+            return;
+        }
+        let now = self.settings.time.now();
+        let record = RunRecord {
+            run_id: RunId::generate(),
+            name: self.config.name.clone(),
+            instance: self.settings.instance.clone(),
+            started: now,
+            finished: now,
+            outcome: RunOutcome::Skipped(reason),
+            log: String::new(),
+            log_truncated: false,
+            dry_run: false,
+            as_of: None,
+            trace_parent: None,
+            events: Vec::new(),
+            result: None,
+        };
+        if let Err(e) = self.repo.record_run(record).await {
+            job_log!(
+                Level::Error,
+                "recording skipped run in history failed: {}",
+                e
+            );
+        }
+    }
+
+    // Report a skip of the job's current due time unless it has been reported already.
+    async fn report_skip_once(&mut self, jdata: &JobData, reason: SkipReason) {
+        let due = jdata.next_run;
+        if self.reported_due != due {
+            self.reported_due = due;
+            self.report_skip(reason).await;
+        }
+    }
 }
 
 enum Executor<R: Repo> {
     Initial(Shared<R>, JobData, Duration),
     Sleeping(Shared<R>, Duration),
     Start(Shared<R>, JobData),
-    CheckDue(Shared<R>, Duration),
+    CheckDue(Shared<R>),
+    Defer(Shared<R>, Duration),
     TryLock(Shared<R>, Duration),
-    Run(Shared<R>, JobData, R::Lock),
+    Run(Shared<R>, JobData, R::Lock, RunId),
     Done,
 }
 
+impl<R: Repo + Clone> Executor<R> {
+    fn new(
+        settings: Settings,
+        config: JobConfig,
+        action: Box<dyn Job + Send>,
+        repo: R,
+        cancel: Receiver<()>,
+        delay: Duration,
+    ) -> Self {
+        let check_interval = config.check_interval;
+        Executor::Initial(
+            Shared {
+                settings,
+                config: config.clone(),
+                heartbeats: repo.clone(),
+                repo,
+                cancel,
+                action,
+                permit: None,
+                quota_wait: None,
+                repo_backoff: Backoff::new(check_interval, MAX_REPO_BACKOFF),
+                lock_backoff: Backoff::new(check_interval, MAX_LOCK_BACKOFF)
+                    .with_jitter(Jitter::Equal),
+                skipped: None,
+                reported_due: None,
+                requested: None,
+                held: None,
+            },
+            JobData::from(config),
+            delay,
+        )
+    }
+}
+
+impl<R: Repo> Executor<R> {
+    fn state(&self) -> &'static str {
+        match self {
+            Executor::Initial(..) => "initial",
+            Executor::Sleeping(..) => "sleeping",
+            Executor::Start(..) => "start",
+            Executor::CheckDue(..) => "check_due",
+            Executor::Defer(..) => "defer",
+            Executor::TryLock(..) => "try_lock",
+            Executor::Run(..) => "run",
+            Executor::Done => "done",
+        }
+    }
+
+    fn run_id(&self) -> Option<&RunId> {
+        match self {
+            Executor::Run(.., run_id) => Some(run_id),
+            _ => None,
+        }
+    }
+
+    // Make the transition out of the current state.
+    async fn step(self) -> Executor<R> {
+        match self {
+            Executor::Initial(shared, jdata, delay) => on_initial(shared, jdata, delay).await,
+            Executor::Start(shared, jdata) => on_start(shared, jdata).await,
+            Executor::Sleeping(shared, delay) => on_sleeping(shared, delay).await,
+            Executor::CheckDue(shared) => on_check_due(shared).await,
+            Executor::Defer(shared, delay) => on_defer(shared, delay).await,
+            Executor::TryLock(shared, delay) => on_try_lock(shared, delay).await,
+            Executor::Run(shared, jdata, lock, run_id) => on_run(shared, jdata, lock, run_id).await,
+            Executor::Done => Executor::Done,
+        }
+    }
+}
+
 impl<R: Repo> Debug for Executor<R> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -34,6 +387,7 @@ impl<R: Repo> Debug for Executor<R> {
                 format!("--------------------------- sleeping {}s", delay.as_secs()).as_str(),
             ),
             Executor::Start(..) => f.write_str("------------------------------------ start"),
+            Executor::Defer(..) => f.write_str("------------------------------------ defer"),
             Executor::TryLock(..) => f.write_str("------------------------------------ trylock"),
             Executor::CheckDue(..) => f.write_str("------------------------------------ CheckDue"),
             Executor::Run(..) => f.write_str("------------------------------------ run"),
@@ -42,36 +396,82 @@ impl<R: Repo> Debug for Executor<R> {
     }
 }
 
+/// Drive the executor of a single job on the current task until `cancel` fires or the job's
+/// data is deleted with [`OnDeleted::Stop`], without a [`crate::JobManager`], e.g. inside a
+/// supervision tree of the application. `instance` owns the job's lock like the instance id of
+/// a manager. Unlike under a manager a panicking job is not restarted, and group quotas,
+/// hooks, events and the other settings of a manager do not apply.
+/// ```rust,ignore
+///     let (stop, cancel) = tokio::sync::oneshot::channel();
+///     let config = JobConfig::new("report", Schedule::from_str("0 0 2 * * *")?);
+///     tokio::spawn(run_single_job(InstanceId::generate(), config, ReportJob, repo, cancel));
+/// ```
+#[allow(private_bounds)]
+pub async fn run_single_job<R: Repo + Clone + Send>(
+    instance: impl Into<InstanceId>,
+    config: JobConfig,
+    job: impl Job + Send + 'static,
+    repo: R,
+    cancel: Receiver<()>,
+) -> Result<()> {
+    // Kept until the executor is done, it never drains.
+    let (_drain, draining) = watch::channel(false);
+    let settings = Settings::new(instance.into().0, Events::new(1), 0, draining);
+    run(
+        settings,
+        config,
+        Box::new(job),
+        repo,
+        cancel,
+        Duration::ZERO,
+    )
+    .await
+}
+
 pub(crate) async fn run<J: Repo + Clone + Send>(
-    instance: String,
+    settings: Settings,
     config: JobConfig,
     action: Box<dyn Job + Send>,
     repo: J,
     cancel: Receiver<()>,
     delay: Duration,
 ) -> Result<()> {
-    let mut executor = Executor::Initial(
-        Shared {
-            instance,
-            name: config.name.clone(),
-            repo,
-            cancel,
-            action,
-        },
-        JobData::from(config),
-        delay,
-    );
+    let level = config.log_level.unwrap_or(settings.log_level);
+    with_job_log_level(level, drive(settings, config, action, repo, cancel, delay)).await
+}
+
+async fn drive<J: Repo + Clone + Send>(
+    settings: Settings,
+    config: JobConfig,
+    action: Box<dyn Job + Send>,
+    repo: J,
+    cancel: Receiver<()>,
+    delay: Duration,
+) -> Result<()> {
+    let name = config.name.clone();
+    let events = settings.events.clone();
+    let log_format = settings.log_format;
+    let transitions = settings.transitions.clone();
+    events.emit(JobEvent::Started { name: name.clone() });
+    let mut executor = Executor::new(settings, config, action, repo, cancel, delay);
+    let mut entered = Instant::now();
     loop {
-        trace!("loop {:?}", executor);
-        executor = match executor {
-            Executor::Initial(shared, jdata, delay) => on_initial(shared, jdata, delay).await,
-            Executor::Start(shared, jdata) => on_start(shared, jdata).await,
-            Executor::Sleeping(shared, delay) => on_sleeping(shared, delay).await,
-            Executor::CheckDue(shared, delay) => on_check_due(shared, delay).await,
-            Executor::TryLock(shared, delay) => on_try_lock(shared, delay).await,
-            Executor::Run(shared, jdata, lock) => on_run(shared, jdata, lock).await,
-            Executor::Done => return Ok(()),
+        if log_format == LogFormat::Text {
+            job_log!(Level::Trace, "loop {:?}", executor);
+        }
+        if let Executor::Done = executor {
+            events.emit(JobEvent::Stopped { name });
+            return Ok(());
         }
+        let from = executor.state();
+        let run_id = executor.run_id().cloned();
+        executor = executor.step().await;
+        transitions.record(&name, from, executor.state(), entered.elapsed());
+        if log_format == LogFormat::Json {
+            let run_id = run_id.as_ref().or(executor.run_id());
+            log_transition(&name, run_id, from, executor.state(), entered.elapsed());
+        }
+        entered = Instant::now();
     }
 }
 
@@ -81,60 +481,371 @@ async fn on_initial<R: Repo>(shared: Shared<R>, jdata: JobData, delay: Duration)
 }
 
 async fn on_sleeping<R: Repo>(mut shared: Shared<R>, delay: Duration) -> Executor<R> {
+    let mut draining = shared.settings.draining.clone();
+    // A kept lock is refreshed while sleeping until the next run.
+    let mut held = shared.held.take();
     let done = tokio::select! {
         _ = sleep(delay) =>  false,
-        _ = &mut shared.cancel => true
+        Err(e) = refresh(&mut held) => {
+            job_log!(Level::Warn, "refreshing the kept lock failed: {}", e);
+            held = None;
+            false
+        }
+        _ = &mut shared.cancel => true,
+        _ = draining.wait_for(|draining| *draining) => true
     };
+    shared.held = held;
 
     if done {
+        shared.release_lock().await;
+        if shared.config.pinned && shared.draining() {
+            shared.release_pin().await;
+        }
         Executor::Done
     } else {
-        Executor::CheckDue(shared, delay)
+        Executor::CheckDue(shared)
+    }
+}
+
+// Refresh the kept lock, if any, until the refresh fails.
+async fn refresh<L: Future<Output = Result<()>> + Unpin>(lock: &mut Option<L>) -> Result<()> {
+    match lock {
+        Some(lock) => lock.await,
+        None => std::future::pending().await,
+    }
+}
+
+// Log the lints of the job's schedule as kept in the repo, which may differ from the
+// registered one, so that mistakes show up when the executor starts.
+fn warn_about_schedule(jdata: &JobData) {
+    for lint in jdata.schedule.lint() {
+        job_log!(
+            Level::Warn,
+            "job {:?}: {} ({})",
+            jdata.name.as_str(),
+            lint,
+            jdata.schedule.describe()
+        );
     }
 }
 
 async fn on_start<R: Repo>(mut shared: Shared<R>, jdata: JobData) -> Executor<R> {
-    match shared.repo.get(jdata.name.clone().into()).await {
+    match shared.repo.get(jdata.name.clone()).await {
         Err(e) => {
-            error!("get job data: {:?}", e);
-            Executor::Initial(shared, jdata, Duration::from_secs(1)) // TODO Backoff
+            let backoff = shared.repo_backoff.next_delay();
+            job_log!(
+                Level::Error,
+                "get job data failed {} times: {:?}, retrying in {}s",
+                shared.repo_backoff.attempts(),
+                e,
+                backoff.as_secs()
+            );
+            shared.settings.events.emit(JobEvent::RepoFailed {
+                name: jdata.name.clone(),
+                kind: e.kind(),
+                error: e.to_string(),
+            });
+            Executor::Initial(shared, jdata, backoff)
+        }
+        Ok(None) => match shared.repo.create(jdata.clone()).await {
+            Err(e) => {
+                let backoff = shared.repo_backoff.next_delay();
+                job_log!(
+                    Level::Error,
+                    "create job data failed {} times: {:?}, retrying in {}s",
+                    shared.repo_backoff.attempts(),
+                    e,
+                    backoff.as_secs()
+                );
+                Executor::Initial(shared, jdata, backoff)
+            }
+            Ok(()) => {
+                shared.repo_backoff.reset();
+                warn_about_schedule(&jdata);
+                Executor::CheckDue(shared)
+            }
+        },
+        Ok(Some(jdata)) => {
+            shared.repo_backoff.reset();
+            warn_about_schedule(&jdata);
+            let now = shared.settings.time.now();
+            // No executor of this instance holds the lock before it starts, so the lock was
+            // left behind by a previous incarnation of the instance, e.g. one that crashed.
+            if jdata.locked_by(now) == Some(shared.settings.instance.as_str()) {
+                job_log!(
+                    Level::Info,
+                    "releasing the lock left behind by a previous incarnation"
+                );
+                if let Err(e) = shared
+                    .repo
+                    .unlock(jdata.name.clone(), shared.settings.instance.clone())
+                    .await
+                {
+                    job_log!(Level::Warn, "releasing the stale lock failed: {}", e);
+                }
+            }
+            if shared.due(&jdata, now) {
+                Executor::CheckDue(shared)
+            } else {
+                Executor::Sleeping(shared, jdata.check_interval)
+            }
+        }
+    }
+}
+
+async fn on_check_due<R: Repo>(mut shared: Shared<R>) -> Executor<R> {
+    // A kept lock is dropped, which releases it, unless the job is due or fires again soon.
+    let held = shared.held.take();
+    match shared.repo.get(shared.config.name.clone()).await {
+        Err(e) => {
+            let backoff = shared.repo_backoff.next_delay();
+            job_log!(
+                Level::Error,
+                "get job data failed {} times: {:?}, retrying in {}s",
+                shared.repo_backoff.attempts(),
+                e,
+                backoff.as_secs()
+            );
+            shared.settings.events.emit(JobEvent::RepoFailed {
+                name: shared.config.name.clone(),
+                kind: e.kind(),
+                error: e.to_string(),
+            });
+            Executor::Sleeping(shared, backoff)
         }
         Ok(None) => {
-            match shared.repo.create(jdata.clone()).await {
-                Err(e) => {
-                    error!("create job data: {:?}", e);
-                    Executor::Initial(shared, jdata, Duration::from_secs(1)) // TODO Backoff
+            shared.repo_backoff.reset();
+            shared.settings.events.emit(JobEvent::DeletedExternally {
+                name: shared.config.name.clone(),
+            });
+            match shared.config.on_deleted {
+                OnDeleted::Recreate => {
+                    job_log!(Level::Warn, "job data deleted externally, recreating it");
+                    let jdata = JobData::from(shared.config.clone());
+                    Executor::Start(shared, jdata)
+                }
+                OnDeleted::Stop => {
+                    job_log!(Level::Warn, "job data deleted externally, exiting executor");
+                    Executor::Done
+                }
+            }
+        }
+        Ok(Some(jdata)) => {
+            shared.repo_backoff.reset();
+            let now = shared.settings.time.now();
+            if shared.config.pinned && !jdata.archived && !shared.hold_pin(&jdata).await {
+                return Executor::Sleeping(shared, jdata.check_interval);
+            }
+            // Requested runs of archived jobs wait until the job is unarchived.
+            if jdata.requested.is_some() && !jdata.archived {
+                shared.held = held;
+                return Executor::TryLock(shared, jdata.check_interval);
+            }
+            if shared.due(&jdata, now) {
+                if awaiting_approval(&shared, &jdata) {
+                    job_log!(Level::Trace, "due run awaiting approval");
+                    return Executor::Sleeping(shared, jdata.check_interval);
                 }
-                Ok(()) => Executor::TryLock(shared, jdata.check_interval),
+                match shared.repo.maintenance().await {
+                    Ok(true) => {
+                        job_log!(Level::Trace, "maintenance mode is on");
+                        shared
+                            .report_skip_once(&jdata, SkipReason::Maintenance)
+                            .await;
+                        return Executor::Sleeping(shared, jdata.check_interval);
+                    }
+                    Ok(false) => {}
+                    Err(e) => job_log!(Level::Warn, "reading the maintenance flag failed: {}", e),
+                }
+                if shared.shed() {
+                    job_log!(
+                        Level::Trace,
+                        "quota of the job's group saturated, shedding the due run"
+                    );
+                    shared.quota_wait = None;
+                    shared.report_skip_once(&jdata, SkipReason::Shed).await;
+                    return Executor::Sleeping(shared, jdata.check_interval);
+                }
+                shared.held = held;
+                return when_due(shared, jdata.check_interval);
+            }
+            shared.lock_backoff.reset();
+            shared.quota_wait = None;
+            if let Some(reason) = shared.skipped.take() {
+                shared.report_skip(reason).await;
             }
+            if !jdata.enabled && !jdata.archived && jdata.schedule.due(&jdata.last_run, now) {
+                shared.report_skip_once(&jdata, SkipReason::Disabled).await;
+            }
+            let delay = if jdata.enabled && !jdata.archived {
+                if shared.config.fast_path
+                    && fires_within(&jdata.schedule, now, jdata.check_interval)
+                {
+                    shared.held = held;
+                }
+                shared.next_check(&jdata.schedule, jdata.check_interval)
+            } else {
+                jdata.check_interval
+            };
+            Executor::Sleeping(shared, delay)
         }
-        Ok(Some(jdata)) if jdata.due(Utc::now()) => Executor::TryLock(shared, jdata.check_interval),
-        Ok(Some(jdata)) => Executor::Sleeping(shared, jdata.check_interval),
     }
 }
 
-async fn on_check_due<R: Repo>(mut shared: Shared<R>, delay: Duration) -> Executor<R> {
-    match shared.repo.get(shared.name.clone()).await {
-        // TODO split these two cases for clarity
-        Err(_) | Ok(None) => Executor::Sleeping(shared, delay), // TODO Retry interval, attempt counter, bbackoff },
-        Ok(Some(jdata)) if jdata.due(Utc::now()) => Executor::TryLock(shared, jdata.check_interval),
-        Ok(Some(_)) => Executor::Sleeping(shared, delay),
+// Whether the due run has been announced for approval but not approved yet.
+fn awaiting_approval<R: Repo>(shared: &Shared<R>, jdata: &JobData) -> bool {
+    shared.config.requires_approval
+        && jdata
+            .approval
+            .as_ref()
+            .is_some_and(|approval| !approval.approved)
+}
+
+// Whether the schedule fires again within `period` after `time`.
+fn fires_within(schedule: &Schedule, time: DateTime<Utc>, period: Duration) -> bool {
+    schedule
+        .next_after(&time)
+        .and_then(|next| (next - time).to_std().ok())
+        .is_some_and(|until| until < period)
+}
+
+// A due job contends for the lock right away unless it prefers another zone, in which case
+// the instances of that zone get a head start.
+fn when_due<R: Repo>(shared: Shared<R>, delay: Duration) -> Executor<R> {
+    if shared.outside_preferred_zone() {
+        Executor::Defer(shared, delay)
+    } else {
+        Executor::TryLock(shared, delay)
+    }
+}
+
+async fn on_defer<R: Repo>(mut shared: Shared<R>, delay: Duration) -> Executor<R> {
+    let done = tokio::select! {
+        _ = sleep(shared.config.zone_grace) =>  false,
+        _ = &mut shared.cancel => true
+    };
+    if done || shared.draining() {
+        return Executor::Done;
+    }
+
+    match shared.repo.get(shared.config.name.clone()).await {
+        Ok(Some(jdata)) if shared.due(&jdata, shared.settings.time.now()) => {
+            Executor::TryLock(shared, delay)
+        }
+        _ => Executor::Sleeping(shared, delay),
     }
 }
 async fn on_try_lock<R: Repo>(mut shared: Shared<R>, delay: Duration) -> Executor<R> {
-    match shared
-        .repo
-        .lock(
-            shared.name.clone(),
-            shared.instance.clone(),
-            Duration::from_secs(10),
-        )
-        .await
+    // A kept lock is used for the run instead of contending for the lock again, and dropped
+    // if the job does not run now.
+    let held = shared.held.take();
+    if shared.draining() {
+        return Executor::Done;
+    }
+    if shared
+        .settings
+        .clock
+        .as_ref()
+        .is_some_and(|clock| clock.refuses())
     {
-        Err(_) => Executor::Sleeping(shared, delay), // TODO Retry interval, attempt counter, bbackoff },
-        Ok(LockStatus::AlreadyLocked) => Executor::Sleeping(shared, delay),
-        Ok(LockStatus::Acquired(jdata, lock)) if jdata.due(Utc::now()) => {
-            Executor::Run(shared, jdata, lock)
+        job_log!(Level::Trace, "clock skew exceeds the maximum");
+        shared.skipped = Some(SkipReason::ClockSkew);
+        return Executor::Sleeping(shared, delay);
+    }
+    let Some(permit) = shared.admit() else {
+        job_log!(Level::Trace, "quota of the job's group exhausted");
+        shared.skipped = Some(SkipReason::QuotaExhausted);
+        return Executor::Sleeping(shared, delay);
+    };
+    let locked = match held {
+        Some(lock) => shared.kept_lock(lock).await,
+        None => {
+            shared
+                .repo
+                .lock(
+                    shared.config.name.clone(),
+                    shared.settings.instance.clone(),
+                    shared.config.lock_ttl,
+                )
+                .await
+        }
+    };
+    if locked.is_ok() {
+        shared.repo_backoff.reset();
+    }
+    match locked {
+        Err(e) => {
+            let backoff = shared.repo_backoff.next_delay();
+            job_log!(
+                Level::Error,
+                "lock failed {} times: {:?}, retrying in {}s",
+                shared.repo_backoff.attempts(),
+                e,
+                backoff.as_secs()
+            );
+            shared.settings.events.emit(JobEvent::RepoFailed {
+                name: shared.config.name.clone(),
+                kind: e.kind(),
+                error: e.to_string(),
+            });
+            Executor::Sleeping(shared, backoff)
+        }
+        Ok(LockStatus::AlreadyLocked) => {
+            // Heavier instances retry sooner and so win contended locks more often.
+            let backoff = shared.lock_backoff.next_delay() / shared.settings.weight;
+            shared.skipped = Some(SkipReason::LockedElsewhere);
+            job_log!(
+                Level::Trace,
+                "lock held by another instance ({} times), retrying in {}ms",
+                shared.lock_backoff.attempts(),
+                backoff.as_millis()
+            );
+            Executor::Sleeping(shared, backoff)
+        }
+        Ok(LockStatus::Acquired(jdata, lock)) if jdata.requested.is_some() => {
+            shared.lock_backoff.reset();
+            // Withdraw the request before running so that a failing run is not repeated.
+            if let Err(e) = shared.repo.request_run(jdata.name.clone(), None).await {
+                job_log!(Level::Error, "withdrawing the run request failed: {}", e);
+                return Executor::Sleeping(shared, delay);
+            }
+            shared.permit = permit;
+            shared.requested = jdata.requested.clone();
+            if let Some(trace_parent) = jdata
+                .requested
+                .as_ref()
+                .and_then(|r| r.trace_parent.as_ref())
+            {
+                job_log!(
+                    Level::Info,
+                    "requested run continues trace {}",
+                    trace_parent
+                );
+            }
+            Executor::Run(shared, jdata, lock, RunId::generate())
+        }
+        Ok(LockStatus::Acquired(jdata, lock)) if shared.due(&jdata, shared.settings.time.now()) => {
+            shared.lock_backoff.reset();
+            shared.skipped = None;
+            if !shared.config.requires_approval {
+                shared.permit = permit;
+                return Executor::Run(shared, jdata, lock, RunId::generate());
+            }
+            match jdata.approval.clone() {
+                Some(Approval {
+                    run_id,
+                    approved: true,
+                }) => {
+                    shared.permit = permit;
+                    Executor::Run(shared, jdata, lock, run_id)
+                }
+                // Dropping the lock releases it until the run is approved.
+                Some(_) => Executor::Sleeping(shared, delay),
+                None => {
+                    request_approval(&mut shared, jdata.name).await;
+                    Executor::Sleeping(shared, delay)
+                }
+            }
         }
         Ok(LockStatus::Acquired(jdata, _)) => {
             // We hold the lock but job is not due, so we call save with existing data to
@@ -143,63 +854,548 @@ async fn on_try_lock<R: Repo>(mut shared: Shared<R>, delay: Duration) -> Executo
             // Usually the job shoud be due when we reach TryLock.
             match shared
                 .repo
-                .save(jdata.name, jdata.last_run, jdata.state)
+                .save(
+                    jdata.name,
+                    shared.settings.instance.clone(),
+                    jdata.last_run,
+                    jdata.state,
+                    jdata.state_version,
+                    None,
+                )
                 .await
             {
                 Ok(()) => Executor::Sleeping(shared, delay),
                 Err(e) => {
-                    error!("unlock failed in try-lock-but-not-due edge case: {:?}", e);
+                    job_log!(
+                        Level::Error,
+                        "unlock failed in try-lock-but-not-due edge case: {:?}",
+                        e
+                    );
                     Executor::Sleeping(shared, delay)
                 }
             }
         }
     }
 }
-async fn on_run<R: Repo>(mut shared: Shared<R>, jdata: JobData, lock: R::Lock) -> Executor<R> {
-    if !jdata.due(Utc::now()) {
+// Persist the due run as pending approval while holding the lock, so that exactly one
+// instance announces it.
+async fn request_approval<R: Repo>(shared: &mut Shared<R>, name: JobName) {
+    let approval = Approval {
+        run_id: RunId::generate(),
+        approved: false,
+    };
+    let run_id = approval.run_id.clone();
+    match shared.repo.set_approval(name.clone(), Some(approval)).await {
+        Ok(()) => {
+            job_log!(Level::Info, "due run {} awaits approval", run_id.as_str());
+            shared
+                .settings
+                .events
+                .emit(JobEvent::ApprovalRequested { name, run_id });
+        }
+        Err(e) => job_log!(Level::Error, "requesting approval failed: {}", e),
+    }
+}
+
+async fn on_run<R: Repo>(
+    mut shared: Shared<R>,
+    jdata: JobData,
+    lock: R::Lock,
+    run_id: RunId,
+) -> Executor<R> {
+    let _permit = shared.permit.take();
+    let requested = shared.requested.take();
+    let is_requested = requested.is_some();
+    let RunRequest {
+        dry_run,
+        as_of,
+        trace_parent,
+        triggered,
+    } = requested.unwrap_or_default();
+    // Only triggered runs keep their state among the requested ones.
+    let discards_state = is_requested && !triggered;
+    if !is_requested && !shared.due(&jdata, shared.settings.time.now()) {
+        let delay = shared.next_check(&jdata.schedule, jdata.check_interval);
+        return Executor::Sleeping(shared, delay);
+    }
+
+    if let Err(e) = shared.settings.hooks.before_run(&jdata.name, &run_id).await {
+        job_log!(Level::Warn, "before-run hook rejected the run: {}", e);
+        shared.report_skip_once(&jdata, SkipReason::Rejected).await;
         return Executor::Sleeping(shared, jdata.check_interval);
     }
 
-    let job_fut = shared.action.call(jdata.state);
-    let select_result = tokio::select! {
-        job_result = job_fut => {
-            match job_result {
-                Ok(state) => {
-                    trace!("callback done, got state");
-                    match shared.repo.save(jdata.name.clone(), Utc::now(), state).await {
-                        Ok(()) => RunSelectResult::Success,
-                        Err(e) => RunSelectResult::SaveFailure(e)
+    if let Some(gate_keeper) = &shared.settings.gate_keeper {
+        let allowed = match gate_keeper.allows(&jdata.name, &jdata.metadata).await {
+            Ok(allowed) => allowed,
+            Err(e) => {
+                job_log!(Level::Warn, "gate keeper failed: {}", e);
+                false
+            }
+        };
+        if !allowed {
+            job_log!(Level::Trace, "gate keeper kept the run from starting");
+            shared.report_skip_once(&jdata, SkipReason::Gated).await;
+            return Executor::Sleeping(shared, jdata.check_interval);
+        }
+    }
+
+    let p95 = match shared.config.stuck_factor {
+        Some(_) => match shared.repo.runs(jdata.name.clone(), STUCK_HISTORY).await {
+            Ok(runs) => p95_duration(&runs, MIN_STUCK_SAMPLES),
+            Err(e) => {
+                job_log!(
+                    Level::Warn,
+                    "reading the run history failed, not checking for a stuck run: {}",
+                    e
+                );
+                None
+            }
+        },
+        None => None,
+    };
+    let events = shared.settings.events.clone();
+    events.emit(JobEvent::RunStarted {
+        name: jdata.name.clone(),
+        run_id: run_id.clone(),
+    });
+    let started = Instant::now();
+    let started_at = shared.settings.time.now();
+    let (cancel_run, canceled) = watch::channel(false);
+    let ctx = JobContext::new(
+        jdata.name.clone(),
+        run_id.clone(),
+        dry_run,
+        shared.config.max_log_size,
+        canceled,
+    )
+    .with_as_of(as_of.unwrap_or(started_at))
+    .with_fire_time(match as_of {
+        Some(as_of) => as_of,
+        None if is_requested => started_at,
+        // The latest time the schedule fired, which a run started within the due tolerance
+        // before the time may not have reached yet.
+        None => jdata
+            .schedule
+            .previous_before(
+                &(started_at
+                    + ChronoDuration::from_std(shared.config.due_tolerance).unwrap_or_default()),
+            )
+            .unwrap_or(started_at),
+    })
+    .with_trace_parent(trace_parent.clone())
+    .with_time(shared.settings.time.clone());
+    let time = shared.settings.time.clone();
+    let state_version = shared.action.state_version();
+    let max_state_size = shared.config.max_state_size.filter(|_| !discards_state);
+    let on_oversized_state = shared.config.on_oversized_state;
+    let heartbeat_interval = (shared.config.lock_ttl / 2).max(Duration::from_secs(1));
+    let cancel_deadline = shared.config.cancel_deadline;
+    let fast_path = shared.config.fast_path;
+    let owner = shared.settings.instance.clone();
+    let mut lock = lock;
+    // Whether the state was saved keeping the lock for the next time the schedule fires.
+    let mut keep_lock = false;
+    // Events and result of the run, kept only when the state is saved or for dry runs. The
+    // job is dropped at the end of the block, aborting a job that ignored a cancel.
+    let ((select_result, run_events, run_result), canceled) = {
+        let action = &mut shared.action;
+        let repo = &mut shared.repo;
+        let heartbeats = &mut shared.heartbeats;
+        let job_fut = async {
+            let state = if jdata.state_version == state_version {
+                jdata.state
+            } else {
+                ctx.logger().info(format!(
+                    "migrating state from version {} to {}",
+                    jdata.state_version, state_version
+                ));
+                action
+                    .migrate_state(jdata.state_version, jdata.state)
+                    .await?
+            };
+            let mut output = tokio::select! {
+                output = action.call(&ctx, state) => output?,
+                never = write_heartbeats(heartbeats, &jdata.name, &ctx, heartbeat_interval) => match never {},
+            };
+            if let Some(max) = max_state_size {
+                output.state =
+                    fit_state(action, &jdata.name, output.state, max, on_oversized_state).await?;
+            }
+            Ok::<_, JobError>(output)
+        };
+        let run_fut = async {
+            match job_fut.await {
+                Ok(output) if discards_state => {
+                    job_log!(Level::Trace, "requested run done, discarding state");
+                    (RunSelectResult::Success, ctx.take_events(), output.result)
+                }
+                Ok(output) => {
+                    job_log!(Level::Trace, "callback done, got state");
+                    let outbox = Outbox {
+                        run_id: run_id.clone(),
+                        events: ctx.take_events(),
+                    };
+                    let run_events = outbox.events.clone();
+                    let last_run = time.now();
+                    let keep =
+                        fast_path && fires_within(&jdata.schedule, last_run, jdata.check_interval);
+                    match save_with_retry(
+                        repo,
+                        &jdata.name,
+                        &owner,
+                        last_run,
+                        output.state,
+                        state_version,
+                        outbox,
+                        keep,
+                    )
+                    .await
+                    {
+                        Ok(()) => {
+                            keep_lock = keep;
+                            (RunSelectResult::Success, run_events, output.result)
+                        }
+                        Err(e) => (RunSelectResult::SaveFailure(e), Vec::new(), None),
                     }
-                },
-                Err(e) => RunSelectResult::JobFailure(e)
+                }
+                Err(e) => (RunSelectResult::JobFailure(e), Vec::new(), None),
             }
+        };
+        let stuck = watch_stuck(
+            &ctx,
+            &time,
+            started_at,
+            p95.zip(shared.config.stuck_factor),
+            shared.config.on_stuck_run,
+            &events,
+        );
+        tokio::pin!(run_fut);
+        tokio::pin!(stuck);
+        // Whether the job was stopped, or else only the run was canceled for being stuck.
+        let stopped = tokio::select! {
+            result = &mut run_fut => Err(result),
+            Err(e) = &mut lock => Err((RunSelectResult::LockFailure(e), Vec::new(), None)),
+            _ = &mut shared.cancel => Ok(true),
+            _ = &mut stuck => Ok(false),
+        };
+        match stopped {
+            Err(result) => (result, false),
+            Ok(stopped) => {
+                // Let the job wind down on its own until the deadline, it is dropped after that.
+                cancel_run.send_replace(true);
+                job_log!(
+                    Level::Info,
+                    "run canceled, waiting up to {}s for the job to return",
+                    cancel_deadline.as_secs()
+                );
+                let result = tokio::select! {
+                    result = &mut run_fut => result,
+                    Err(e) = &mut lock => (RunSelectResult::LockFailure(e), Vec::new(), None),
+                    _ = sleep(cancel_deadline) => (RunSelectResult::Aborted, Vec::new(), None),
+                };
+                (result, stopped)
+            }
+        }
+    };
+    if keep_lock && !canceled {
+        shared.held = Some(lock);
+    } else {
+        drop(lock);
+    }
+    let outcome = match &select_result {
+        RunSelectResult::Success => RunOutcome::Succeeded,
+        RunSelectResult::JobFailure(e) => RunOutcome::Failed(e.to_string()),
+        RunSelectResult::LockFailure(e) => {
+            RunOutcome::Interrupted(format!("lock refresh failed: {}", e))
+        }
+        RunSelectResult::SaveFailure(e) => {
+            RunOutcome::Interrupted(format!("state saving failed: {}", e))
         }
-        Err(e) = lock => {
-            RunSelectResult::LockFailure(e)
+        RunSelectResult::Aborted => RunOutcome::Aborted,
+    };
+    if (keep_lock && canceled) || matches!(select_result, RunSelectResult::Aborted) {
+        // The dropped lock is released in the background, which may not happen anymore when
+        // the runtime shuts down.
+        if let Err(e) = shared
+            .repo
+            .unlock(jdata.name.clone(), shared.settings.instance.clone())
+            .await
+        {
+            job_log!(
+                Level::Error,
+                "releasing the lock of the stopped run failed: {}",
+                e
+            );
         }
-        _ = &mut shared.cancel => {
-            RunSelectResult::Canceled
-         }
+    }
+    for e in shared
+        .settings
+        .hooks
+        .after_run(&jdata.name, &run_id, &outcome)
+        .await
+    {
+        job_log!(Level::Warn, "after-run hook failed: {}", e);
+    }
+    let (log, log_truncated) = ctx.logger().output();
+    let record = RunRecord {
+        run_id: run_id.clone(),
+        name: jdata.name.clone(),
+        instance: shared.settings.instance.clone(),
+        started: started_at,
+        finished: shared.settings.time.now(),
+        outcome,
+        log,
+        log_truncated,
+        dry_run,
+        as_of,
+        trace_parent,
+        events: run_events,
+        result: run_result,
     };
+    if let Err(e) = shared.repo.record_run(record).await {
+        job_log!(Level::Error, "recording run in history failed: {}", e);
+    }
+    if ctx.last_heartbeat().is_some() {
+        if let Err(e) = shared.repo.heartbeat(jdata.name.clone(), None).await {
+            job_log!(Level::Error, "clearing the heartbeat failed: {}", e);
+        }
+    }
+    // An approval covers a single run, whatever its outcome.
+    if shared.config.requires_approval && !is_requested {
+        if let Err(e) = shared.repo.set_approval(jdata.name.clone(), None).await {
+            job_log!(Level::Error, "clearing the approval failed: {}", e);
+        }
+    }
+    let run_duration = started.elapsed();
+    if lock_ttl_tight(shared.config.lock_ttl, run_duration) {
+        job_log!(
+            Level::Warn,
+            "run took {}s, at least half the lock TTL of {}s",
+            run_duration.as_secs(),
+            shared.config.lock_ttl.as_secs()
+        );
+        events.emit(JobEvent::LockTtlTight {
+            name: jdata.name.clone(),
+            lock_ttl: shared.config.lock_ttl,
+            run_duration,
+        });
+    }
 
+    let next_check = shared.next_check(&jdata.schedule, jdata.check_interval);
     // TODO refine all the Done cases to proper sleeps + backoff
     match select_result {
-        RunSelectResult::Success => Executor::Sleeping(shared, jdata.check_interval),
+        RunSelectResult::Success => {
+            events.emit(JobEvent::RunSucceeded {
+                name: jdata.name,
+                run_id,
+                duration: run_duration,
+            });
+            if canceled {
+                return Executor::Done;
+            }
+            Executor::Sleeping(shared, next_check)
+        }
         RunSelectResult::JobFailure(e) => {
-            error!("job failed: {}, seleeping", e);
-            Executor::Sleeping(shared, jdata.check_interval)
+            job_log!(Level::Error, "job failed: {}, seleeping", e);
+            events.emit(JobEvent::RunFailed {
+                name: jdata.name,
+                run_id,
+                error: e.to_string(),
+            });
+            if canceled {
+                return Executor::Done;
+            }
+            Executor::Sleeping(shared, next_check)
         }
         RunSelectResult::LockFailure(e) => {
-            error!("lock refresh failed: {}, exiting executor", e);
+            job_log!(Level::Error, "lock refresh failed: {}, exiting executor", e);
+            events.emit(JobEvent::LockFailed {
+                name: jdata.name,
+                error: e.to_string(),
+            });
             Executor::Done
         }
         RunSelectResult::SaveFailure(e) => {
-            error!("state saving failed: {}, exiting executor", e);
+            job_log!(
+                Level::Error,
+                "state saving failed after {} attempts: {}, exiting executor",
+                SAVE_ATTEMPTS,
+                e
+            );
+            events.emit(JobEvent::SaveFailed {
+                name: jdata.name,
+                error: e.to_string(),
+            });
             Executor::Done
         }
-        RunSelectResult::Canceled => {
-            info!("executor canceled");
-            Executor::Done
+        RunSelectResult::Aborted => {
+            job_log!(
+                Level::Warn,
+                "job ignored the cancel for {}s, aborted it",
+                cancel_deadline.as_secs()
+            );
+            events.emit(JobEvent::RunAborted {
+                name: jdata.name,
+                run_id,
+            });
+            if canceled {
+                return Executor::Done;
+            }
+            Executor::Sleeping(shared, next_check)
+        }
+    }
+}
+
+// Report the run once it made no progress for longer than the factor times the 95th percentile
+// of the job's run durations. Completes only if the run is to be canceled then.
+async fn watch_stuck(
+    ctx: &JobContext,
+    time: &TimeSource,
+    started_at: DateTime<Utc>,
+    p95_and_factor: Option<(Duration, f64)>,
+    on_stuck: OnStuckRun,
+    events: &Events,
+) {
+    let Some((p95, factor)) = p95_and_factor else {
+        return std::future::pending().await;
+    };
+    let limit = p95.mul_f64(factor).max(MIN_STUCK_LIMIT);
+    loop {
+        let progress = ctx.last_heartbeat().unwrap_or(started_at);
+        let stalled = (time.now() - progress).to_std().unwrap_or_default();
+        if stalled < limit {
+            sleep(limit - stalled).await;
+            continue;
+        }
+        job_log!(
+            Level::Warn,
+            "run made no progress for {}s, more than {} times the usual {}ms",
+            stalled.as_secs(),
+            factor,
+            p95.as_millis()
+        );
+        events.emit(JobEvent::RunStuck {
+            name: ctx.name().clone(),
+            run_id: ctx.run_id().clone(),
+            stalled,
+            p95,
+        });
+        if on_stuck == OnStuckRun::Cancel {
+            return;
+        }
+        return std::future::pending().await;
+    }
+}
+
+// Write the latest heartbeat of the run to the repo whenever it changed since the last write.
+async fn write_heartbeats<R: Repo>(
+    repo: &mut R,
+    name: &JobName,
+    ctx: &JobContext,
+    interval: Duration,
+) -> Infallible {
+    let mut written = None;
+    loop {
+        sleep(interval).await;
+        let latest = ctx.last_heartbeat();
+        if latest == written {
+            continue;
+        }
+        match repo.heartbeat(name.clone(), latest).await {
+            Ok(()) => written = latest,
+            Err(e) => job_log!(Level::Warn, "writing heartbeat failed: {}", e),
+        }
+    }
+}
+
+// Bring state larger than the maximum down to size as configured, or fail the run so that the
+// previous state is kept.
+async fn fit_state(
+    action: &mut Box<dyn Job + Send>,
+    name: &JobName,
+    state: Vec<u8>,
+    max: usize,
+    on_oversized: OnOversizedState,
+) -> std::result::Result<Vec<u8>, JobError> {
+    if state.len() <= max {
+        return Ok(state);
+    }
+    let size = state.len();
+    let state = match on_oversized {
+        OnOversizedState::Fail => state,
+        OnOversizedState::Shrink => {
+            let state = action.shrink_state(state, max).await?;
+            job_log!(
+                Level::Trace,
+                "shrunk state from {} to {} bytes",
+                size,
+                state.len()
+            );
+            state
+        }
+    };
+    if state.len() > max {
+        return Err(JobError::any(Error::StateTooLarge(
+            name.clone(),
+            state.len(),
+            max,
+        )));
+    }
+    Ok(state)
+}
+
+// Saving runs while the lock is still refreshed, so transient repo failures are retried
+// before the executor gives up, but not the loss of the lock. The lock is released by the
+// save unless `keep_lock`.
+#[allow(clippy::too_many_arguments)]
+async fn save_with_retry<R: Repo>(
+    repo: &mut R,
+    name: &JobName,
+    owner: &str,
+    last_run: DateTime<Utc>,
+    state: Vec<u8>,
+    state_version: u32,
+    outbox: Outbox,
+    keep_lock: bool,
+) -> Result<()> {
+    let mut backoff = Backoff::new(SAVE_RETRY_BACKOFF, MAX_REPO_BACKOFF);
+    loop {
+        let saved = if keep_lock {
+            repo.commit(
+                name.clone(),
+                owner.to_owned(),
+                last_run,
+                state.clone(),
+                state_version,
+                Some(outbox.clone()),
+            )
+            .await
+        } else {
+            repo.save(
+                name.clone(),
+                owner.to_owned(),
+                last_run,
+                state.clone(),
+                state_version,
+                Some(outbox.clone()),
+            )
+            .await
+        };
+        match saved {
+            Ok(()) => return Ok(()),
+            Err(e @ Error::LockRefreshFailed(_)) => return Err(e),
+            Err(e) if backoff.attempts() + 1 < SAVE_ATTEMPTS => {
+                let attempt = backoff.attempts() + 1;
+                job_log!(
+                    Level::Warn,
+                    "state saving attempt {} failed: {}, retrying",
+                    attempt,
+                    e
+                );
+                sleep(backoff.next_delay()).await;
+            }
+            Err(e) => return Err(e),
         }
     }
 }
@@ -209,5 +1405,90 @@ enum RunSelectResult<E> {
     JobFailure(E),
     LockFailure(Error),
     SaveFailure(Error),
-    Canceled,
+    Aborted,
+}
+
+/// Drives the executor of a single job one transition at a time, to test how the executor
+/// behaves against a repo without a manager. The transitions are named like in the
+/// [`crate::TRANSITION_TARGET`] log. Run it on a paused tokio runtime, e.g. with
+/// `#[tokio::test(start_paused = true)]`, so that the waits of the executor pass instantly and
+/// its clock, which starts at the given time, advances with them.
+/// ```rust,ignore
+///     let mut harness = ExecutorHarness::new("instance-a", config, ReportJob, repo, start);
+///     assert_eq!(
+///         harness.run_until("run", 10).await,
+///         ["start", "check_due", "try_lock", "run"]
+///     );
+/// ```
+#[cfg(feature = "test-util")]
+#[allow(private_bounds)]
+pub struct ExecutorHarness<R: Repo> {
+    executor: Executor<R>,
+    events: Events,
+    stop: Option<tokio::sync::oneshot::Sender<()>>,
+    _drain: watch::Sender<bool>,
+}
+
+#[cfg(feature = "test-util")]
+#[allow(private_bounds)]
+impl<R: Repo + Clone + Send> ExecutorHarness<R> {
+    pub fn new(
+        instance: impl Into<InstanceId>,
+        config: JobConfig,
+        job: impl Job + Send + 'static,
+        repo: R,
+        start: DateTime<Utc>,
+    ) -> Self {
+        let (_drain, draining) = watch::channel(false);
+        let (stop, cancel) = tokio::sync::oneshot::channel();
+        let mut settings = Settings::new(instance.into().0, Events::new(64), 0, draining);
+        settings.time = TimeSource::tokio(start);
+        let events = settings.events.clone();
+        ExecutorHarness {
+            executor: Executor::new(
+                settings,
+                config,
+                Box::new(job),
+                repo,
+                cancel,
+                Duration::ZERO,
+            ),
+            events,
+            stop: Some(stop),
+            _drain,
+        }
+    }
+    /// State the executor is in, `initial` before the first transition.
+    pub fn state(&self) -> &'static str {
+        self.executor.state()
+    }
+    /// Make a single transition and return the state it led to.
+    pub async fn step(&mut self) -> &'static str {
+        let executor = std::mem::replace(&mut self.executor, Executor::Done);
+        self.executor = executor.step().await;
+        self.executor.state()
+    }
+    /// Make transitions until the executor is in `state` or done, at most `max_steps` of
+    /// them, and return the states it went through.
+    pub async fn run_until(&mut self, state: &str, max_steps: usize) -> Vec<&'static str> {
+        let mut states = Vec::new();
+        while states.len() < max_steps {
+            let reached = self.step().await;
+            states.push(reached);
+            if reached == state || reached == "done" {
+                break;
+            }
+        }
+        states
+    }
+    /// Stop the job like the manager does, the executor is done after its next transition.
+    pub fn stop(&mut self) {
+        if let Some(stop) = self.stop.take() {
+            let _ = stop.send(());
+        }
+    }
+    /// Subscribe to the events the executor emits from now on.
+    pub fn events(&self) -> impl futures::Stream<Item = JobEvent> + Send + 'static {
+        self.events.stream()
+    }
 }
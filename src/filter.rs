@@ -0,0 +1,70 @@
+use crate::job::JobData;
+use chrono::{DateTime, Utc};
+
+/// Selects jobs for [`crate::JobManager::statuses_matching`]. Every criterion that is set has
+/// to match; the default filter matches all jobs that are not archived.
+/// ```rust,ignore
+///     let disabled_etl = JobFilter::default()
+///         .with_enabled(false)
+///         .with_label("kind", "etl");
+///     let statuses = manager.statuses_matching(&disabled_etl).await?;
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct JobFilter {
+    pub enabled: Option<bool>,
+    /// Label key and value, see [`crate::JobConfig::with_label`].
+    pub label: Option<(String, String)>,
+    /// Prefix of the job names, e.g. `etl.` for jobs named like `etl.orders`.
+    pub namespace: Option<String>,
+    /// Whether the job is due but has not run yet.
+    pub overdue: Option<bool>,
+    /// Whether an instance holds the job's lock.
+    pub locked: Option<bool>,
+    /// Whether the job is archived, see [`crate::JobManager::archive`]. Unless set to
+    /// `Some(true)` archived jobs are left out.
+    pub archived: Option<bool>,
+}
+
+impl JobFilter {
+    pub fn with_enabled(mut self, enabled: bool) -> Self {
+        self.enabled = Some(enabled);
+        self
+    }
+    pub fn with_label(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.label = Some((key.into(), value.into()));
+        self
+    }
+    pub fn with_namespace(mut self, prefix: impl Into<String>) -> Self {
+        self.namespace = Some(prefix.into());
+        self
+    }
+    pub fn with_overdue(mut self, overdue: bool) -> Self {
+        self.overdue = Some(overdue);
+        self
+    }
+    pub fn with_locked(mut self, locked: bool) -> Self {
+        self.locked = Some(locked);
+        self
+    }
+    pub fn with_archived(mut self, archived: bool) -> Self {
+        self.archived = Some(archived);
+        self
+    }
+
+    pub(crate) fn matches(&self, jdata: &JobData, now: DateTime<Utc>) -> bool {
+        self.archived.unwrap_or(false) == jdata.archived
+            && self.enabled.is_none_or(|enabled| jdata.enabled == enabled)
+            && self
+                .label
+                .as_ref()
+                .is_none_or(|(key, value)| jdata.metadata.labels.get(key) == Some(value))
+            && self
+                .namespace
+                .as_ref()
+                .is_none_or(|prefix| jdata.name.as_str().starts_with(prefix))
+            && self.overdue.is_none_or(|overdue| jdata.due(now) == overdue)
+            && self
+                .locked
+                .is_none_or(|locked| jdata.locked_by(now).is_some() == locked)
+    }
+}
@@ -0,0 +1,24 @@
+use crate::{JobError, JobMetadata, JobName};
+use async_trait::async_trait;
+
+/// Decides right before every run whether the job may run now, e.g. by evaluating a flag of
+/// an external feature flag service such as LaunchDarkly or Unleash, so that jobs do not have
+/// to check flags themselves.
+/// ```rust,ignore
+///     struct Flags(UnleashClient);
+///
+///     #[async_trait]
+///     impl GateKeeper for Flags {
+///         async fn allows(&self, name: &JobName, _: &JobMetadata) -> Result<bool, JobError> {
+///             Ok(self.0.is_enabled(&format!("job.{}", name.as_str())))
+///         }
+///     }
+///
+///     let manager = JobManager::new(InstanceId::generate(), repo).with_gate_keeper(Flags(client));
+/// ```
+#[async_trait]
+pub trait GateKeeper: Send + Sync {
+    /// Whether the due run of the job may start. A closed gate and a failure of the gate
+    /// keeper both skip the run, and the job stays due until the gate opens.
+    async fn allows(&self, name: &JobName, metadata: &JobMetadata) -> Result<bool, JobError>;
+}
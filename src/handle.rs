@@ -0,0 +1,102 @@
+use crate::error::Error;
+use crate::event::Events;
+use crate::job::RunRequest;
+use crate::manager::STATUS_HISTORY_DEPTH;
+use crate::repos::Repo;
+use crate::{JobEvent, JobFilter, JobName, JobStatus};
+use chrono::Utc;
+use futures::Stream;
+use log::info;
+
+/// Cheap handle on the jobs of a [`crate::JobManager`] for the parts of an application that
+/// only control and observe them, e.g. stored in the state of a web framework and cloned into
+/// every request handler. It stays usable while the manager owning the executors is borrowed
+/// mutably or moved, and after the manager is dropped it still reaches the repo, though no
+/// events arrive anymore.
+/// ```rust,ignore
+///     let handle = manager.handle();
+///     manager.start_all();
+///     let app = Router::new().route("/jobs/:name/trigger", post(trigger)).with_state(handle);
+/// ```
+#[derive(Clone)]
+pub struct JobManagerHandle<J> {
+    job_repo: J,
+    events: Events,
+}
+
+#[allow(private_bounds)]
+impl<J: Repo + Clone + Send + 'static> JobManagerHandle<J> {
+    pub(crate) fn new(job_repo: J, events: Events) -> Self {
+        JobManagerHandle { job_repo, events }
+    }
+    /// See [`crate::JobManager::events`].
+    pub fn events(&self) -> impl Stream<Item = JobEvent> + Send + 'static {
+        self.events.stream()
+    }
+    /// See [`crate::JobManager::trigger`].
+    pub async fn trigger(&self, name: JobName) -> Result<(), Error> {
+        let request = RunRequest {
+            triggered: true,
+            ..RunRequest::default()
+        };
+        self.job_repo
+            .clone()
+            .request_run(name.clone(), Some(request))
+            .await?;
+        info!("triggered run of job {:?}", name);
+        Ok(())
+    }
+    /// See [`crate::JobManager::pause`].
+    pub async fn pause(&self, name: JobName) -> Result<(), Error> {
+        self.job_repo
+            .clone()
+            .set_enabled(name.clone(), false)
+            .await?;
+        info!("paused job {:?}", name);
+        Ok(())
+    }
+    /// See [`crate::JobManager::resume`].
+    pub async fn resume(&self, name: JobName) -> Result<(), Error> {
+        self.job_repo
+            .clone()
+            .set_enabled(name.clone(), true)
+            .await?;
+        info!("resumed job {:?}", name);
+        Ok(())
+    }
+    /// See [`crate::JobManager::status`].
+    pub async fn status(&self, name: JobName) -> Result<Option<JobStatus>, Error> {
+        let mut repo = self.job_repo.clone();
+        match repo.get(name.clone()).await? {
+            None => Ok(None),
+            Some(jdata) => {
+                let runs = repo.runs(name, STATUS_HISTORY_DEPTH).await?;
+                Ok(Some(JobStatus::new(&jdata, &runs, Utc::now())))
+            }
+        }
+    }
+    /// See [`crate::JobManager::statuses_matching`].
+    pub async fn statuses_matching(&self, filter: &JobFilter) -> Result<Vec<JobStatus>, Error> {
+        let mut repo = self.job_repo.clone();
+        let now = Utc::now();
+        let mut statuses = Vec::new();
+        for jdata in repo.list_matching(filter).await? {
+            let runs = repo.runs(jdata.name.clone(), STATUS_HISTORY_DEPTH).await?;
+            statuses.push(JobStatus::new(&jdata, &runs, now));
+        }
+        Ok(statuses)
+    }
+    /// Pause or resume all jobs sharing the repo, see [`crate::JobManager::set_maintenance`].
+    pub async fn set_maintenance(&self, on: bool) -> Result<(), Error> {
+        self.job_repo.clone().set_maintenance(on).await?;
+        info!(
+            "maintenance mode switched {}",
+            if on { "on" } else { "off" }
+        );
+        Ok(())
+    }
+    /// See [`crate::JobManager::maintenance`].
+    pub async fn maintenance(&self) -> Result<bool, Error> {
+        self.job_repo.clone().maintenance().await
+    }
+}
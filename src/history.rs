@@ -0,0 +1,134 @@
+use crate::{JobName, RunId};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+
+/// A finished run of a job as stored in the run history.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RunRecord {
+    pub run_id: RunId,
+    pub name: JobName,
+    pub instance: String,
+    pub started: DateTime<Utc>,
+    pub finished: DateTime<Utc>,
+    pub outcome: RunOutcome,
+    /// Output of the run's [`crate::RunLogger`], capped at the job's max log size.
+    pub log: String,
+    pub log_truncated: bool,
+    /// Whether this was a dry run, whose state was discarded.
+    #[serde(default)]
+    pub dry_run: bool,
+    /// Time the run computed for if it was a backfill, see [`crate::JobManager::backfill`].
+    #[serde(default)]
+    pub as_of: Option<DateTime<Utc>>,
+    /// W3C traceparent of the request the run was made for, see
+    /// [`crate::JobManager::dry_run_traced`].
+    #[serde(default)]
+    pub trace_parent: Option<String>,
+    /// Events the run emitted, see [`crate::JobContext::emit`]. Empty unless the run
+    /// succeeded.
+    #[serde(default)]
+    pub events: Vec<RunEvent>,
+    /// Result the run returned apart from the state, see [`crate::JobOutput`]. `None` unless
+    /// the run succeeded.
+    #[serde(default)]
+    pub result: Option<Vec<u8>>,
+}
+
+/// Event a job emitted during a run for downstream consumers, see [`crate::JobContext::emit`].
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RunEvent {
+    pub kind: String,
+    pub payload: serde_json::Value,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum RunOutcome {
+    /// The job returned and its state was saved.
+    Succeeded,
+    /// The job returned an error.
+    Failed(String),
+    /// The run was interrupted by a cancel, a lost lock or a failure to save the state.
+    Interrupted(String),
+    /// The run was canceled but the job did not return before its cancel deadline, so it was
+    /// aborted, see [`crate::JobConfig::with_cancel_deadline`].
+    Aborted,
+    /// This instance did not run the job although it was due, see
+    /// [`crate::JobConfig::with_record_skips`].
+    Skipped(SkipReason),
+}
+
+impl RunOutcome {
+    /// Name of the outcome without its details, e.g. for metric labels.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RunOutcome::Succeeded => "succeeded",
+            RunOutcome::Failed(_) => "failed",
+            RunOutcome::Interrupted(_) => "interrupted",
+            RunOutcome::Aborted => "aborted",
+            RunOutcome::Skipped(_) => "skipped",
+        }
+    }
+}
+
+/// Why an instance did not run a due job.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SkipReason {
+    /// Another instance holds the job's lock.
+    LockedElsewhere,
+    /// The quota of the job's group on this instance was exhausted.
+    QuotaExhausted,
+    /// The job is disabled.
+    Disabled,
+    /// A before-run hook of the manager failed, see [`crate::JobManager::on_before_run`].
+    Rejected,
+    /// The cluster-wide maintenance mode is on, see [`crate::JobManager::set_maintenance`].
+    Maintenance,
+    /// The gate keeper of the manager kept the run from starting, see
+    /// [`crate::JobManager::with_gate_keeper`].
+    Gated,
+    /// The clock of this instance is off from the clock of the store by more than the
+    /// maximum, see [`crate::JobManager::with_max_clock_skew`].
+    ClockSkew,
+    /// The quota of the job's group on this instance was saturated for too long and the job's
+    /// priority is too low to wait for a slot, see [`crate::JobManager::with_load_shedding`].
+    Shed,
+}
+
+impl SkipReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SkipReason::LockedElsewhere => "locked_elsewhere",
+            SkipReason::QuotaExhausted => "quota_exhausted",
+            SkipReason::Disabled => "disabled",
+            SkipReason::Rejected => "rejected",
+            SkipReason::Maintenance => "maintenance",
+            SkipReason::Gated => "gated",
+            SkipReason::ClockSkew => "clock_skew",
+            SkipReason::Shed => "shed",
+        }
+    }
+}
+
+impl Display for SkipReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// 95th percentile of the durations of the scheduled runs among `runs` that succeeded, `None`
+// with fewer than `min_samples` of them.
+pub(crate) fn p95_duration(runs: &[RunRecord], min_samples: usize) -> Option<Duration> {
+    let mut durations: Vec<Duration> = runs
+        .iter()
+        .filter(|run| !run.dry_run && run.as_of.is_none() && run.outcome == RunOutcome::Succeeded)
+        .filter_map(|run| (run.finished - run.started).to_std().ok())
+        .collect();
+    if durations.is_empty() || durations.len() < min_samples {
+        return None;
+    }
+    durations.sort();
+    let rank = (durations.len() * 95).div_ceil(100);
+    Some(durations[rank - 1])
+}
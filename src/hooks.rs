@@ -0,0 +1,43 @@
+use crate::{JobError, JobName, RunId, RunOutcome};
+use futures_util::future::BoxFuture;
+use std::sync::Arc;
+
+pub(crate) type BeforeRunHook =
+    Arc<dyn Fn(JobName, RunId) -> BoxFuture<'static, Result<(), JobError>> + Send + Sync>;
+pub(crate) type AfterRunHook = Arc<
+    dyn Fn(JobName, RunId, RunOutcome) -> BoxFuture<'static, Result<(), JobError>> + Send + Sync,
+>;
+
+/// Callbacks the manager runs around every run of every job, see
+/// `JobManager::on_before_run` and `JobManager::on_after_run`.
+#[derive(Clone, Default)]
+pub(crate) struct Hooks {
+    pub before_run: Vec<BeforeRunHook>,
+    pub after_run: Vec<AfterRunHook>,
+}
+
+impl Hooks {
+    // Run the before hooks in registration order, stopping at the first that fails.
+    pub(crate) async fn before_run(&self, name: &JobName, run_id: &RunId) -> Result<(), JobError> {
+        for hook in &self.before_run {
+            hook(name.clone(), run_id.clone()).await?;
+        }
+        Ok(())
+    }
+
+    // Run all after hooks in registration order and collect their errors.
+    pub(crate) async fn after_run(
+        &self,
+        name: &JobName,
+        run_id: &RunId,
+        outcome: &RunOutcome,
+    ) -> Vec<JobError> {
+        let mut errors = Vec::new();
+        for hook in &self.after_run {
+            if let Err(e) = hook(name.clone(), run_id.clone(), outcome.clone()).await {
+                errors.push(e);
+            }
+        }
+        errors
+    }
+}
@@ -0,0 +1,109 @@
+use crate::repos::Repo;
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use log::{error, trace, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::time::sleep;
+
+const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(10);
+// Announcements missed before the presence of an incarnation is considered gone.
+const MISSED_ANNOUNCEMENTS: i32 = 3;
+
+/// What an instance does on finding another live instance with the same id, see
+/// [`crate::JobManager::with_duplicate_instance_check`]. Duplicate ids let both instances
+/// take and release each other's locks, and attribute their runs to a single instance.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum OnDuplicateInstance {
+    /// Log an error and keep running jobs.
+    #[default]
+    Warn,
+    /// The instance that started last drains, so it runs no jobs.
+    Refuse,
+    /// The instance that started first drains, so the one started last takes over, e.g.
+    /// when a replacement starts before a stalled process has died.
+    Fence,
+}
+
+// Presence of one incarnation of an instance id, announced periodically to the repo.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct InstancePresence {
+    pub instance: String,
+    // Distinguishes the processes using the same instance id.
+    pub incarnation: String,
+    pub started: DateTime<Utc>,
+    pub seen: DateTime<Utc>,
+}
+
+impl InstancePresence {
+    fn live(&self, now: DateTime<Utc>) -> bool {
+        let gone_after =
+            ChronoDuration::from_std(ANNOUNCE_INTERVAL).unwrap_or_default() * MISSED_ANNOUNCEMENTS;
+        now - self.seen < gone_after
+    }
+}
+
+// Announce the presence of this incarnation of the instance until the manager drains or is
+// dropped, draining it once it has to give way to a duplicate. Another incarnation only counts
+// as live once its presence was announced again after this incarnation first saw it, so that
+// the presence a crashed process left behind does not stop its quick restart.
+pub(crate) async fn monitor<R: Repo>(
+    mut repo: R,
+    instance: String,
+    on_duplicate: OnDuplicateInstance,
+    drain: Arc<watch::Sender<bool>>,
+) {
+    let mut draining = drain.subscribe();
+    let started = Utc::now();
+    let incarnation = format!("{:016x}", rand::random::<u64>());
+    // Time in the first presence of each other incarnation this one read.
+    let mut first_seen: HashMap<String, DateTime<Utc>> = HashMap::new();
+    loop {
+        let presence = InstancePresence {
+            instance: instance.clone(),
+            incarnation: incarnation.clone(),
+            started,
+            seen: Utc::now(),
+        };
+        let other = match repo.announce_instance(presence.clone()).await {
+            Ok(other) => {
+                trace!("announced instance {}", instance);
+                other.filter(|other| other.incarnation != incarnation)
+            }
+            Err(e) => {
+                warn!("announcing instance {} failed: {}", instance, e);
+                None
+            }
+        };
+        let duplicate = other.filter(|other| {
+            let first = *first_seen
+                .entry(other.incarnation.clone())
+                .or_insert(other.seen);
+            other.live(presence.seen) && other.seen > first
+        });
+        if let Some(other) = duplicate {
+            let other_is_newer = other.started > started;
+            let give_way = match on_duplicate {
+                OnDuplicateInstance::Warn => false,
+                OnDuplicateInstance::Refuse => !other_is_newer,
+                OnDuplicateInstance::Fence => other_is_newer,
+            };
+            error!(
+                "another instance with id {} started at {} is running{}",
+                instance,
+                other.started,
+                if give_way { ", draining this one" } else { "" }
+            );
+            if give_way {
+                drain.send_replace(true);
+                return;
+            }
+        }
+        tokio::select! {
+            _ = sleep(ANNOUNCE_INTERVAL) => {}
+            _ = draining.wait_for(|draining| *draining) => return,
+        }
+    }
+}
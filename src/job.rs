@@ -1,6 +1,7 @@
 use crate::schedule::Schedule;
-use crate::{JobConfig, JobName};
+use crate::{JobConfig, JobMetadata, JobName, RunEvent, RunId};
 use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
 use std::fmt::Debug;
 use std::time::Duration;
 
@@ -10,27 +11,157 @@ pub(crate) struct JobData {
     pub check_interval: Duration,
     pub lock_ttl: Duration,
     pub state: Vec<u8>,
+    pub state_version: u32,
     pub schedule: Schedule,
     pub enabled: bool,
     pub last_run: DateTime<Utc>,
+    // Next time the schedule fires after the last run as kept by the repo, `None` if it never
+    // fires again.
+    pub next_run: Option<DateTime<Utc>>,
+    // Lock holder as last read from the repo; empty when the job has never been locked.
+    pub owner: String,
+    pub expires: DateTime<Utc>,
+    pub metadata: JobMetadata,
+    // Due run of a job requiring approval, see `JobConfig::with_requires_approval`.
+    pub approval: Option<Approval>,
+    // Run requested outside of the schedule, see `JobManager::dry_run`.
+    pub requested: Option<RunRequest>,
+    // Latest heartbeat of the running run, see `JobContext::heartbeat`.
+    pub last_heartbeat: Option<DateTime<Utc>>,
+    // Whether the job is archived, see `JobManager::archive`.
+    pub archived: bool,
+    // Instance the job is pinned to, see `JobConfig::with_pinning`.
+    pub pin: Option<Pin>,
+}
+
+// Claim of an instance on running a pinned job, renewed while the instance checks the job.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Pin {
+    pub owner: String,
+    pub until: DateTime<Utc>,
+}
+
+impl Pin {
+    // The pin kept in the flat fields of the job documents.
+    pub(crate) fn from_fields(owner: Option<String>, until: Option<DateTime<Utc>>) -> Option<Pin> {
+        Some(Pin {
+            owner: owner?,
+            until: until?,
+        })
+    }
+}
+
+// Run of a job requested outside of its schedule, see `JobManager::dry_run`,
+// `JobManager::backfill` and `JobManager::trigger`. Unless it was triggered it does not count
+// as the job's last run and its state is discarded.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct RunRequest {
+    // Whether the job is asked to avoid side effects.
+    pub dry_run: bool,
+    // Time the run computes for instead of the time it starts at.
+    pub as_of: Option<DateTime<Utc>>,
+    // W3C traceparent of the request, to continue its trace in the run.
+    pub trace_parent: Option<String>,
+    // Whether the run is a regular run that just starts early.
+    pub triggered: bool,
+}
+
+impl RunRequest {
+    // The request kept in the flat fields of the job documents, where a run is requested if
+    // it is a dry run, has an as-of time or was triggered.
+    pub(crate) fn from_fields(
+        dry_run: bool,
+        as_of: Option<DateTime<Utc>>,
+        trace_parent: Option<String>,
+        triggered: bool,
+    ) -> Option<RunRequest> {
+        (dry_run || as_of.is_some() || triggered).then_some(RunRequest {
+            dry_run,
+            as_of,
+            trace_parent,
+            triggered,
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Approval {
+    pub run_id: RunId,
+    pub approved: bool,
+}
+
+// Events of the last run, written in the same write as the state the run returned, so that
+// they are persisted if and only if the state is.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Outbox {
+    pub run_id: RunId,
+    pub events: Vec<RunEvent>,
 }
 
 impl JobData {
+    // Copy of the job's settings and state under another name, unlocked and without the runs
+    // requested or pending approval.
+    pub(crate) fn copied_as(&self, name: JobName) -> JobData {
+        JobData {
+            name,
+            owner: String::default(),
+            expires: DateTime::default(),
+            approval: None,
+            requested: None,
+            last_heartbeat: None,
+            archived: false,
+            pin: None,
+            ..self.clone()
+        }
+    }
     pub(crate) fn due(&self, now: DateTime<Utc>) -> bool {
-        self.enabled && self.schedule.due(&self.last_run, now)
+        self.due_within(now, Duration::ZERO)
+    }
+    pub(crate) fn due_within(&self, now: DateTime<Utc>, tolerance: Duration) -> bool {
+        self.enabled && !self.archived && self.schedule.due_within(&self.last_run, now, tolerance)
     }
+    pub(crate) fn pinned_to(&self, now: DateTime<Utc>) -> Option<&str> {
+        self.pin
+            .as_ref()
+            .filter(|pin| pin.until > now)
+            .map(|pin| pin.owner.as_str())
+    }
+    pub(crate) fn locked_by(&self, now: DateTime<Utc>) -> Option<&str> {
+        if self.owner.is_empty() || self.expires <= now {
+            None
+        } else {
+            Some(self.owner.as_str())
+        }
+    }
+}
+
+// Whether a run of the given duration outlasted the refresh cadence of the lock, half its TTL,
+// and so depended on a refresh succeeding in time to keep the lock.
+pub(crate) fn lock_ttl_tight(lock_ttl: Duration, run_duration: Duration) -> bool {
+    run_duration >= lock_ttl / 2
 }
 
 impl From<JobConfig> for JobData {
     fn from(value: JobConfig) -> Self {
+        let next_run = value.schedule.next_after(&DateTime::default());
         Self {
             name: value.name,
             check_interval: value.check_interval,
             lock_ttl: value.lock_ttl,
             state: Vec::default(),
+            state_version: 0,
             schedule: value.schedule,
             enabled: value.enabled,
             last_run: DateTime::default(),
+            next_run,
+            owner: String::default(),
+            expires: DateTime::default(),
+            metadata: value.metadata,
+            approval: None,
+            requested: None,
+            last_heartbeat: None,
+            archived: false,
+            pin: None,
         }
     }
 }
@@ -1,31 +1,93 @@
+// Without a storage backend the repo plumbing is compiled but never instantiated.
+#![cfg_attr(not(any(feature = "mongodb", feature = "pickledb")), allow(dead_code))]
+
 #[cfg(all(feature = "pickledb", feature = "mongodb"))]
 compile_error!("feature \"pickledb\" and feature \"mongodb\" cannot be enabled at the same time");
 
+#[cfg(feature = "amqp")]
+mod amqp;
+mod backoff;
+mod backup;
+mod clock;
+mod context;
 mod error;
+mod event;
 mod executor;
+mod filter;
+mod gate;
+mod handle;
+mod history;
+mod hooks;
+mod instance;
 mod job;
+mod logging;
+mod maintenance;
 mod manager;
+mod metrics;
+mod preflight;
+mod quota;
+#[cfg(feature = "redis-streams")]
+mod redis_stream;
+mod registry;
 mod repos;
 pub mod schedule;
+mod status;
+mod supervisor;
 
 use async_trait::async_trait;
+use log::LevelFilter;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fmt::{Debug, Display, Formatter};
 use std::time::Duration;
 
-pub use manager::JobManager;
+#[cfg(feature = "amqp")]
+pub use amqp::AmqpTrigger;
+pub use backoff::{Backoff, Jitter};
+pub use backup::{JobSnapshot, Snapshot};
+pub use clock::OnClockSkew;
+pub use context::{JobContext, RunLogger, DEFAULT_MAX_LOG_SIZE};
+pub use error::{Error, ErrorKind};
+pub use event::JobEvent;
+pub use executor::run_single_job;
+#[cfg(feature = "test-util")]
+pub use executor::ExecutorHarness;
+pub use filter::JobFilter;
+pub use gate::GateKeeper;
+pub use handle::JobManagerHandle;
+pub use history::{RunEvent, RunOutcome, RunRecord, SkipReason};
+pub use instance::OnDuplicateInstance;
+pub use logging::{LogFormat, TRANSITION_TARGET};
+pub use maintenance::PRUNE_HISTORY_JOB;
+pub use manager::{JobManager, PurgeReport};
+pub use metrics::{MetricKind, MetricSample, MetricsSnapshot};
+pub use preflight::{PreflightCheck, PreflightReport};
+#[cfg(feature = "redis-streams")]
+pub use redis_stream::RedisStreamTrigger;
+pub use registry::{JobDefinition, JobRegistry, JobTemplate, TEMPLATE_LABEL};
+pub use repos::cache::CachedRepo;
+#[cfg(feature = "chaos")]
+pub use repos::chaos::ChaosRepo;
+#[cfg(feature = "test-util")]
+pub use repos::conformance;
 #[cfg(feature = "mongodb")]
-pub use repos::mongo::MongoRepo;
+pub use repos::mongo::{DocumentFormat, MongoRepo};
 #[cfg(feature = "pickledb")]
 pub use repos::pickledb::PickleDbRepo;
+#[cfg(feature = "redlock")]
+pub use repos::redlock::RedlockRepo;
+pub use repos::RepoCapabilities;
 use schedule::Schedule;
+pub use status::{ClusterOverview, JobStatus};
+
+const DEFAULT_CANCEL_DEADLINE: Duration = Duration::from_secs(30);
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct JobName(pub String);
 
 impl JobName {
     pub fn as_str(&self) -> &str {
-        &self.0.as_str()
+        self.0.as_str()
     }
 }
 
@@ -35,6 +97,64 @@ impl AsRef<str> for crate::JobName {
     }
 }
 
+/// Identifies a single run of a job, e.g. to correlate its events and log records.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RunId(pub String);
+
+impl RunId {
+    pub(crate) fn generate() -> Self {
+        RunId(format!("{:016x}", rand::random::<u64>()))
+    }
+    pub fn as_str(&self) -> &str {
+        self.0.as_str()
+    }
+}
+
+/// Identifies an instance of the application, e.g. as the owner of the locks it holds. Must be
+/// unique across all instances sharing a repo.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct InstanceId(pub String);
+
+impl InstanceId {
+    /// Generate an id of the form `hostname-pid-random`, unique across hosts and restarts.
+    /// The hostname is taken from `HOSTNAME` or `/etc/hostname`, falling back to `unknown`.
+    pub fn generate() -> Self {
+        let hostname = std::env::var("HOSTNAME")
+            .ok()
+            .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+            .map(|name| name.trim().to_owned())
+            .filter(|name| !name.is_empty())
+            .unwrap_or_else(|| "unknown".to_owned());
+        InstanceId(format!(
+            "{}-{}-{:08x}",
+            hostname,
+            std::process::id(),
+            rand::random::<u32>()
+        ))
+    }
+    pub fn as_str(&self) -> &str {
+        self.0.as_str()
+    }
+}
+
+impl From<String> for InstanceId {
+    fn from(value: String) -> Self {
+        InstanceId(value)
+    }
+}
+
+impl From<&str> for InstanceId {
+    fn from(value: &str) -> Self {
+        InstanceId(value.to_owned())
+    }
+}
+
+impl Display for InstanceId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
 #[derive(Clone)]
 pub struct JobConfig {
     pub name: JobName,
@@ -42,6 +162,69 @@ pub struct JobConfig {
     pub lock_ttl: Duration,
     pub schedule: Schedule,
     pub enabled: bool,
+    pub max_log_size: usize,
+    pub preferred_zone: Option<String>,
+    pub zone_grace: Duration,
+    pub group: Option<String>,
+    pub priority: u8,
+    pub record_skips: bool,
+    pub on_deleted: OnDeleted,
+    pub metadata: JobMetadata,
+    pub requires_approval: bool,
+    pub cancel_deadline: Duration,
+    pub due_tolerance: Duration,
+    pub max_state_size: Option<usize>,
+    pub on_oversized_state: OnOversizedState,
+    pub stuck_factor: Option<f64>,
+    pub on_stuck_run: OnStuckRun,
+    pub log_level: Option<LevelFilter>,
+    pub fast_path: bool,
+    pub pinned: bool,
+}
+
+/// Human-readable context of a job for operational tooling. It is written to the repo when
+/// the job's document is created and returned with the job's status.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct JobMetadata {
+    pub description: Option<String>,
+    /// Team responsible for the job.
+    pub owner_team: Option<String>,
+    pub labels: BTreeMap<String, String>,
+}
+
+/// What an executor does when the job's document disappears from the repo, e.g. because an
+/// operator deleted it.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum OnDeleted {
+    /// Recreate the document from the registered config, starting with empty state.
+    #[default]
+    Recreate,
+    /// Stop the executor after emitting [`JobEvent::DeletedExternally`].
+    Stop,
+}
+
+/// What a run does with new state larger than [`JobConfig::with_max_state_size`], instead of
+/// running into the document size limit of the store.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum OnOversizedState {
+    /// Fail the run with [`Error::StateTooLarge`], keeping the previous state.
+    #[default]
+    Fail,
+    /// Pass the state to [`Job::shrink_state`], failing the run like `Fail` when the shrunk
+    /// state is still too large.
+    Shrink,
+}
+
+/// What an executor does with a run of its job that is stuck, see
+/// [`JobConfig::with_stuck_run_detection`].
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum OnStuckRun {
+    /// Only emit [`JobEvent::RunStuck`].
+    #[default]
+    Warn,
+    /// Also cancel the run as if the job was stopped, aborting it after the cancel deadline,
+    /// and keep running the job on its schedule.
+    Cancel,
 }
 
 impl JobConfig {
@@ -52,6 +235,24 @@ impl JobConfig {
             check_interval: Duration::from_secs(60),
             lock_ttl: Duration::from_secs(20),
             enabled: true,
+            max_log_size: DEFAULT_MAX_LOG_SIZE,
+            preferred_zone: None,
+            zone_grace: Duration::ZERO,
+            group: None,
+            priority: 0,
+            record_skips: false,
+            on_deleted: OnDeleted::default(),
+            metadata: JobMetadata::default(),
+            requires_approval: false,
+            cancel_deadline: DEFAULT_CANCEL_DEADLINE,
+            due_tolerance: Duration::ZERO,
+            max_state_size: None,
+            on_oversized_state: OnOversizedState::default(),
+            stuck_factor: None,
+            on_stuck_run: OnStuckRun::default(),
+            log_level: None,
+            fast_path: false,
+            pinned: false,
         }
     }
     pub fn with_check_interval(mut self, interval: Duration) -> Self {
@@ -62,6 +263,121 @@ impl JobConfig {
         self.lock_ttl = ttl;
         self
     }
+    /// Cap the log output of a run that is kept in the run history.
+    pub fn with_max_log_size(mut self, bytes: usize) -> Self {
+        self.max_log_size = bytes;
+        self
+    }
+    /// Assign the job to a group, e.g. a tenant, whose concurrency can be limited with
+    /// [`JobManager::with_group_quota`].
+    pub fn with_group(mut self, group: impl Into<String>) -> Self {
+        self.group = Some(group.into());
+        self
+    }
+    /// Log the executor of this job at most at `level`, e.g. [`LevelFilter::Warn`] for a noisy
+    /// job running every minute or [`LevelFilter::Trace`] for one being investigated,
+    /// instead of the level set with [`JobManager::with_log_level`]. The filter of the logger
+    /// still applies, so a verbose job needs a logger letting its records through. Output of
+    /// the job itself through [`RunLogger`] is not affected.
+    pub fn with_log_level(mut self, level: LevelFilter) -> Self {
+        self.log_level = Some(level);
+        self
+    }
+    /// Jobs of higher priority are admitted first when many jobs of a group with an exhausted
+    /// quota are due. Defaults to 0.
+    pub fn with_priority(mut self, priority: u8) -> Self {
+        self.priority = priority;
+        self
+    }
+    /// Also record due times this instance skipped in the run history, not only as
+    /// [`JobEvent::RunSkipped`]. Every instance that skips a due time records it.
+    pub fn with_record_skips(mut self, record: bool) -> Self {
+        self.record_skips = record;
+        self
+    }
+    pub fn with_description(mut self, description: impl Into<String>) -> Self {
+        self.metadata.description = Some(description.into());
+        self
+    }
+    pub fn with_owner_team(mut self, team: impl Into<String>) -> Self {
+        self.metadata.owner_team = Some(team.into());
+        self
+    }
+    /// Attach a key-value label, replacing an earlier label with the same key.
+    pub fn with_label(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.metadata.labels.insert(key.into(), value.into());
+        self
+    }
+    /// Hold every due run until it is approved with [`JobManager::approve`]. The run is
+    /// announced with [`JobEvent::ApprovalRequested`] and shown in [`JobStatus::pending_approval`].
+    pub fn with_requires_approval(mut self, requires_approval: bool) -> Self {
+        self.requires_approval = requires_approval;
+        self
+    }
+    /// How long a canceled run gets to return after [`JobContext::canceled`] fired. A job
+    /// that is still running then is aborted, its lock released and the run recorded as
+    /// [`RunOutcome::Aborted`]. Defaults to 30s.
+    pub fn with_cancel_deadline(mut self, deadline: Duration) -> Self {
+        self.cancel_deadline = deadline;
+        self
+    }
+    /// Treat the job as due up to `tolerance` before the schedule fires, and ignore times the
+    /// schedule fires within `tolerance` after the last run, see [`schedule::Schedule::due_within`].
+    /// Use a fraction of a second to absorb clock skew between the instances and the store.
+    /// Keep it well below the interval of the schedule. Defaults to zero.
+    pub fn with_due_tolerance(mut self, tolerance: Duration) -> Self {
+        self.due_tolerance = tolerance;
+        self
+    }
+    /// Wake up when the schedule fires next if that is sooner than the next check, instead of
+    /// checking only every check interval, so that a schedule firing every few seconds runs at
+    /// its own cadence. The instance that ran the job keeps the lock, refreshing it, for as
+    /// long as the schedule fires again within the check interval, so consecutive runs stay on
+    /// that instance without taking the lock anew. The lock is released once the job is no
+    /// longer due soon, e.g. when it is disabled or its schedule slows down, or the instance
+    /// drains.
+    pub fn with_fast_path(mut self) -> Self {
+        self.fast_path = true;
+        self
+    }
+    /// Pin the job to the first instance that checks it, so that it runs there for as long as
+    /// that instance stays healthy, e.g. a job with an expensive local warm-up like a loaded
+    /// model. The pin is renewed at every check and expires after three check intervals
+    /// without one, after which another instance takes the job over. A draining instance
+    /// releases its pins. Shown in [`JobStatus::pinned_to`].
+    pub fn with_pinning(mut self) -> Self {
+        self.pinned = true;
+        self
+    }
+    /// Cap the size of the state a run saves, see [`OnOversizedState`]. Unlimited by default.
+    pub fn with_max_state_size(mut self, bytes: usize, on_oversized: OnOversizedState) -> Self {
+        self.max_state_size = Some(bytes);
+        self.on_oversized_state = on_oversized;
+        self
+    }
+    /// Report a run as stuck with [`JobEvent::RunStuck`] when it made no progress, i.e. neither
+    /// started nor sent a [`JobContext::heartbeat`], for longer than `factor` times the 95th
+    /// percentile of the durations of the job's recent successful runs, e.g. 3. Detection
+    /// starts once the job has a few successful runs in its history.
+    pub fn with_stuck_run_detection(mut self, factor: f64, on_stuck: OnStuckRun) -> Self {
+        self.stuck_factor = Some(factor);
+        self.on_stuck_run = on_stuck;
+        self
+    }
+    /// Define what happens when the job's document is deleted from the repo while the job is
+    /// running on this instance.
+    pub fn with_on_deleted(mut self, on_deleted: OnDeleted) -> Self {
+        self.on_deleted = on_deleted;
+        self
+    }
+    /// Prefer running the job on instances in the given zone. Instances in other zones wait
+    /// for `grace` after the job became due before contending for the lock, so they only take
+    /// over when no instance of the preferred zone ran it.
+    pub fn with_preferred_zone(mut self, zone: impl Into<String>, grace: Duration) -> Self {
+        self.preferred_zone = Some(zone.into());
+        self.zone_grace = grace;
+        self
+    }
 }
 
 pub struct JobError(String);
@@ -103,7 +419,60 @@ impl Display for JobError {
 }
 impl std::error::Error for JobError {}
 
+/// What a run returns: the job's new state and, optionally, a result of the run such as a
+/// summary report, which is kept in the run record instead of the state.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct JobOutput {
+    pub state: Vec<u8>,
+    pub result: Option<Vec<u8>>,
+}
+
+impl JobOutput {
+    pub fn new(state: Vec<u8>) -> Self {
+        JobOutput {
+            state,
+            result: None,
+        }
+    }
+    pub fn with_result(mut self, result: Vec<u8>) -> Self {
+        self.result = Some(result);
+        self
+    }
+}
+
+impl From<Vec<u8>> for JobOutput {
+    fn from(state: Vec<u8>) -> Self {
+        JobOutput::new(state)
+    }
+}
+
 #[async_trait]
 pub trait Job {
-    async fn call(&mut self, state: Vec<u8>) -> Result<Vec<u8>, JobError>;
+    /// Run the job on its current state. Return the new state with `Ok(state.into())`, or a
+    /// [`JobOutput`] that carries a result of the run as well.
+    async fn call(&mut self, ctx: &JobContext, state: Vec<u8>) -> Result<JobOutput, JobError>;
+    /// Version of the state format the job reads and writes. Bump it when a deploy changes the
+    /// format so that state persisted by older code is passed through `migrate_state` first.
+    fn state_version(&self) -> u32 {
+        0
+    }
+    /// Called before `call` when the persisted state was written with a different
+    /// `state_version`. The default discards the old state so that `call` starts afresh.
+    async fn migrate_state(
+        &mut self,
+        _from_version: u32,
+        _state: Vec<u8>,
+    ) -> Result<Vec<u8>, JobError> {
+        Ok(Vec::new())
+    }
+    /// Called with new state larger than `max_size` when the job is configured with
+    /// [`OnOversizedState::Shrink`], e.g. to drop the oldest entries of a cache. The default
+    /// returns the state unchanged, failing the run.
+    async fn shrink_state(
+        &mut self,
+        state: Vec<u8>,
+        _max_size: usize,
+    ) -> Result<Vec<u8>, JobError> {
+        Ok(state)
+    }
 }
@@ -0,0 +1,65 @@
+use crate::{JobName, RunId};
+use log::{Level, LevelFilter};
+use serde_json::json;
+use std::future::Future;
+use std::time::Duration;
+
+tokio::task_local! {
+    // Most verbose level the executor running on the current task logs at.
+    static JOB_LOG_LEVEL: LevelFilter;
+}
+
+// Run the executor of a job logging at most at `level`, see `JobConfig::with_log_level`.
+pub(crate) async fn with_job_log_level<F: Future>(level: LevelFilter, executor: F) -> F::Output {
+    JOB_LOG_LEVEL.scope(level, executor).await
+}
+
+// Whether a record of the level passes the log level of the job running on the current task.
+// Tasks the executor spawns log unfiltered.
+pub(crate) fn job_log_enabled(level: Level) -> bool {
+    JOB_LOG_LEVEL.try_with(|max| level <= *max).unwrap_or(true)
+}
+
+// Like `log::log!`, skipping records above the log level of the job running on the current
+// task.
+macro_rules! job_log {
+    ($level:expr, $($arg:tt)+) => {
+        if $crate::logging::job_log_enabled($level) {
+            log::log!($level, $($arg)+)
+        }
+    };
+}
+pub(crate) use job_log;
+
+/// Target of the structured records logged in [`LogFormat::Json`] mode.
+pub const TRANSITION_TARGET: &str = "ply_jobs::transitions";
+
+/// Format of the records the executors log for each state transition.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum LogFormat {
+    /// Human-readable trace lines.
+    #[default]
+    Text,
+    /// One JSON object per transition, logged at trace level to [`TRANSITION_TARGET`] with
+    /// the fields `job`, `run_id`, `from`, `to` and `duration_ms` (time spent in `from`).
+    Json,
+}
+
+pub(crate) fn log_transition(
+    name: &JobName,
+    run_id: Option<&RunId>,
+    from: &str,
+    to: &str,
+    duration: Duration,
+) {
+    let record = json!({
+        "job": name.as_str(),
+        "run_id": run_id.map(RunId::as_str),
+        "from": from,
+        "to": to,
+        "duration_ms": duration.as_millis() as u64,
+    });
+    if job_log_enabled(Level::Trace) {
+        log::trace!(target: TRANSITION_TARGET, "{}", record);
+    }
+}
@@ -0,0 +1,36 @@
+use crate::repos::Repo;
+use crate::{Job, JobContext, JobError, JobOutput};
+use async_trait::async_trait;
+use chrono::Utc;
+use std::time::Duration;
+
+/// Name under which the history pruning job is registered.
+pub const PRUNE_HISTORY_JOB: &str = "ply_jobs::prune-history";
+
+/// Internal job removing run records older than the retention period. Like any other job it
+/// only runs on the instance holding its lock, so one instance prunes on behalf of the cluster.
+pub(crate) struct PruneHistory<R> {
+    repo: R,
+    retention: Duration,
+}
+
+impl<R> PruneHistory<R> {
+    pub(crate) fn new(repo: R, retention: Duration) -> Self {
+        PruneHistory { repo, retention }
+    }
+}
+
+#[async_trait]
+impl<R: Repo + Send> Job for PruneHistory<R> {
+    async fn call(&mut self, ctx: &JobContext, state: Vec<u8>) -> Result<JobOutput, JobError> {
+        let retention = chrono::Duration::from_std(self.retention).map_err(JobError::any)?;
+        let pruned = self
+            .repo
+            .prune_runs(Utc::now() - retention)
+            .await
+            .map_err(JobError::any)?;
+        ctx.logger()
+            .info(format!("pruned {} run records from the history", pruned));
+        Ok(state.into())
+    }
+}
@@ -1,29 +1,246 @@
-use log::{info, trace, warn};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use futures::{FutureExt, Stream};
+use log::{info, warn, LevelFilter};
 use rand::Rng;
+use std::collections::HashSet;
 use std::convert::Infallible;
+use std::future::Future;
+use std::ops::Range;
+use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::oneshot;
 use tokio::sync::oneshot::Sender;
+use tokio::sync::{oneshot, watch, Semaphore};
+use tokio::task::JoinHandle;
 
+use crate::clock::{ClockSkew, OnClockSkew};
 use crate::error::Error;
-use crate::repos::Repo;
-use crate::{executor, Job, JobConfig, JobName};
+use crate::event::Events;
+use crate::executor::Settings;
+use crate::handle::JobManagerHandle;
+use crate::instance::{self, OnDuplicateInstance};
+use crate::job::{JobData, RunRequest};
+use crate::maintenance::{PruneHistory, PRUNE_HISTORY_JOB};
+use crate::preflight::preflight;
+use crate::quota::Quota;
+use crate::registry::{JobDefinition, JobRegistry, JobTemplate};
+use crate::repos::limit::LimitedRepo;
+use crate::repos::{LockStatus, Repo};
+use crate::schedule::Schedule;
+use crate::supervisor::{supervise, SharedJob};
+use crate::{
+    ClusterOverview, GateKeeper, InstanceId, Job, JobConfig, JobError, JobEvent, JobFilter,
+    JobName, JobStatus, LogFormat, MetricsSnapshot, PreflightReport, RepoCapabilities, RunId,
+    RunOutcome, RunRecord, Snapshot,
+};
+
+const DEFAULT_EVENT_CAPACITY: usize = 100;
+const DEFAULT_RESTART_LIMIT: u32 = 5;
+// How far back the run history is inspected to count consecutive failures.
+pub(crate) const STATUS_HISTORY_DEPTH: usize = 100;
+// Clock skew accepted by the preflight checks unless the manager has a maximum of its own.
+const PREFLIGHT_MAX_CLOCK_SKEW: Duration = Duration::from_secs(1);
+// Long enough for copying a job, which releases the lock right after.
+const RENAME_LOCK_TTL: Duration = Duration::from_secs(30);
+// Long enough for deleting a job, which is archived before, so an expired lock starts no run.
+const PURGE_LOCK_TTL: Duration = Duration::from_secs(30);
 
 /// JobManager holds the job + lock repo along with the list of jobs
 pub struct JobManager<J> {
-    instance: String,
+    settings: Settings,
     job_repo: J,
     jobs: Vec<ManagedJob>,
+    drain: Arc<watch::Sender<bool>>,
+    // Slots of the repo operations the executors of this instance may have in flight.
+    repo_operations: Option<Arc<Semaphore>>,
+    // Task measuring the clock skew, spawned by the first start.
+    clock_monitor: Option<JoinHandle<()>>,
+    // What to do about another instance with the same id, if it is to be checked.
+    on_duplicate_instance: Option<OnDuplicateInstance>,
+    // Task announcing this instance, spawned by the first start.
+    instance_monitor: Option<JoinHandle<()>>,
+    // IANA time zone the schedules of the registered jobs are read in unless they have their
+    // own, see `Schedule::in_time_zone`.
+    time_zone: Option<Tz>,
 }
 
 #[allow(private_bounds)]
 impl<J: Repo + Clone + Send + 'static> JobManager<J> {
-    pub fn new(instance: String, job_repo: J) -> Self {
+    /// Create a manager for the instance with the given id, e.g. [`InstanceId::generate`].
+    pub fn new(instance: impl Into<InstanceId>, job_repo: J) -> Self {
+        let (drain, draining) = watch::channel(false);
         JobManager {
-            instance,
+            settings: Settings::new(
+                instance.into().0,
+                Events::new(DEFAULT_EVENT_CAPACITY),
+                DEFAULT_RESTART_LIMIT,
+                draining,
+            ),
             job_repo,
             jobs: Default::default(),
+            drain: Arc::new(drain),
+            repo_operations: None,
+            clock_monitor: None,
+            on_duplicate_instance: None,
+            instance_monitor: None,
+            time_zone: None,
+        }
+    }
+    /// Set how many events are buffered per subscriber of [`JobManager::events`]. Subscribers
+    /// falling further behind than this skip the oldest events. Set this before subscribing.
+    pub fn with_event_capacity(mut self, capacity: usize) -> Self {
+        self.settings.events = Events::new(capacity);
+        self
+    }
+    /// Set the format of the records the executors log for their state transitions. Use
+    /// [`LogFormat::Json`] to feed the executor behavior into a log indexing pipeline.
+    pub fn with_log_format(mut self, format: LogFormat) -> Self {
+        self.settings.log_format = format;
+        self
+    }
+    /// Log the executors of the jobs at most at `level` unless a job has a level of its own,
+    /// see [`JobConfig::with_log_level`]. Defaults to [`LevelFilter::Trace`], leaving the
+    /// filtering to the logger.
+    pub fn with_log_level(mut self, level: LevelFilter) -> Self {
+        self.settings.log_level = level;
+        self
+    }
+    /// Tag this instance with a zone label, see [`JobConfig::with_preferred_zone`].
+    pub fn with_zone(mut self, zone: impl Into<String>) -> Self {
+        self.settings.zone = Some(zone.into());
+        self
+    }
+    /// Read the schedules of the jobs registered from then on in the given time zone, e.g. the
+    /// business time zone of the organization, unless a schedule has a time zone of its own,
+    /// see [`Schedule::in_time_zone`]. The schedule of a job is stored when the job is first
+    /// created, so setting this does not move the runs of jobs that exist in the repo already.
+    pub fn with_time_zone(mut self, tz: Tz) -> Self {
+        self.time_zone = Some(tz);
+        self
+    }
+    /// Declare the capacity of this instance relative to the other instances, e.g. 4 for a
+    /// big node and 1 for a small one. Instances back off from contended locks in inverse
+    /// proportion to their weight, so heavier instances run more of the jobs. Defaults to 1.
+    pub fn with_weight(mut self, weight: u32) -> Self {
+        self.settings.weight = weight.max(1);
+        self
+    }
+    /// Allow at most `max` jobs of the given group to run concurrently on this instance, see
+    /// [`JobConfig::with_group`]. Due jobs exceeding the quota wait for their next check, and
+    /// while they wait no job of the group with a lower [`JobConfig::with_priority`] starts.
+    pub fn with_group_quota(mut self, group: impl Into<String>, max: usize) -> Self {
+        self.settings
+            .quotas
+            .insert(group.into(), Arc::new(Quota::new(max)));
+        self
+    }
+    /// Shed load of the given group once its quota, see [`JobManager::with_group_quota`], has
+    /// had no free slot for longer than `after`: due runs of jobs with a
+    /// [`JobConfig::with_priority`] below `below_priority` are then skipped as
+    /// [`crate::SkipReason::Shed`] instead of waiting for a slot, so the slots freed go to the
+    /// critical jobs. The skipped jobs run once a slot is free again. Has no effect on a group
+    /// without a quota.
+    pub fn with_load_shedding(
+        mut self,
+        group: impl Into<String>,
+        after: Duration,
+        below_priority: u8,
+    ) -> Self {
+        let group = group.into();
+        match self.settings.quotas.get_mut(&group).and_then(Arc::get_mut) {
+            Some(quota) => quota.shed(after, below_priority),
+            None => warn!("no quota for group {:?} to shed load of", group),
         }
+        self
+    }
+    /// Set how often the executor of a job is restarted after its task panicked or was
+    /// aborted, with a growing delay between restarts. Once the limit is used up the job does
+    /// not run on this instance until the process restarts. Defaults to 5.
+    pub fn with_restart_limit(mut self, limit: u32) -> Self {
+        self.settings.restart_limit = limit;
+        self
+    }
+    /// Allow the executors of this instance at most `max` concurrent operations on the repo,
+    /// independent of how many jobs run. Executors wait for a free slot instead, so that many
+    /// jobs becoming due at once do not exhaust the connection pool of a client shared with
+    /// the application. Lock refreshes are not limited. Unlimited by default.
+    pub fn with_max_repo_operations(mut self, max: usize) -> Self {
+        self.repo_operations = Some(Arc::new(Semaphore::new(max.max(1))));
+        self
+    }
+    /// Call `hook` before every run of every job on this instance, e.g. to check feature flags
+    /// or a maintenance mode. If a hook fails the run is skipped and the job stays due, so the
+    /// run is attempted again at the next check.
+    pub fn on_before_run<F, Fut>(mut self, hook: F) -> Self
+    where
+        F: Fn(JobName, RunId) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), JobError>> + Send + 'static,
+    {
+        self.settings
+            .hooks
+            .before_run
+            .push(Arc::new(move |name, run_id| hook(name, run_id).boxed()));
+        self
+    }
+    /// Consult the gate keeper right before every run of every job on this instance, see
+    /// [`GateKeeper`]. Runs it keeps from starting are skipped as [`SkipReason::Gated`].
+    ///
+    /// [`SkipReason::Gated`]: crate::SkipReason::Gated
+    pub fn with_gate_keeper(mut self, gate_keeper: impl GateKeeper + 'static) -> Self {
+        self.settings.gate_keeper = Some(Arc::new(gate_keeper));
+        self
+    }
+    /// Compare the clock of this instance with the clock of the store when the jobs start and
+    /// every minute after, see [`JobManager::clock_skew`]. Lock expiry and due checks assume
+    /// synchronized clocks, so a skew above `max` is logged, and with [`OnClockSkew::Refuse`]
+    /// due runs are skipped until the clocks agree again. Stores without a clock of their own,
+    /// like PickleDb, are never skewed.
+    pub fn with_max_clock_skew(mut self, max: Duration, on_skew: OnClockSkew) -> Self {
+        self.settings.clock = Some(Arc::new(ClockSkew::new(max, on_skew)));
+        self
+    }
+    /// Announce this instance in the repo when the jobs start and every 10s after, and check
+    /// that no other live instance announces the same id, e.g. because a deploy reused a
+    /// fixed id. On finding one the instance logs an error, and with
+    /// [`OnDuplicateInstance::Refuse`] or [`OnDuplicateInstance::Fence`] one of the two
+    /// drains. Another instance counts as live once it announced itself again after this one
+    /// first saw it, so a process restarting right after a crash does not mistake its own
+    /// previous incarnation for a duplicate. Instances sharing an id should use the same
+    /// setting.
+    pub fn with_duplicate_instance_check(mut self, on_duplicate: OnDuplicateInstance) -> Self {
+        self.on_duplicate_instance = Some(on_duplicate);
+        self
+    }
+    /// Call `hook` after every run of every job on this instance with the outcome of the run.
+    /// Failures of the hook are logged only.
+    pub fn on_after_run<F, Fut>(mut self, hook: F) -> Self
+    where
+        F: Fn(JobName, RunId, RunOutcome) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), JobError>> + Send + 'static,
+    {
+        self.settings
+            .hooks
+            .after_run
+            .push(Arc::new(move |name, run_id, outcome| {
+                hook(name, run_id, outcome).boxed()
+            }));
+        self
+    }
+    /// events returns a stream of the lifecycle events of all jobs of this manager, starting
+    /// with the events emitted after the call.
+    /// ```rust,ignore
+    ///     let failures = manager
+    ///         .events()
+    ///         .filter(|e| future::ready(matches!(e, JobEvent::RunFailed { .. })));
+    /// ```
+    pub fn events(&self) -> impl Stream<Item = JobEvent> + Send + 'static {
+        self.settings.events.stream()
+    }
+    /// handle returns a cloneable handle to trigger, pause and observe the jobs without
+    /// access to the manager, see [`JobManagerHandle`]. Take it after setting
+    /// [`JobManager::with_event_capacity`], which replaces the event channel.
+    pub fn handle(&self) -> JobManagerHandle<J> {
+        JobManagerHandle::new(self.job_repo.clone(), self.settings.events.clone())
     }
     /// Add a new
     /// register will add the job to the vector of jobs in JobManager
@@ -37,70 +254,601 @@ impl<J: Repo + Clone + Send + 'static> JobManager<J> {
     ///        },
     ///     );
     pub fn register(&mut self, data: JobConfig, action: impl Job + Send + 'static) {
-        self.jobs.push(ManagedJob::new(data, action)); // TODO: add validation during registration??
+        self.add(data, Box::new(action)); // TODO: add validation during registration??
+    }
+
+    fn add(&mut self, mut config: JobConfig, action: Box<dyn Job + Send>) {
+        if let Some(tz) = self
+            .time_zone
+            .filter(|_| config.schedule.time_zone().is_none())
+        {
+            config.schedule = config.schedule.in_time_zone(tz);
+        }
+        self.jobs.push(ManagedJob::new(config, action));
+    }
+
+    /// Register the job described by the definition, constructed by the registry's factory of
+    /// the definition's kind.
+    pub fn register_definition(
+        &mut self,
+        registry: &JobRegistry,
+        definition: JobDefinition,
+    ) -> Result<(), Error> {
+        let (config, action) = registry.instantiate(&definition)?;
+        self.add(config, action);
+        Ok(())
+    }
+
+    /// Register an instance of the template for each of the given instance names and
+    /// parameters, or none of them if any is invalid, see [`JobManager::register_all`].
+    pub fn register_template<'a>(
+        &mut self,
+        registry: &JobRegistry,
+        template: &JobTemplate,
+        instances: impl IntoIterator<Item = (&'a str, serde_json::Value)>,
+    ) -> Result<(), Error> {
+        let jobs = instances
+            .into_iter()
+            .map(|(instance, params)| registry.instantiate(&template.instance(instance, params)))
+            .collect::<Result<Vec<_>, Error>>()?;
+        self.register_all(jobs)
+    }
+
+    /// Register all the jobs, e.g. generated from configuration, or none of them if any is
+    /// invalid. The error lists every problem found: an empty name, a name that is registered
+    /// already or appears twice, and a zero check interval or lock TTL.
+    pub fn register_all(
+        &mut self,
+        jobs: impl IntoIterator<Item = (JobConfig, Box<dyn Job + Send>)>,
+    ) -> Result<(), Error> {
+        let jobs: Vec<_> = jobs.into_iter().collect();
+        let mut names: HashSet<&str> = self.jobs.iter().map(|j| j.config.name.as_str()).collect();
+        let mut invalid = Vec::new();
+        for (config, _) in &jobs {
+            let mut problem = |problem: &str| invalid.push((config.name.clone(), problem.into()));
+            if config.name.as_str().is_empty() {
+                problem("has an empty name");
+            }
+            if !names.insert(config.name.as_str()) {
+                problem("is registered more than once");
+            }
+            if config.check_interval.is_zero() {
+                problem("has a zero check interval");
+            }
+            if config.lock_ttl.is_zero() {
+                problem("has a zero lock TTL");
+            }
+        }
+        if !invalid.is_empty() {
+            return Err(Error::InvalidJobs(invalid));
+        }
+        for (config, action) in jobs {
+            self.add(config, action);
+        }
+        Ok(())
+    }
+
+    /// Register the built-in maintenance job that prunes run records older than `retention`
+    /// from the history on the given schedule. Only the instance holding the job's lock prunes.
+    pub fn register_history_pruning(&mut self, retention: Duration, schedule: Schedule) {
+        let config = JobConfig::new(PRUNE_HISTORY_JOB, schedule);
+        let job = PruneHistory::new(self.job_repo.clone(), retention);
+        self.register(config, job);
+    }
+
+    /// simulate computes every run the registered jobs would fire within the range, ordered
+    /// by time, without executing anything. Jobs already in the repo fire on the schedule
+    /// kept there, which may differ from the registered one, e.g. a randomized schedule
+    /// written by another instance, unless they are paused or archived. The others fire on
+    /// their registered schedule unless disabled. Actual runs start at the first due check
+    /// after these times.
+    pub async fn simulate(
+        &self,
+        range: Range<DateTime<Utc>>,
+    ) -> Result<Vec<(JobName, DateTime<Utc>)>, Error> {
+        let mut repo = self.job_repo.clone();
+        let mut runs = Vec::new();
+        for j in &self.jobs {
+            let (fires, schedule) = match repo.get(j.config.name.clone()).await? {
+                Some(jdata) => (jdata.enabled && !jdata.archived, jdata.schedule),
+                None => (j.config.enabled, j.config.schedule.clone()),
+            };
+            if fires {
+                runs.extend(
+                    schedule
+                        .between(range.clone())
+                        .map(|t| (j.config.name.clone(), t)),
+                );
+            }
+        }
+        runs.sort_by_key(|(_, t)| *t);
+        Ok(runs)
     }
 
     /// start_all will spawn the jobs and run the job for ever until the job is stopped or aborted
-    pub fn start_all(&mut self) -> () {
+    pub fn start_all(&mut self) {
+        if let (Some(clock), None) = (&self.settings.clock, &self.clock_monitor) {
+            let clock = clock.clone();
+            let repo = self.job_repo.clone();
+            let draining = self.settings.draining.clone();
+            self.clock_monitor = Some(tokio::spawn(
+                async move { clock.monitor(repo, draining).await },
+            ));
+        }
+        if let (Some(on_duplicate), None) = (self.on_duplicate_instance, &self.instance_monitor) {
+            self.instance_monitor = Some(tokio::spawn(instance::monitor(
+                self.job_repo.clone(),
+                self.settings.instance.clone(),
+                on_duplicate,
+                self.drain.clone(),
+            )));
+        }
         for job in self.jobs.iter_mut().filter(|jb| jb.registered()) {
             let (tx, rx) = oneshot::channel();
-            let job_repo = self.job_repo.clone();
-            let action = job
-                .action
-                .take()
-                .expect("Registered job must have some action because it cannot be taken.");
+            let job_repo = LimitedRepo::new(self.job_repo.clone(), self.repo_operations.clone());
+            let action = job.action.clone();
             let config = job.config.clone();
 
             job.status = Status::Running(tx);
-            let instance = self.instance.clone();
+            let settings = self.settings.clone();
             let mut rng = rand::thread_rng();
             let delay = Duration::from_millis(rng.gen_range(10..100));
-            tokio::spawn(async move {
-                let name = config.name.clone();
-                match executor::run(instance, config, action, job_repo, rx, delay).await {
-                    Ok(()) => trace!("job {:?} stopped", &name),
-                    Err(e) => warn!("job {:?} stopped with an error: {:?}", &name, e),
-                };
-            });
+            job.handle = Some(tokio::spawn(supervise(
+                settings, config, action, job_repo, rx, delay,
+            )));
+        }
+    }
+    /// clock_skew returns how far the clock of this instance was ahead of the clock of the
+    /// store at the last check, negative if it was behind. It is `None` unless the skew is
+    /// monitored, see [`JobManager::with_max_clock_skew`], and until it has been measured.
+    pub fn clock_skew(&self) -> Option<chrono::Duration> {
+        self.settings.clock.as_ref().and_then(|clock| clock.skew())
+    }
+    /// preflight checks that the repo can be reached, that this instance may create, update and
+    /// delete job documents, that the indexes the repo relies on exist, e.g. those of
+    /// `MongoRepo::create_indexes`, and that the clock of this instance agrees with the clock
+    /// of the store within the maximum of [`JobManager::with_max_clock_skew`], or 1s. Run it
+    /// before [`JobManager::start_all`], e.g. in the health check of a deploy. It writes and
+    /// deletes a disabled probe job named after the instance.
+    /// ```rust,ignore
+    ///     let report = manager.preflight().await;
+    ///     if !report.passed() {
+    ///         return Err(format!("preflight failed:\n{}", report).into());
+    ///     }
+    /// ```
+    pub async fn preflight(&self) -> PreflightReport {
+        let max_clock_skew = self
+            .settings
+            .clock
+            .as_ref()
+            .map_or(PREFLIGHT_MAX_CLOCK_SKEW, |clock| clock.max());
+        preflight(
+            self.job_repo.clone(),
+            &self.settings.instance,
+            max_clock_skew,
+        )
+        .await
+    }
+    /// capabilities probes the guarantees the store of the repo offers in its current
+    /// deployment, e.g. whether it supports transactions, to check the store meets the needs
+    /// of the application instead of silently running with weaker guarantees.
+    pub async fn capabilities(&self) -> Result<RepoCapabilities, Error> {
+        self.job_repo.clone().capabilities().await
+    }
+    /// history returns the most recent runs of the job, newest first, including the log
+    /// output captured during each run.
+    pub async fn history(&self, name: JobName, limit: usize) -> Result<Vec<RunRecord>, Error> {
+        self.job_repo.clone().runs(name, limit).await
+    }
+    /// last_result returns the result of the most recent run among the recent runs of the job
+    /// that returned one, see [`crate::JobOutput`].
+    pub async fn last_result(&self, name: JobName) -> Result<Option<Vec<u8>>, Error> {
+        let runs = self
+            .job_repo
+            .clone()
+            .runs(name, STATUS_HISTORY_DEPTH)
+            .await?;
+        Ok(runs.into_iter().find_map(|run| run.result))
+    }
+    /// status returns the current status of the job, or `None` if the repo has no such job.
+    pub async fn status(&self, name: JobName) -> Result<Option<JobStatus>, Error> {
+        self.handle().status(name).await
+    }
+    /// statuses returns the current status of every job in the repo.
+    pub async fn statuses(&self) -> Result<Vec<JobStatus>, Error> {
+        self.statuses_matching(&JobFilter::default()).await
+    }
+    /// statuses_matching returns the current status of the jobs in the repo that match the
+    /// filter. The repo evaluates as much of the filter as it can itself.
+    pub async fn statuses_matching(&self, filter: &JobFilter) -> Result<Vec<JobStatus>, Error> {
+        self.handle().statuses_matching(filter).await
+    }
+    /// due_statuses returns the current status of at most `limit` jobs that are due now, the
+    /// longest due first. The repo selects the due jobs itself where it can.
+    pub async fn due_statuses(&self, limit: usize) -> Result<Vec<JobStatus>, Error> {
+        let mut repo = self.job_repo.clone();
+        let now = Utc::now();
+        let mut statuses = Vec::new();
+        for jdata in repo.due_jobs(now, limit).await? {
+            let runs = repo.runs(jdata.name.clone(), STATUS_HISTORY_DEPTH).await?;
+            statuses.push(JobStatus::new(&jdata, &runs, now));
         }
-        ()
+        Ok(statuses)
+    }
+    /// cluster_overview reads the jobs of all instances from the repo to show which instance
+    /// runs what, which jobs are overdue and which locks look stale.
+    pub async fn cluster_overview(&self) -> Result<ClusterOverview, Error> {
+        let jobs = self.jobs_with_runs().await?;
+        Ok(ClusterOverview::new(&jobs, Utc::now()))
+    }
+    /// metrics reads the jobs of all instances and their recent runs from the repo as
+    /// metric samples, see [`MetricsSnapshot::to_prometheus`].
+    pub async fn metrics(&self) -> Result<MetricsSnapshot, Error> {
+        let jobs = self.jobs_with_runs().await?;
+        Ok(MetricsSnapshot::new(&jobs, Utc::now()))
+    }
+    /// transition_metrics counts the state transitions the executors of this instance made
+    /// since the manager was created, per job and pair of states, as
+    /// `ply_jobs_transitions_total` and the time spent in the state left as
+    /// `ply_jobs_transition_seconds_total`, both labeled with `job`, `instance`, `from` and
+    /// `to`. A growing count of `try_lock` to `sleeping` mostly means locks held by other
+    /// instances, and of `start` to `initial` failures to read or create the job's data. The
+    /// states are named like the records of [`LogFormat::Json`].
+    pub fn transition_metrics(&self) -> MetricsSnapshot {
+        self.settings.transitions.snapshot(&self.settings.instance)
+    }
+    async fn jobs_with_runs(&self) -> Result<Vec<(JobData, Vec<RunRecord>)>, Error> {
+        let mut repo = self.job_repo.clone();
+        let mut jobs = Vec::new();
+        for jdata in repo.list().await? {
+            if jdata.archived {
+                continue;
+            }
+            let runs = repo.runs(jdata.name.clone(), STATUS_HISTORY_DEPTH).await?;
+            jobs.push((jdata, runs));
+        }
+        Ok(jobs)
+    }
+    /// snapshot copies all jobs and the run history from the repo, e.g. to back them up as
+    /// JSON independent of the storage backend, or to export them as CSV with
+    /// [`Snapshot::write_runs_csv`] and [`Snapshot::write_jobs_csv`], or as Parquet with the
+    /// `parquet` feature.
+    pub async fn snapshot(&self) -> Result<Snapshot, Error> {
+        self.job_repo.clone().snapshot().await
+    }
+    /// restore writes a snapshot taken with [`JobManager::snapshot`] back to the repo. Jobs
+    /// and runs present in the snapshot replace their current counterparts.
+    pub async fn restore(&self, snapshot: Snapshot) -> Result<(), Error> {
+        self.job_repo.clone().restore(snapshot).await
+    }
+    /// purge deletes the job's document including its state and its whole run history from
+    /// the repo, e.g. to honor a data deletion request. As a safeguard `confirmation` must
+    /// repeat the job name, and a job that is running on this instance cannot be purged.
+    ///
+    /// The job is archived first, see [`JobManager::archive`], so that no instance starts
+    /// another run, and deleted only while no instance holds its lock. A job locked by a run
+    /// elsewhere fails with [`Error::JobLocked`] and stays archived, so purge it again once
+    /// the run finished. Purging is not coordinated across the cluster beyond that: an
+    /// instance that still has the job registered with [`crate::OnDeleted::Recreate`] recreates it
+    /// afresh, so remove the job from the registrations of all instances before purging it.
+    pub async fn purge(&self, name: JobName, confirmation: &str) -> Result<PurgeReport, Error> {
+        if name.as_str() != confirmation {
+            return Err(Error::PurgeNotConfirmed(name));
+        }
+        let running = self
+            .jobs
+            .iter()
+            .any(|j| j.config.name == name && !j.registered());
+        if running {
+            return Err(Error::JobRunning(name));
+        }
+        let mut repo = self.job_repo.clone();
+        let lock = match repo.set_archived(name.clone(), true).await {
+            Ok(()) => {
+                let owner = format!("{}#purge", self.settings.instance);
+                match repo.lock(name.clone(), owner, PURGE_LOCK_TTL).await? {
+                    LockStatus::Acquired(_, lock) => Some(lock),
+                    LockStatus::AlreadyLocked => return Err(Error::JobLocked(name)),
+                }
+            }
+            // Only the run history is left.
+            Err(Error::NotFound(_)) => None,
+            Err(e) => return Err(e),
+        };
+        let runs_deleted = repo.delete_runs(name.clone()).await?;
+        let job_deleted = repo.delete(name.clone()).await?;
+        drop(lock);
+        info!("purged job {:?}", name);
+        Ok(PurgeReport {
+            job_deleted,
+            runs_deleted,
+        })
+    }
+    /// archive retires the job without deleting it: the executors of all instances stop
+    /// running it, it is left out of the statuses unless asked for with
+    /// [`JobFilter::with_archived`], and its state and run history are kept. A run in flight
+    /// finishes. [`JobManager::unarchive`] brings the job back.
+    pub async fn archive(&self, name: JobName) -> Result<(), Error> {
+        self.job_repo
+            .clone()
+            .set_archived(name.clone(), true)
+            .await?;
+        info!("archived job {:?}", name);
+        Ok(())
+    }
+    /// unarchive brings an archived job back, so that it runs on its schedule again with the
+    /// state it had when it was archived.
+    pub async fn unarchive(&self, name: JobName) -> Result<(), Error> {
+        self.job_repo
+            .clone()
+            .set_archived(name.clone(), false)
+            .await?;
+        info!("unarchived job {:?}", name);
+        Ok(())
+    }
+    /// pause keeps the executors of all instances from running the job until it is resumed
+    /// with [`JobManager::resume`], like a job registered as disabled. Its state is kept and a
+    /// run in flight finishes.
+    pub async fn pause(&self, name: JobName) -> Result<(), Error> {
+        self.job_repo
+            .clone()
+            .set_enabled(name.clone(), false)
+            .await?;
+        info!("paused job {:?}", name);
+        Ok(())
+    }
+    /// resume lets a paused job run on its schedule again.
+    pub async fn resume(&self, name: JobName) -> Result<(), Error> {
+        self.job_repo
+            .clone()
+            .set_enabled(name.clone(), true)
+            .await?;
+        info!("resumed job {:?}", name);
+        Ok(())
+    }
+    /// rename moves the job's state and settings to a new name, so that a job renamed in code
+    /// continues from its state instead of starting afresh. The job must not be running while
+    /// it is renamed. The old document is kept as an archived tombstone, so that executors
+    /// still running under the old name neither run nor recreate it, see
+    /// [`JobManager::archive`]. Rename before starting the job under the new name, which
+    /// would otherwise create a fresh job that takes the name.
+    ///
+    /// Where the store supports transactions, see [`RepoCapabilities::transactions`], the
+    /// copy is created and the old job archived in one transaction. Otherwise the copy is
+    /// created first and removed again if archiving the old job fails, but a crash in between
+    /// leaves both jobs active, so archive the old job then. The run history is not moved:
+    /// it stays under the old name, where [`JobManager::history`] still finds it, and the
+    /// renamed job starts a history of its own.
+    pub async fn rename(&self, old: JobName, new: JobName) -> Result<(), Error> {
+        let mut repo = self.job_repo.clone();
+        if repo.get(old.clone()).await?.is_none() {
+            return Err(Error::NotFound(old));
+        }
+        if repo.get(new.clone()).await?.is_some() {
+            return Err(Error::JobExists(new));
+        }
+        // Holding the lock keeps the job from starting a run while it is copied.
+        let owner = format!("{}#rename", self.settings.instance);
+        let (jdata, lock) = match repo
+            .lock(old.clone(), owner.clone(), RENAME_LOCK_TTL)
+            .await?
+        {
+            LockStatus::Acquired(jdata, lock) => (jdata, lock),
+            LockStatus::AlreadyLocked => return Err(Error::JobLocked(old)),
+        };
+        let renamed = repo.rename(old.clone(), jdata.copied_as(new.clone())).await;
+        drop(lock);
+        repo.unlock(old.clone(), owner).await?;
+        renamed?;
+        info!("renamed job {:?} to {:?}", old, new);
+        Ok(())
+    }
+    /// clone_job creates a job named `dst` with the schedule, settings and metadata of the
+    /// job `src`, e.g. to start a variant of a template job. With `with_state` the clone
+    /// continues from the state and last run of `src`, otherwise it starts with empty state
+    /// as a job that never ran. Register a job under the new name to run the clone.
+    pub async fn clone_job(
+        &self,
+        src: JobName,
+        dst: JobName,
+        with_state: bool,
+    ) -> Result<(), Error> {
+        let mut repo = self.job_repo.clone();
+        let Some(jdata) = repo.get(src.clone()).await? else {
+            return Err(Error::NotFound(src));
+        };
+        if repo.get(dst.clone()).await?.is_some() {
+            return Err(Error::JobExists(dst));
+        }
+        let mut clone = jdata.copied_as(dst.clone());
+        if !with_state {
+            clone.state = Vec::default();
+            clone.state_version = 0;
+            clone.last_run = DateTime::default();
+            clone.next_run = clone.schedule.next_after(&clone.last_run);
+        }
+        repo.create(clone).await?;
+        info!("cloned job {:?} to {:?}", src, dst);
+        Ok(())
+    }
+    /// approve lets the due run of a job configured with
+    /// [`JobConfig::with_requires_approval`] start. `run_id` has to match the pending run, as
+    /// announced by [`JobEvent::ApprovalRequested`] or shown in [`JobStatus::pending_approval`].
+    pub async fn approve(&self, name: JobName, run_id: RunId) -> Result<(), Error> {
+        let mut repo = self.job_repo.clone();
+        if !repo.approve(name.clone(), run_id.clone()).await? {
+            return Err(Error::NotPendingApproval(name, run_id));
+        }
+        info!("approved run {} of job {:?}", run_id.as_str(), name);
+        Ok(())
+    }
+    /// dry_run requests a run of the job whose returned state is discarded and which does not
+    /// count as the job's last run, e.g. to validate new job logic in production. The job sees
+    /// [`crate::JobContext::is_dry_run`]. The next instance checking the job runs it, even if
+    /// the job is disabled, not due or requires approval.
+    pub async fn dry_run(&self, name: JobName) -> Result<(), Error> {
+        let request = RunRequest {
+            dry_run: true,
+            ..RunRequest::default()
+        };
+        self.job_repo
+            .clone()
+            .request_run(name.clone(), Some(request))
+            .await?;
+        info!("requested dry run of job {:?}", name);
+        Ok(())
+    }
+    /// dry_run_traced requests a dry run like [`JobManager::dry_run`] and keeps the given W3C
+    /// traceparent with the request, e.g. of the span handling the request. The instance
+    /// running the job hands it to the job as [`crate::JobContext::trace_parent`] and stores it
+    /// with the run record, so that the request and the run show up in the same trace.
+    pub async fn dry_run_traced(&self, name: JobName, trace_parent: &str) -> Result<(), Error> {
+        if !valid_trace_parent(trace_parent) {
+            return Err(Error::InvalidTraceParent(trace_parent.to_string()));
+        }
+        let request = RunRequest {
+            dry_run: true,
+            trace_parent: Some(trace_parent.to_string()),
+            ..RunRequest::default()
+        };
+        self.job_repo
+            .clone()
+            .request_run(name.clone(), Some(request))
+            .await?;
+        info!(
+            "requested dry run of job {:?} in trace {}",
+            name, trace_parent
+        );
+        Ok(())
+    }
+    /// backfill requests a run of the job that computes for the given time instead of now, see
+    /// [`crate::JobContext::as_of`], e.g. to produce the daily report of a past date again. Like
+    /// a dry run it runs on the next instance checking the job, even if the job is disabled, not
+    /// due or requires approval, its returned state is discarded and it does not count as the
+    /// job's last run, but the job is not asked to avoid side effects. A request replaces an
+    /// earlier request of the job that has not run yet, so request backfills of several times
+    /// one after the other.
+    pub async fn backfill(&self, name: JobName, as_of: DateTime<Utc>) -> Result<(), Error> {
+        let request = RunRequest {
+            as_of: Some(as_of),
+            ..RunRequest::default()
+        };
+        self.job_repo
+            .clone()
+            .request_run(name.clone(), Some(request))
+            .await?;
+        info!("requested backfill of job {:?} as of {}", name, as_of);
+        Ok(())
+    }
+    /// trigger requests a regular run of the job ahead of its schedule, e.g. for an operator or
+    /// an external event. Unlike a dry run or a backfill, the state the run returns is saved
+    /// and the run counts as the job's last run. It runs on the next instance checking the
+    /// job, even if the job is disabled, not due or requires approval. A request replaces an
+    /// earlier request of the job that has not run yet.
+    pub async fn trigger(&self, name: JobName) -> Result<(), Error> {
+        self.handle().trigger(name).await
+    }
+    /// set_maintenance switches the maintenance mode of all instances sharing the repo. While it
+    /// is on no job starts a run; due runs are reported as skipped and start once it is off.
+    /// Runs in flight are not interrupted.
+    pub async fn set_maintenance(&self, on: bool) -> Result<(), Error> {
+        self.handle().set_maintenance(on).await
+    }
+    /// maintenance returns whether the maintenance mode is on, see
+    /// [`JobManager::set_maintenance`].
+    pub async fn maintenance(&self) -> Result<bool, Error> {
+        self.handle().maintenance().await
+    }
+    /// drain stops all jobs from starting new runs, lets the runs in flight finish and save
+    /// their state, and returns once all executors exited, e.g. during a rolling deploy. Unlike
+    /// a cancel it does not interrupt running jobs.
+    pub async fn drain(&mut self) {
+        info!("draining all jobs");
+        self.drain.send_replace(true);
+        for job in self.jobs.iter_mut() {
+            if let Some(handle) = job.handle.take() {
+                let _ = handle.await;
+            }
+        }
+    }
+    /// stop cancels the job like [`JobManager::stop_by_name`] and returns once its executor
+    /// exited, after the run in flight was canceled, see [`JobConfig::with_cancel_deadline`].
+    /// The job stays registered, so the next [`JobManager::start_all`] starts it again.
+    /// Returns whether the job was running.
+    pub async fn stop(&mut self, name: &JobName) -> Result<bool, Error> {
+        let Some(job) = self.jobs.iter_mut().find(|j| &j.config.name == name) else {
+            return Err(Error::NotFound(name.clone()));
+        };
+        let Status::Running(cancel) = std::mem::replace(&mut job.status, Status::Registered) else {
+            return Ok(false);
+        };
+        info!("stopping job {:?}", name);
+        // The executor may have exited on its own already, which stops the job just as well.
+        let _ = cancel.send(());
+        if let Some(handle) = job.handle.take() {
+            let _ = handle.await;
+        }
+        Ok(true)
     }
     /// stop_by_name will stop the job which is started as part of start_all
     pub async fn stop_by_name(self, name: JobName) -> std::result::Result<(), Infallible> {
-        for job in self.jobs.into_iter().filter(|j| j.config.name == name) {
-            return match job.status {
-                Status::Running(s) => {
-                    info!("received stop signal. Stopping job: {:?}", name.clone());
-                    let _xx = s.send(()).map_err(|()| Error::CancelFailed(name)).unwrap();
-                    Ok(())
-                }
-                _ => Ok(()),
-            };
+        if let Some(job) = self.jobs.into_iter().find(|j| j.config.name == name) {
+            if let Status::Running(s) = job.status {
+                info!("received stop signal. Stopping job: {:?}", name.clone());
+                s.send(()).map_err(|()| Error::CancelFailed(name)).unwrap();
+            }
         }
         Ok(())
     }
 }
 
+// Whether the value is a W3C traceparent of version 00: `00-<trace id>-<parent id>-<flags>`
+// in lowercase hex, with neither id all zeros.
+pub(crate) fn valid_trace_parent(value: &str) -> bool {
+    let hex = |part: &str, len: usize| {
+        part.len() == len && part.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+    };
+    let zero = |part: &str| part.bytes().all(|b| b == b'0');
+    match value.split('-').collect::<Vec<_>>()[..] {
+        ["00", trace_id, parent_id, flags] => {
+            hex(trace_id, 32)
+                && hex(parent_id, 16)
+                && hex(flags, 2)
+                && !zero(trace_id)
+                && !zero(parent_id)
+        }
+        _ => false,
+    }
+}
+
+/// Outcome of [`JobManager::purge`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct PurgeReport {
+    pub job_deleted: bool,
+    pub runs_deleted: u64,
+}
+
 impl ManagedJob {
-    pub fn new(config: JobConfig, action: impl Job + Send + 'static) -> Self {
+    pub fn new(config: JobConfig, action: Box<dyn Job + Send>) -> Self {
         ManagedJob {
             config,
-            action: Some(Box::new(action)),
+            action: SharedJob::new(action),
             status: Status::Registered,
+            handle: None,
         }
     }
     pub fn registered(&self) -> bool {
-        match self.status {
-            Status::Registered => true,
-            _ => false,
-        }
+        matches!(self.status, Status::Registered)
     }
 }
 
 pub(crate) struct ManagedJob {
     pub config: JobConfig,
-    pub action: Option<Box<dyn Job + Send>>,
+    // Shared with the executors, so that the job can be started again after it stopped.
+    pub action: SharedJob,
     pub status: Status,
+    // Task supervising the job's executor once started.
+    pub handle: Option<JoinHandle<()>>,
 }
 
 #[derive(Debug)]
@@ -0,0 +1,223 @@
+//! Metrics of the scheduler derived from the repo, named and labeled the same whichever
+//! instance and backend they are read from, so that one dashboard fits every deployment.
+//! The executors of each instance also count their state transitions in memory.
+use crate::job::JobData;
+use crate::{JobName, JobStatus, RunOutcome, RunRecord};
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Whether a metric is a current value or a running total.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum MetricKind {
+    #[default]
+    Gauge,
+    /// Only grows while the process lives, so that rates can be derived from it.
+    Counter,
+}
+
+impl MetricKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            MetricKind::Gauge => "gauge",
+            MetricKind::Counter => "counter",
+        }
+    }
+}
+
+/// Single value of a metric.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MetricSample {
+    pub name: &'static str,
+    pub kind: MetricKind,
+    pub labels: BTreeMap<&'static str, String>,
+    pub value: f64,
+}
+
+/// Metrics of all jobs in the repo at one point in time, see
+/// [`crate::JobManager::metrics`].
+///
+/// | Metric                                    | Labels                       |
+/// |-------------------------------------------|------------------------------|
+/// | `ply_jobs_job_enabled`                    | `job`                        |
+/// | `ply_jobs_job_due`                        | `job`                        |
+/// | `ply_jobs_job_running`                    | `job`, `instance`            |
+/// | `ply_jobs_job_consecutive_failures`       | `job`                        |
+/// | `ply_jobs_job_last_run_timestamp_seconds` | `job`                        |
+/// | `ply_jobs_last_run_duration_seconds`      | `job`, `instance`, `outcome` |
+/// | `ply_jobs_recent_runs`                    | `job`, `instance`, `outcome` |
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MetricsSnapshot {
+    pub samples: Vec<MetricSample>,
+}
+
+impl MetricsSnapshot {
+    // `jobs` pairs every job with its most recent runs, newest first, which make up the
+    // window of `ply_jobs_recent_runs`.
+    pub(crate) fn new(jobs: &[(JobData, Vec<RunRecord>)], now: DateTime<Utc>) -> Self {
+        let mut samples = Vec::new();
+        let mut push = |name, labels: &[(&'static str, &str)], value: f64| {
+            samples.push(MetricSample {
+                name,
+                kind: MetricKind::Gauge,
+                labels: labels
+                    .iter()
+                    .map(|(key, value)| (*key, (*value).to_owned()))
+                    .collect(),
+                value,
+            })
+        };
+        for (jdata, runs) in jobs {
+            let status = JobStatus::new(jdata, runs, now);
+            let job = status.name.0.as_str();
+            push(
+                "ply_jobs_job_enabled",
+                &[("job", job)],
+                flag(status.enabled),
+            );
+            push("ply_jobs_job_due", &[("job", job)], flag(status.is_due_now));
+            if let Some(instance) = &status.running_on {
+                push(
+                    "ply_jobs_job_running",
+                    &[("job", job), ("instance", instance)],
+                    1.0,
+                );
+            }
+            push(
+                "ply_jobs_job_consecutive_failures",
+                &[("job", job)],
+                status.consecutive_failures as f64,
+            );
+            if let Some(last_run) = status.last_run {
+                push(
+                    "ply_jobs_job_last_run_timestamp_seconds",
+                    &[("job", job)],
+                    last_run.timestamp_millis() as f64 / 1000.0,
+                );
+            }
+            let last = runs
+                .iter()
+                .find(|run| !matches!(run.outcome, RunOutcome::Skipped(_)));
+            if let Some(run) = last {
+                push(
+                    "ply_jobs_last_run_duration_seconds",
+                    &[
+                        ("job", job),
+                        ("instance", &run.instance),
+                        ("outcome", run.outcome.as_str()),
+                    ],
+                    (run.finished - run.started).num_milliseconds() as f64 / 1000.0,
+                );
+            }
+            let mut counts: BTreeMap<(&str, &str), u32> = BTreeMap::new();
+            for run in runs {
+                *counts
+                    .entry((run.instance.as_str(), run.outcome.as_str()))
+                    .or_default() += 1;
+            }
+            for ((instance, outcome), count) in counts {
+                push(
+                    "ply_jobs_recent_runs",
+                    &[("job", job), ("instance", instance), ("outcome", outcome)],
+                    count as f64,
+                );
+            }
+        }
+        MetricsSnapshot { samples }
+    }
+
+    /// Render the samples in the Prometheus text exposition format, e.g. to serve them to a
+    /// Prometheus server scraping the embedding application.
+    pub fn to_prometheus(&self) -> String {
+        let mut by_name: BTreeMap<&str, Vec<&MetricSample>> = BTreeMap::new();
+        for sample in &self.samples {
+            by_name.entry(sample.name).or_default().push(sample);
+        }
+        let mut out = String::new();
+        for (name, samples) in by_name {
+            let _ = writeln!(out, "# TYPE {} {}", name, samples[0].kind.as_str());
+            for sample in samples {
+                let labels: Vec<String> = sample
+                    .labels
+                    .iter()
+                    .map(|(key, value)| format!("{}=\"{}\"", key, escape_label(value)))
+                    .collect();
+                let _ = writeln!(out, "{}{{{}}} {}", name, labels.join(","), sample.value);
+            }
+        }
+        out
+    }
+}
+
+// Number of and time spent in the state transitions of the executors of one instance, per
+// job and pair of states.
+#[derive(Default)]
+pub(crate) struct Transitions {
+    totals: Mutex<BTreeMap<TransitionKey, (u64, Duration)>>,
+}
+
+// Job, state left and state entered.
+type TransitionKey = (String, &'static str, &'static str);
+
+impl Transitions {
+    // Count a transition from state `from`, entered `spent` before, to state `to`.
+    pub(crate) fn record(
+        &self,
+        job: &JobName,
+        from: &'static str,
+        to: &'static str,
+        spent: Duration,
+    ) {
+        let mut totals = self.totals.lock().unwrap_or_else(|e| e.into_inner());
+        let total = totals
+            .entry((job.as_str().to_owned(), from, to))
+            .or_default();
+        total.0 += 1;
+        total.1 += spent;
+    }
+
+    // The totals counted so far, see `crate::JobManager::transition_metrics`.
+    pub(crate) fn snapshot(&self, instance: &str) -> MetricsSnapshot {
+        let totals = self.totals.lock().unwrap_or_else(|e| e.into_inner());
+        let mut samples = Vec::new();
+        for ((job, from, to), (count, spent)) in totals.iter() {
+            let labels: BTreeMap<_, _> = [
+                ("job", job.clone()),
+                ("instance", instance.to_owned()),
+                ("from", (*from).to_owned()),
+                ("to", (*to).to_owned()),
+            ]
+            .into();
+            samples.push(MetricSample {
+                name: "ply_jobs_transitions_total",
+                kind: MetricKind::Counter,
+                labels: labels.clone(),
+                value: *count as f64,
+            });
+            samples.push(MetricSample {
+                name: "ply_jobs_transition_seconds_total",
+                kind: MetricKind::Counter,
+                labels,
+                value: spent.as_secs_f64(),
+            });
+        }
+        MetricsSnapshot { samples }
+    }
+}
+
+fn flag(on: bool) -> f64 {
+    if on {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
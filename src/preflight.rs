@@ -0,0 +1,132 @@
+//! Checks of the repo to run before starting the jobs, e.g. in the health check of a deploy,
+//! so that a misconfigured store fails the deploy instead of the first runs.
+use crate::clock::measure_skew;
+use crate::job::JobData;
+use crate::repos::Repo;
+use crate::schedule::Schedule;
+use crate::{JobConfig, JobName};
+use chrono::Utc;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Outcome of a single check of [`crate::JobManager::preflight`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct PreflightCheck {
+    /// One of `connectivity`, `create`, `update`, `delete`, `indexes` and `clock`.
+    pub name: &'static str,
+    pub passed: bool,
+    /// What was found, or why the check failed or was not made.
+    pub detail: String,
+}
+
+/// Outcomes of the checks of [`crate::JobManager::preflight`], in the order they were made.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PreflightReport {
+    pub checks: Vec<PreflightCheck>,
+}
+
+impl PreflightReport {
+    /// Whether all checks passed.
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|check| check.passed)
+    }
+    /// The checks that failed.
+    pub fn failures(&self) -> impl Iterator<Item = &PreflightCheck> {
+        self.checks.iter().filter(|check| !check.passed)
+    }
+
+    fn check(&mut self, name: &'static str, passed: bool, detail: impl Into<String>) {
+        self.checks.push(PreflightCheck {
+            name,
+            passed,
+            detail: detail.into(),
+        });
+    }
+    fn check_result<E: Display>(
+        &mut self,
+        name: &'static str,
+        result: Result<(), E>,
+        ok: &str,
+    ) -> bool {
+        let passed = result.is_ok();
+        match result {
+            Ok(()) => self.check(name, true, ok),
+            Err(e) => self.check(name, false, e.to_string()),
+        }
+        passed
+    }
+}
+
+impl Display for PreflightReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for check in &self.checks {
+            let outcome = if check.passed { "ok" } else { "FAILED" };
+            writeln!(f, "{:<12} {:<6} {}", check.name, outcome, check.detail)?;
+        }
+        Ok(())
+    }
+}
+
+// Check the repo with a disabled, archived probe job of the instance, which is deleted again.
+pub(crate) async fn preflight<R: Repo>(
+    mut repo: R,
+    instance: &str,
+    max_clock_skew: Duration,
+) -> PreflightReport {
+    let mut report = PreflightReport::default();
+    let name = JobName(format!("__ply_jobs_preflight.{}", instance));
+    let reached = repo.get(name.clone()).await.map(|_| ());
+    if !report.check_result("connectivity", reached, "the repo answered") {
+        for name in ["create", "update", "delete", "indexes", "clock"] {
+            report.check(name, false, "not checked, the repo is unreachable");
+        }
+        return report;
+    }
+
+    let schedule = Schedule::from_str("0 0 0 1 1 *").expect("valid schedule");
+    let mut probe = JobData::from(JobConfig::new(name.0.clone(), schedule));
+    probe.enabled = false;
+    probe.archived = true;
+    let created = repo.create(probe).await;
+    if report.check_result("create", created, "created a probe job") {
+        let updated = repo.heartbeat(name.clone(), Some(Utc::now())).await;
+        report.check_result("update", updated, "updated the probe job");
+        match repo.delete(name.clone()).await {
+            Ok(true) => report.check("delete", true, "deleted the probe job"),
+            Ok(false) => report.check("delete", false, "the probe job was gone before deleting it"),
+            Err(e) => report.check("delete", false, e.to_string()),
+        }
+    } else {
+        report.check("update", false, "not checked, no probe job to update");
+        report.check("delete", false, "not checked, no probe job to delete");
+    }
+
+    match repo.missing_indexes().await {
+        Ok(missing) if missing.is_empty() => report.check("indexes", true, "all indexes exist"),
+        Ok(missing) => report.check(
+            "indexes",
+            false,
+            format!("missing indexes {}", missing.join(", ")),
+        ),
+        Err(e) => report.check("indexes", false, e.to_string()),
+    }
+
+    match measure_skew(&mut repo).await {
+        Ok(None) => report.check("clock", true, "the store has no clock of its own"),
+        Ok(Some(skew)) => {
+            let passed = skew.abs().to_std().unwrap_or_default() <= max_clock_skew;
+            report.check(
+                "clock",
+                passed,
+                format!(
+                    "{}ms off from the clock of the store, at most {}ms allowed",
+                    skew.num_milliseconds(),
+                    max_clock_skew.as_millis()
+                ),
+            )
+        }
+        Err(e) => report.check("clock", false, e.to_string()),
+    }
+    report
+}
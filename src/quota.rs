@@ -0,0 +1,105 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::Instant;
+
+/// Limit of concurrently running jobs of a group on this instance. Due jobs that find the
+/// quota exhausted register as waiting, and no job of lower priority is admitted while they
+/// wait, so freed slots go to the most important jobs first.
+pub(crate) struct Quota {
+    slots: Arc<Semaphore>,
+    // Number of executors waiting for a slot, by priority.
+    waiting: Mutex<BTreeMap<u8, usize>>,
+    // Since when admissions have found no free slot.
+    saturated_since: Mutex<Option<Instant>>,
+    shedding: Option<Shedding>,
+}
+
+// Skip due runs of jobs below the priority once the quota has been saturated for longer than
+// the threshold, see `JobManager::with_load_shedding`.
+struct Shedding {
+    after: Duration,
+    below_priority: u8,
+}
+
+impl Quota {
+    pub(crate) fn new(max: usize) -> Self {
+        Quota {
+            slots: Arc::new(Semaphore::new(max)),
+            waiting: Mutex::new(BTreeMap::new()),
+            saturated_since: Mutex::new(None),
+            shedding: None,
+        }
+    }
+
+    pub(crate) fn shed(&mut self, after: Duration, below_priority: u8) {
+        self.shedding = Some(Shedding {
+            after,
+            below_priority,
+        });
+    }
+
+    // Take a slot unless none is free or a job of higher priority is waiting for one.
+    pub(crate) fn try_admit(&self, priority: u8) -> Option<OwnedSemaphorePermit> {
+        let outranked = priority < u8::MAX && self.waiting().range(priority + 1..).next().is_some();
+        if outranked {
+            return None;
+        }
+        let permit = self.slots.clone().try_acquire_owned().ok();
+        let mut saturated_since = self.saturated_since();
+        match permit {
+            Some(_) => *saturated_since = None,
+            None => {
+                saturated_since.get_or_insert_with(Instant::now);
+            }
+        }
+        permit
+    }
+
+    // Whether due runs of jobs of the priority are to be skipped rather than wait for a slot.
+    pub(crate) fn sheds(&self, priority: u8) -> bool {
+        let Some(shedding) = &self.shedding else {
+            return false;
+        };
+        priority < shedding.below_priority
+            && self.slots.available_permits() == 0
+            && self
+                .saturated_since()
+                .is_some_and(|since| since.elapsed() >= shedding.after)
+    }
+
+    // Register an executor waiting for a slot until the returned guard is dropped.
+    pub(crate) fn wait(self: &Arc<Self>, priority: u8) -> QuotaWait {
+        *self.waiting().entry(priority).or_default() += 1;
+        QuotaWait {
+            quota: self.clone(),
+            priority,
+        }
+    }
+
+    fn waiting(&self) -> std::sync::MutexGuard<'_, BTreeMap<u8, usize>> {
+        self.waiting.lock().expect("quota lock poisoned")
+    }
+
+    fn saturated_since(&self) -> std::sync::MutexGuard<'_, Option<Instant>> {
+        self.saturated_since.lock().expect("quota lock poisoned")
+    }
+}
+
+pub(crate) struct QuotaWait {
+    quota: Arc<Quota>,
+    priority: u8,
+}
+
+impl Drop for QuotaWait {
+    fn drop(&mut self) {
+        let mut waiting = self.quota.waiting();
+        if let Some(count) = waiting.get_mut(&self.priority) {
+            *count -= 1;
+            if *count == 0 {
+                waiting.remove(&self.priority);
+            }
+        }
+    }
+}
@@ -0,0 +1,233 @@
+//! Triggering jobs from a Redis stream. Every entry of the stream requests a run of the job
+//! named in its `job` field, like [`crate::JobManager::trigger`] does, and an optional
+//! `traceparent` field joins the run to the trace of the producer. The entries are read
+//! through a consumer group, so that every entry is handled by one instance only.
+//!
+//! ```rust,ignore
+//!     let trigger = RedisStreamTrigger::new("redis://redis:6379", "job-triggers", "ply-jobs")?;
+//!     let (stop, stopped) = oneshot::channel();
+//!     tokio::spawn(trigger.run(instance_id.clone(), repo.clone(), stopped));
+//! ```
+//!
+//! Producers add entries with `XADD job-triggers * job nightly-report`.
+use crate::backoff::Backoff;
+use crate::error::{Error, Result};
+use crate::job::RunRequest;
+use crate::manager::valid_trace_parent;
+use crate::repos::Repo;
+use crate::{InstanceId, JobName};
+use log::{trace, warn};
+use redis::aio::Connection;
+use redis::streams::{
+    StreamClaimReply, StreamId, StreamPendingCountReply, StreamReadOptions, StreamReadReply,
+};
+use redis::{AsyncCommands, Client, RedisError};
+use std::convert::Infallible;
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tokio::time::sleep;
+
+// The default lock TTL, see `crate::JobConfig::with_lock_ttl`.
+const DEFAULT_CLAIM_AFTER: Duration = Duration::from_secs(20);
+const READ_BLOCK: Duration = Duration::from_secs(1);
+const BATCH_SIZE: usize = 16;
+
+/// Consumer of a Redis stream requesting runs of the jobs named in its entries. An entry is
+/// acknowledged once the run is requested in the repo, and entries naming unknown jobs are
+/// acknowledged and dropped with a warning. Entries that could not be handled, because the
+/// repo failed or the consuming instance died, are claimed by the next consumer once they
+/// were pending for longer than [`RedisStreamTrigger::with_claim_after`], just like a lock
+/// that is no longer refreshed is taken over after its TTL.
+///
+/// Entries for a job that arrive before its requested run started coalesce into a single run.
+pub struct RedisStreamTrigger {
+    client: Client,
+    stream: String,
+    group: String,
+    claim_after: Duration,
+}
+
+impl RedisStreamTrigger {
+    /// Read the stream stored at key `stream` through the consumer group `group`. The stream
+    /// and the group are created if missing, starting with the entries added from then on.
+    pub fn new(url: &str, stream: impl Into<String>, group: impl Into<String>) -> Result<Self> {
+        Ok(RedisStreamTrigger {
+            client: Client::open(url).map_err(repo_error)?,
+            stream: stream.into(),
+            group: group.into(),
+            claim_after: DEFAULT_CLAIM_AFTER,
+        })
+    }
+    /// Claim entries that were read but not acknowledged for longer than `idle`. Defaults to
+    /// 20s, the default lock TTL.
+    pub fn with_claim_after(mut self, idle: Duration) -> Self {
+        self.claim_after = idle;
+        self
+    }
+
+    /// Consume the stream as consumer `instance` of the group until `stop` fires, requesting
+    /// the runs in `repo`, the repo of the managers running the jobs. Failures to reach Redis
+    /// are logged and retried with a growing delay.
+    #[allow(private_bounds)]
+    pub async fn run<R: Repo>(
+        self,
+        instance: impl Into<InstanceId>,
+        mut repo: R,
+        mut stop: oneshot::Receiver<()>,
+    ) -> Result<()> {
+        let consumer = instance.into().0;
+        let mut backoff = Backoff::new(Duration::from_millis(500), Duration::from_secs(30));
+        loop {
+            let consumed = tokio::select! {
+                res = self.consume(&consumer, &mut repo, &mut backoff) => res,
+                _ = &mut stop => return Ok(()),
+            };
+            let Err(e) = consumed;
+            let delay = backoff.next_delay();
+            warn!(
+                "consuming stream {} failed, retrying in {}ms: {}",
+                self.stream,
+                delay.as_millis(),
+                e
+            );
+            tokio::select! {
+                _ = sleep(delay) => {}
+                _ = &mut stop => return Ok(()),
+            }
+        }
+    }
+
+    // Handle the entries of the stream on one connection until Redis fails.
+    async fn consume<R: Repo>(
+        &self,
+        consumer: &str,
+        repo: &mut R,
+        backoff: &mut Backoff,
+    ) -> Result<Infallible> {
+        let mut con = self
+            .client
+            .get_async_connection()
+            .await
+            .map_err(repo_error)?;
+        self.create_group(&mut con).await?;
+        backoff.reset();
+        let options = StreamReadOptions::default()
+            .group(&self.group, consumer)
+            .count(BATCH_SIZE)
+            .block(READ_BLOCK.as_millis() as usize);
+        loop {
+            for entry in self.claim_stale(&mut con, consumer).await? {
+                self.handle(&mut con, repo, entry).await?;
+            }
+            let reply: StreamReadReply = con
+                .xread_options(&[&self.stream], &[">"], &options)
+                .await
+                .map_err(repo_error)?;
+            for entry in reply.keys.into_iter().flat_map(|key| key.ids) {
+                self.handle(&mut con, repo, entry).await?;
+            }
+        }
+    }
+
+    async fn create_group(&self, con: &mut Connection) -> Result<()> {
+        let created: std::result::Result<(), RedisError> = con
+            .xgroup_create_mkstream(&self.stream, &self.group, "$")
+            .await;
+        match created {
+            Err(e) if e.code() != Some("BUSYGROUP") => Err(repo_error(e)),
+            _ => Ok(()),
+        }
+    }
+
+    // Take over the entries pending for too long, from consumers that are gone or from this
+    // one if requesting the run failed.
+    async fn claim_stale(&self, con: &mut Connection, consumer: &str) -> Result<Vec<StreamId>> {
+        let pending: StreamPendingCountReply = con
+            .xpending_count(&self.stream, &self.group, "-", "+", BATCH_SIZE)
+            .await
+            .map_err(repo_error)?;
+        let min_idle = self.claim_after.as_millis() as usize;
+        let stale: Vec<String> = pending
+            .ids
+            .into_iter()
+            .filter(|p| p.last_delivered_ms >= min_idle)
+            .map(|p| p.id)
+            .collect();
+        if stale.is_empty() {
+            return Ok(Vec::new());
+        }
+        let claimed: StreamClaimReply = con
+            .xclaim(&self.stream, &self.group, consumer, min_idle, &stale)
+            .await
+            .map_err(repo_error)?;
+        trace!(
+            "claimed {} stale entries of stream {}",
+            claimed.ids.len(),
+            self.stream
+        );
+        Ok(claimed.ids)
+    }
+
+    // Request the run named by the entry, acknowledging the entry unless the repo failed.
+    async fn handle<R: Repo>(
+        &self,
+        con: &mut Connection,
+        repo: &mut R,
+        entry: StreamId,
+    ) -> Result<()> {
+        let Some(job) = entry.get::<String>("job") else {
+            warn!(
+                "entry {} of stream {} names no job, dropping it",
+                entry.id, self.stream
+            );
+            return self.ack(con, &entry.id).await;
+        };
+        let trace_parent = entry.get::<String>("traceparent").filter(|tp| {
+            let valid = valid_trace_parent(tp);
+            if !valid {
+                warn!(
+                    "entry {} of stream {} has an invalid traceparent",
+                    entry.id, self.stream
+                );
+            }
+            valid
+        });
+        let request = RunRequest {
+            triggered: true,
+            trace_parent,
+            ..RunRequest::default()
+        };
+        match repo.request_run(JobName(job.clone()), Some(request)).await {
+            Ok(()) => trace!(
+                "entry {} of stream {} triggered job {}",
+                entry.id,
+                self.stream,
+                job
+            ),
+            Err(Error::NotFound(_)) => {
+                warn!(
+                    "entry {} of stream {} names unknown job {}, dropping it",
+                    entry.id, self.stream, job
+                )
+            }
+            Err(e) => {
+                warn!(
+                    "triggering job {} from entry {} failed: {}",
+                    job, entry.id, e
+                );
+                return Ok(());
+            }
+        }
+        self.ack(con, &entry.id).await
+    }
+
+    async fn ack(&self, con: &mut Connection, id: &str) -> Result<()> {
+        con.xack(&self.stream, &self.group, &[id])
+            .await
+            .map_err(repo_error)
+    }
+}
+
+fn repo_error(e: RedisError) -> Error {
+    Error::Repo(e.to_string())
+}
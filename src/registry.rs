@@ -0,0 +1,163 @@
+use crate::error::{Error, Result};
+use crate::schedule::Schedule;
+use crate::{Job, JobConfig, JobError, JobMetadata};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::time::Duration;
+
+type JobFactory = Box<
+    dyn Fn(&serde_json::Value) -> std::result::Result<Box<dyn Job + Send>, JobError> + Send + Sync,
+>;
+
+/// Factories of jobs by kind, so that which jobs exist can be declared as data, see
+/// [`JobDefinition`], while how a job of a kind is constructed stays in code.
+/// ```rust,ignore
+///     let registry = JobRegistry::new().with_job("report", |params| {
+///         let customer = params["customer"].as_str().ok_or("customer missing")?;
+///         Ok(ReportJob::new(customer))
+///     });
+///     let definition: JobDefinition = serde_json::from_str(
+///         r#"{"name": "report-acme", "kind": "report", "schedule": "0 0 2 * * *",
+///             "params": {"customer": "acme"}}"#,
+///     )?;
+///     manager.register_definition(&registry, definition)?;
+/// ```
+#[derive(Default)]
+pub struct JobRegistry {
+    factories: BTreeMap<String, JobFactory>,
+}
+
+impl JobRegistry {
+    pub fn new() -> Self {
+        JobRegistry::default()
+    }
+    /// Register the factory of the jobs of the given kind. It gets the parameters of the
+    /// definition and replaces a factory registered for the same kind before.
+    pub fn with_job<F, J>(mut self, kind: impl Into<String>, factory: F) -> Self
+    where
+        F: Fn(&serde_json::Value) -> std::result::Result<J, JobError> + Send + Sync + 'static,
+        J: Job + Send + 'static,
+    {
+        self.factories.insert(
+            kind.into(),
+            Box::new(move |params| Ok(Box::new(factory(params)?) as Box<dyn Job + Send>)),
+        );
+        self
+    }
+    /// Kinds of jobs the registry can construct, in alphabetical order.
+    pub fn kinds(&self) -> impl Iterator<Item = &str> {
+        self.factories.keys().map(String::as_str)
+    }
+
+    // Construct the job and its config from the definition.
+    pub(crate) fn instantiate(
+        &self,
+        definition: &JobDefinition,
+    ) -> Result<(JobConfig, Box<dyn Job + Send>)> {
+        let factory = self
+            .factories
+            .get(&definition.kind)
+            .ok_or_else(|| Error::UnknownJobKind(definition.kind.clone()))?;
+        let config = definition.config()?;
+        let action = factory(&definition.params)
+            .map_err(|e| Error::JobFactoryFailed(config.name.clone(), e.to_string()))?;
+        Ok((config, action))
+    }
+}
+
+/// Declarative, serializable description of a job whose action a [`JobRegistry`] constructs.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct JobDefinition {
+    pub name: String,
+    /// Kind under which the job's factory is registered.
+    pub kind: String,
+    /// Cron expression of the schedule.
+    pub schedule: String,
+    #[serde(default = "enabled")]
+    pub enabled: bool,
+    #[serde(default)]
+    pub check_interval_secs: Option<u64>,
+    #[serde(default)]
+    pub lock_ttl_secs: Option<u64>,
+    #[serde(default)]
+    pub metadata: JobMetadata,
+    /// Parameters handed to the factory of the job's kind.
+    #[serde(default)]
+    pub params: serde_json::Value,
+}
+
+/// Definition shared by a group of jobs of one kind that differ in their parameters, e.g. one
+/// report job per customer. Every instance is a job of its own with its own state and lock,
+/// named `<template>.<instance>` and labeled with `template=<template>`, so that the group
+/// can be selected with [`crate::JobFilter::with_namespace`] or
+/// [`crate::JobFilter::with_label`].
+/// ```rust,ignore
+///     let template: JobTemplate = serde_json::from_str(
+///         r#"{"name": "report", "kind": "report", "schedule": "0 0 2 * * *"}"#,
+///     )?;
+///     manager.register_template(&registry, &template, [
+///         ("acme", json!({"customer": "acme"})),
+///         ("globex", json!({"customer": "globex", "region": "eu"})),
+///     ])?;
+/// ```
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct JobTemplate {
+    pub name: String,
+    /// Kind under which the factory of the instances is registered.
+    pub kind: String,
+    /// Cron expression of the schedule of every instance.
+    pub schedule: String,
+    #[serde(default = "enabled")]
+    pub enabled: bool,
+    #[serde(default)]
+    pub check_interval_secs: Option<u64>,
+    #[serde(default)]
+    pub lock_ttl_secs: Option<u64>,
+    #[serde(default)]
+    pub metadata: JobMetadata,
+}
+
+impl JobTemplate {
+    /// The definition of the instance with the given name and parameters. Change it before
+    /// registering, e.g. to give the instance a schedule of its own.
+    pub fn instance(&self, instance: &str, params: serde_json::Value) -> JobDefinition {
+        let mut metadata = self.metadata.clone();
+        metadata
+            .labels
+            .insert(TEMPLATE_LABEL.to_owned(), self.name.clone());
+        JobDefinition {
+            name: format!("{}.{}", self.name, instance),
+            kind: self.kind.clone(),
+            schedule: self.schedule.clone(),
+            enabled: self.enabled,
+            check_interval_secs: self.check_interval_secs,
+            lock_ttl_secs: self.lock_ttl_secs,
+            metadata,
+            params,
+        }
+    }
+}
+
+/// Label naming the template a job was instantiated from, see [`JobTemplate`].
+pub const TEMPLATE_LABEL: &str = "template";
+
+fn enabled() -> bool {
+    true
+}
+
+impl JobDefinition {
+    /// The config of the job, failing on an invalid schedule.
+    pub fn config(&self) -> Result<JobConfig> {
+        let mut config = JobConfig::new(self.name.clone(), Schedule::from_str(&self.schedule)?);
+        config.enabled = self.enabled;
+        if let Some(secs) = self.check_interval_secs {
+            config = config.with_check_interval(Duration::from_secs(secs));
+        }
+        if let Some(secs) = self.lock_ttl_secs {
+            config = config.with_lock_ttl(Duration::from_secs(secs));
+        }
+        config.metadata = self.metadata.clone();
+        Ok(config)
+    }
+}
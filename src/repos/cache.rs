@@ -0,0 +1,264 @@
+//! Read-through cache for the job data, so that frequent due checks of many jobs do not hit
+//! the backend while nothing could have changed.
+//!
+//! ```rust,ignore
+//!     let repo = CachedRepo::new(MongoRepo::new(client, "jobs", "jobs")).with_ttl(Duration::from_secs(2));
+//!     let mut manager = JobManager::new(InstanceId::generate(), repo);
+//! ```
+use super::{LockStatus, Repo, RepoCapabilities};
+use crate::error::Result;
+use crate::instance::InstancePresence;
+use crate::job::{Approval, JobData, Outbox, RunRequest};
+use crate::{JobFilter, JobName, RunId, RunRecord, Snapshot};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use tokio::time::Instant;
+
+const DEFAULT_TTL: Duration = Duration::from_secs(1);
+
+/// Repo wrapper caching the job data read by name for a short TTL. Writes through the repo or
+/// any of its clones drop the cached job data of the job, while locking and saving always go
+/// to the wrapped repo. Changes made by other instances show up once the cached data expired,
+/// and so does the release of a lock that is dropped without saving.
+#[derive(Clone)]
+pub struct CachedRepo<R> {
+    inner: R,
+    ttl: Duration,
+    entries: Arc<Mutex<HashMap<String, (Instant, JobData)>>>,
+}
+
+impl<R> CachedRepo<R> {
+    pub fn new(inner: R) -> Self {
+        CachedRepo {
+            inner,
+            ttl: DEFAULT_TTL,
+            entries: Arc::default(),
+        }
+    }
+    /// How long job data read from the wrapped repo is served from the cache. Keep it well
+    /// below the check intervals of the jobs, runs start up to `ttl` late otherwise. Defaults
+    /// to 1s.
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    fn entries(&self) -> MutexGuard<'_, HashMap<String, (Instant, JobData)>> {
+        self.entries.lock().expect("job data cache lock poisoned")
+    }
+
+    fn cached(&self, name: &JobName) -> Option<JobData> {
+        let mut entries = self.entries();
+        match entries.get(name.as_str()) {
+            Some((cached_at, jdata)) if cached_at.elapsed() < self.ttl => Some(jdata.clone()),
+            Some(_) => {
+                entries.remove(name.as_str());
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn invalidate(&self, name: &JobName) {
+        self.entries().remove(name.as_str());
+    }
+}
+
+#[async_trait]
+impl<R: Repo + Send + Sync> Repo for CachedRepo<R> {
+    type Lock = R::Lock;
+
+    async fn create(&mut self, data: JobData) -> Result<()> {
+        let name = data.name.clone();
+        let result = self.inner.create(data).await;
+        self.invalidate(&name);
+        result
+    }
+
+    async fn get(&mut self, name: JobName) -> Result<Option<JobData>> {
+        if let Some(jdata) = self.cached(&name) {
+            return Ok(Some(jdata));
+        }
+        let jdata = self.inner.get(name.clone()).await?;
+        if let Some(jdata) = &jdata {
+            self.entries()
+                .insert(name.as_str().to_owned(), (Instant::now(), jdata.clone()));
+        }
+        Ok(jdata)
+    }
+
+    async fn commit(
+        &mut self,
+        name: JobName,
+        owner: String,
+        last_run: DateTime<Utc>,
+        state: Vec<u8>,
+        state_version: u32,
+        outbox: Option<Outbox>,
+    ) -> Result<()> {
+        let result = self
+            .inner
+            .commit(name.clone(), owner, last_run, state, state_version, outbox)
+            .await;
+        self.invalidate(&name);
+        result
+    }
+
+    async fn save(
+        &mut self,
+        name: JobName,
+        owner: String,
+        last_run: DateTime<Utc>,
+        state: Vec<u8>,
+        state_version: u32,
+        outbox: Option<Outbox>,
+    ) -> Result<()> {
+        let result = self
+            .inner
+            .save(name.clone(), owner, last_run, state, state_version, outbox)
+            .await;
+        self.invalidate(&name);
+        result
+    }
+
+    async fn set_approval(&mut self, name: JobName, approval: Option<Approval>) -> Result<()> {
+        let result = self.inner.set_approval(name.clone(), approval).await;
+        self.invalidate(&name);
+        result
+    }
+
+    async fn approve(&mut self, name: JobName, run_id: RunId) -> Result<bool> {
+        let result = self.inner.approve(name.clone(), run_id).await;
+        self.invalidate(&name);
+        result
+    }
+
+    async fn request_run(&mut self, name: JobName, request: Option<RunRequest>) -> Result<()> {
+        let result = self.inner.request_run(name.clone(), request).await;
+        self.invalidate(&name);
+        result
+    }
+
+    async fn heartbeat(&mut self, name: JobName, at: Option<DateTime<Utc>>) -> Result<()> {
+        let result = self.inner.heartbeat(name.clone(), at).await;
+        self.invalidate(&name);
+        result
+    }
+
+    async fn set_archived(&mut self, name: JobName, archived: bool) -> Result<()> {
+        let result = self.inner.set_archived(name.clone(), archived).await;
+        self.invalidate(&name);
+        result
+    }
+
+    async fn set_enabled(&mut self, name: JobName, enabled: bool) -> Result<()> {
+        let result = self.inner.set_enabled(name.clone(), enabled).await;
+        self.invalidate(&name);
+        result
+    }
+
+    async fn set_maintenance(&mut self, on: bool) -> Result<()> {
+        self.inner.set_maintenance(on).await
+    }
+
+    async fn maintenance(&mut self) -> Result<bool> {
+        self.inner.maintenance().await
+    }
+
+    async fn announce_instance(
+        &mut self,
+        presence: InstancePresence,
+    ) -> Result<Option<InstancePresence>> {
+        self.inner.announce_instance(presence).await
+    }
+
+    async fn lock(
+        &mut self,
+        name: JobName,
+        owner: String,
+        ttl: Duration,
+    ) -> Result<LockStatus<Self::Lock>> {
+        let result = self.inner.lock(name.clone(), owner, ttl).await;
+        self.invalidate(&name);
+        result
+    }
+
+    async fn unlock(&mut self, name: JobName, owner: String) -> Result<bool> {
+        let result = self.inner.unlock(name.clone(), owner).await;
+        self.invalidate(&name);
+        result
+    }
+
+    async fn pin(&mut self, name: JobName, owner: String, until: DateTime<Utc>) -> Result<bool> {
+        let result = self.inner.pin(name.clone(), owner, until).await;
+        self.invalidate(&name);
+        result
+    }
+
+    async fn server_time(&mut self) -> Result<Option<DateTime<Utc>>> {
+        self.inner.server_time().await
+    }
+
+    async fn capabilities(&mut self) -> Result<RepoCapabilities> {
+        self.inner.capabilities().await
+    }
+
+    async fn missing_indexes(&mut self) -> Result<Vec<String>> {
+        self.inner.missing_indexes().await
+    }
+
+    async fn record_run(&mut self, record: RunRecord) -> Result<()> {
+        self.inner.record_run(record).await
+    }
+
+    async fn runs(&mut self, name: JobName, limit: usize) -> Result<Vec<RunRecord>> {
+        self.inner.runs(name, limit).await
+    }
+
+    async fn list(&mut self) -> Result<Vec<JobData>> {
+        self.inner.list().await
+    }
+
+    async fn list_matching(&mut self, filter: &JobFilter) -> Result<Vec<JobData>> {
+        self.inner.list_matching(filter).await
+    }
+
+    async fn due_jobs(&mut self, now: DateTime<Utc>, limit: usize) -> Result<Vec<JobData>> {
+        self.inner.due_jobs(now, limit).await
+    }
+
+    async fn snapshot(&mut self) -> Result<Snapshot> {
+        self.inner.snapshot().await
+    }
+
+    async fn restore(&mut self, snapshot: Snapshot) -> Result<()> {
+        let result = self.inner.restore(snapshot).await;
+        self.entries().clear();
+        result
+    }
+
+    async fn delete(&mut self, name: JobName) -> Result<bool> {
+        let result = self.inner.delete(name.clone()).await;
+        self.invalidate(&name);
+        result
+    }
+
+    async fn rename(&mut self, old: JobName, renamed: JobData) -> Result<()> {
+        let new = renamed.name.clone();
+        let result = self.inner.rename(old.clone(), renamed).await;
+        self.invalidate(&old);
+        self.invalidate(&new);
+        result
+    }
+
+    async fn delete_runs(&mut self, name: JobName) -> Result<u64> {
+        self.inner.delete_runs(name).await
+    }
+
+    async fn prune_runs(&mut self, before: DateTime<Utc>) -> Result<u64> {
+        self.inner.prune_runs(before).await
+    }
+}
@@ -0,0 +1,280 @@
+//! Fault injection for integration tests. [`ChaosRepo`] wraps any repo and makes it fail or
+//! stall at random, so that the idempotency of jobs and the recovery paths of the executor
+//! can be exercised in CI.
+//!
+//! ```rust,ignore
+//!     let repo = ChaosRepo::new(PickleDbRepo::new(db))
+//!         .with_lock_refresh_failures(0.1)
+//!         .with_save_failures(0.2)
+//!         .with_delays(0.5, Duration::from_millis(300));
+//!     let mut manager = JobManager::new(InstanceId::generate(), repo);
+//! ```
+use super::{LockStatus, Repo, RepoCapabilities};
+use crate::error::{Error, Result};
+use crate::instance::InstancePresence;
+use crate::job::{Approval, JobData, Outbox, RunRequest};
+use crate::{JobFilter, JobName, RunId, RunRecord, Snapshot};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures::FutureExt;
+use futures_util::future::BoxFuture;
+use rand::Rng;
+use std::time::Duration;
+use tokio::time::sleep;
+
+/// Repo wrapper injecting failures and delays with configurable probabilities between 0 and 1.
+#[derive(Clone)]
+pub struct ChaosRepo<R> {
+    inner: R,
+    lock_refresh_failure: f64,
+    save_failure: f64,
+    delay: f64,
+    max_delay: Duration,
+}
+
+impl<R> ChaosRepo<R> {
+    pub fn new(inner: R) -> Self {
+        ChaosRepo {
+            inner,
+            lock_refresh_failure: 0.0,
+            save_failure: 0.0,
+            delay: 0.0,
+            max_delay: Duration::ZERO,
+        }
+    }
+    /// Let an acquired lock fail at its first refresh, half the lock TTL after acquiring it.
+    pub fn with_lock_refresh_failures(mut self, probability: f64) -> Self {
+        self.lock_refresh_failure = probability;
+        self
+    }
+    /// Fail saving the job state after a run without touching the wrapped repo.
+    pub fn with_save_failures(mut self, probability: f64) -> Self {
+        self.save_failure = probability;
+        self
+    }
+    /// Delay any call to the repo by a random duration up to `max`.
+    pub fn with_delays(mut self, probability: f64, max: Duration) -> Self {
+        self.delay = probability;
+        self.max_delay = max;
+        self
+    }
+
+    async fn maybe_delay(&self) {
+        if chance(self.delay) {
+            let delay = rand::thread_rng().gen_range(Duration::ZERO..=self.max_delay);
+            sleep(delay).await;
+        }
+    }
+}
+
+fn chance(probability: f64) -> bool {
+    probability > 0.0 && rand::thread_rng().gen_bool(probability.min(1.0))
+}
+
+#[async_trait]
+impl<R> Repo for ChaosRepo<R>
+where
+    R: Repo + Send + Sync,
+    R::Lock: 'static,
+{
+    type Lock = BoxFuture<'static, Result<()>>;
+
+    async fn create(&mut self, data: JobData) -> Result<()> {
+        self.maybe_delay().await;
+        self.inner.create(data).await
+    }
+
+    async fn get(&mut self, name: JobName) -> Result<Option<JobData>> {
+        self.maybe_delay().await;
+        self.inner.get(name).await
+    }
+
+    async fn commit(
+        &mut self,
+        name: JobName,
+        owner: String,
+        last_run: DateTime<Utc>,
+        state: Vec<u8>,
+        state_version: u32,
+        outbox: Option<Outbox>,
+    ) -> Result<()> {
+        self.maybe_delay().await;
+        if chance(self.save_failure) {
+            return Err(Error::Repo("injected save failure".into()));
+        }
+        self.inner
+            .commit(name, owner, last_run, state, state_version, outbox)
+            .await
+    }
+
+    async fn save(
+        &mut self,
+        name: JobName,
+        owner: String,
+        last_run: DateTime<Utc>,
+        state: Vec<u8>,
+        state_version: u32,
+        outbox: Option<Outbox>,
+    ) -> Result<()> {
+        self.maybe_delay().await;
+        if chance(self.save_failure) {
+            return Err(Error::Repo("injected save failure".into()));
+        }
+        self.inner
+            .save(name, owner, last_run, state, state_version, outbox)
+            .await
+    }
+
+    async fn set_approval(&mut self, name: JobName, approval: Option<Approval>) -> Result<()> {
+        self.maybe_delay().await;
+        self.inner.set_approval(name, approval).await
+    }
+
+    async fn approve(&mut self, name: JobName, run_id: RunId) -> Result<bool> {
+        self.maybe_delay().await;
+        self.inner.approve(name, run_id).await
+    }
+
+    async fn request_run(&mut self, name: JobName, request: Option<RunRequest>) -> Result<()> {
+        self.maybe_delay().await;
+        self.inner.request_run(name, request).await
+    }
+
+    async fn heartbeat(&mut self, name: JobName, at: Option<DateTime<Utc>>) -> Result<()> {
+        self.maybe_delay().await;
+        self.inner.heartbeat(name, at).await
+    }
+
+    async fn set_archived(&mut self, name: JobName, archived: bool) -> Result<()> {
+        self.maybe_delay().await;
+        self.inner.set_archived(name, archived).await
+    }
+
+    async fn set_enabled(&mut self, name: JobName, enabled: bool) -> Result<()> {
+        self.maybe_delay().await;
+        self.inner.set_enabled(name, enabled).await
+    }
+
+    async fn set_maintenance(&mut self, on: bool) -> Result<()> {
+        self.maybe_delay().await;
+        self.inner.set_maintenance(on).await
+    }
+
+    async fn maintenance(&mut self) -> Result<bool> {
+        self.maybe_delay().await;
+        self.inner.maintenance().await
+    }
+
+    async fn announce_instance(
+        &mut self,
+        presence: InstancePresence,
+    ) -> Result<Option<InstancePresence>> {
+        self.maybe_delay().await;
+        self.inner.announce_instance(presence).await
+    }
+
+    async fn lock(
+        &mut self,
+        name: JobName,
+        owner: String,
+        ttl: Duration,
+    ) -> Result<LockStatus<Self::Lock>> {
+        self.maybe_delay().await;
+        let (jdata, lock) = match self.inner.lock(name, owner, ttl).await? {
+            LockStatus::Acquired(jdata, lock) => (jdata, lock),
+            LockStatus::AlreadyLocked => return Ok(LockStatus::AlreadyLocked),
+        };
+        if !chance(self.lock_refresh_failure) {
+            return Ok(LockStatus::Acquired(jdata, lock.boxed()));
+        }
+        // Dropping the wrapped lock on failure releases it like a real refresh failure would
+        // end the run.
+        let failing = async move {
+            tokio::select! {
+                res = lock => res,
+                _ = sleep(ttl / 2) => Err(Error::LockRefreshFailed("injected refresh failure".into())),
+            }
+        };
+        Ok(LockStatus::Acquired(jdata, failing.boxed()))
+    }
+
+    async fn unlock(&mut self, name: JobName, owner: String) -> Result<bool> {
+        self.maybe_delay().await;
+        self.inner.unlock(name, owner).await
+    }
+
+    async fn pin(&mut self, name: JobName, owner: String, until: DateTime<Utc>) -> Result<bool> {
+        self.maybe_delay().await;
+        self.inner.pin(name, owner, until).await
+    }
+
+    async fn server_time(&mut self) -> Result<Option<DateTime<Utc>>> {
+        self.maybe_delay().await;
+        self.inner.server_time().await
+    }
+
+    async fn capabilities(&mut self) -> Result<RepoCapabilities> {
+        self.maybe_delay().await;
+        self.inner.capabilities().await
+    }
+
+    async fn missing_indexes(&mut self) -> Result<Vec<String>> {
+        self.maybe_delay().await;
+        self.inner.missing_indexes().await
+    }
+
+    async fn record_run(&mut self, record: RunRecord) -> Result<()> {
+        self.maybe_delay().await;
+        self.inner.record_run(record).await
+    }
+
+    async fn runs(&mut self, name: JobName, limit: usize) -> Result<Vec<RunRecord>> {
+        self.maybe_delay().await;
+        self.inner.runs(name, limit).await
+    }
+
+    async fn list(&mut self) -> Result<Vec<JobData>> {
+        self.maybe_delay().await;
+        self.inner.list().await
+    }
+
+    async fn list_matching(&mut self, filter: &JobFilter) -> Result<Vec<JobData>> {
+        self.maybe_delay().await;
+        self.inner.list_matching(filter).await
+    }
+
+    async fn due_jobs(&mut self, now: DateTime<Utc>, limit: usize) -> Result<Vec<JobData>> {
+        self.maybe_delay().await;
+        self.inner.due_jobs(now, limit).await
+    }
+
+    async fn snapshot(&mut self) -> Result<Snapshot> {
+        self.maybe_delay().await;
+        self.inner.snapshot().await
+    }
+
+    async fn restore(&mut self, snapshot: Snapshot) -> Result<()> {
+        self.maybe_delay().await;
+        self.inner.restore(snapshot).await
+    }
+
+    async fn delete(&mut self, name: JobName) -> Result<bool> {
+        self.maybe_delay().await;
+        self.inner.delete(name).await
+    }
+
+    async fn rename(&mut self, old: JobName, renamed: JobData) -> Result<()> {
+        self.maybe_delay().await;
+        self.inner.rename(old, renamed).await
+    }
+
+    async fn delete_runs(&mut self, name: JobName) -> Result<u64> {
+        self.maybe_delay().await;
+        self.inner.delete_runs(name).await
+    }
+
+    async fn prune_runs(&mut self, before: DateTime<Utc>) -> Result<u64> {
+        self.maybe_delay().await;
+        self.inner.prune_runs(before).await
+    }
+}
@@ -0,0 +1,854 @@
+//! Conformance scenarios every repo implementation has to pass, so that the executor can rely
+//! on identical semantics regardless of the storage backend. Each scenario works on its own
+//! job names derived from `prefix`, so all of them can share one store. Failures panic.
+//!
+//! ```rust,ignore
+//!     #[tokio::test]
+//!     async fn pickledb_conforms() {
+//!         let db = PickleDb::new("jobs.db", PickleDbDumpPolicy::NeverDump, SerializationMethod::Json);
+//!         ply_jobs::conformance::run_all(PickleDbRepo::new(db), "conformance").await;
+//!     }
+//! ```
+use super::{LockStatus, Repo};
+use crate::error::Error;
+use crate::instance::InstancePresence;
+use crate::job::{Approval, JobData, RunRequest};
+use crate::schedule::minutely;
+use crate::{JobConfig, JobFilter, JobName, RunEvent, RunId, RunOutcome, RunRecord};
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use std::time::Duration;
+use tokio::time::sleep;
+
+const TTL: Duration = Duration::from_secs(2);
+
+/// Run all scenarios against the repo.
+#[allow(private_bounds)]
+pub async fn run_all<R>(repo: R, prefix: &str)
+where
+    R: Repo + Clone + Send + 'static,
+    R::Lock: 'static,
+{
+    create_and_get(repo.clone(), prefix).await;
+    create_existing_fails(repo.clone(), prefix).await;
+    lock_contention(repo.clone(), prefix).await;
+    lock_refresh(repo.clone(), prefix).await;
+    lock_taken_over(repo.clone(), prefix).await;
+    lock_released_on_drop(repo.clone(), prefix).await;
+    unlock(repo.clone(), prefix).await;
+    commit_keeps_lock(repo.clone(), prefix).await;
+    save_releases_lock(repo.clone(), prefix).await;
+    save_after_lock_lost(repo.clone(), prefix).await;
+    concurrent_lock(repo.clone(), prefix).await;
+    run_history(repo.clone(), prefix).await;
+    list_matching(repo.clone(), prefix).await;
+    due_jobs(repo.clone(), prefix).await;
+    approval(repo.clone(), prefix).await;
+    dry_run(repo.clone(), prefix).await;
+    maintenance(repo.clone(), prefix).await;
+    instance_presence(repo.clone(), prefix).await;
+    heartbeat(repo.clone(), prefix).await;
+    pin(repo.clone(), prefix).await;
+    archive(repo.clone(), prefix).await;
+    pause(repo.clone(), prefix).await;
+    rename(repo.clone(), prefix).await;
+    capabilities(repo.clone(), prefix).await;
+    delete(repo.clone(), prefix).await;
+    snapshot_and_restore(repo, prefix).await;
+}
+
+fn job(prefix: &str, scenario: &str) -> JobData {
+    JobData::from(JobConfig::new(
+        format!("{}-{}", prefix, scenario),
+        minutely(),
+    ))
+}
+
+fn run(name: &JobName, started: DateTime<Utc>, outcome: RunOutcome) -> RunRecord {
+    // Backends may store timestamps with millisecond precision only.
+    let started = DateTime::from_timestamp_millis(started.timestamp_millis()).unwrap();
+    RunRecord {
+        run_id: RunId::generate(),
+        name: name.clone(),
+        instance: "conformance".into(),
+        started,
+        finished: started + ChronoDuration::seconds(1),
+        outcome,
+        log: "line\n".into(),
+        log_truncated: false,
+        dry_run: false,
+        as_of: None,
+        trace_parent: None,
+        events: vec![RunEvent {
+            kind: "counted".into(),
+            payload: serde_json::json!({"count": 1}),
+        }],
+        result: Some(vec![1, 2]),
+    }
+}
+
+async fn acquired<R: Repo>(repo: &mut R, name: &JobName, owner: &str) -> Option<R::Lock> {
+    match repo.lock(name.clone(), owner.into(), TTL).await {
+        Ok(LockStatus::Acquired(_, lock)) => Some(lock),
+        Ok(LockStatus::AlreadyLocked) => None,
+        Err(e) => panic!("locking {:?} as {} failed: {}", name, owner, e),
+    }
+}
+
+/// A created job can be read back unchanged.
+#[allow(private_bounds)]
+pub async fn create_and_get<R: Repo>(mut repo: R, prefix: &str) {
+    let mut data = job(prefix, "create-and-get");
+    data.state_version = 3;
+    data.metadata.description = Some("conformance".into());
+    data.metadata.labels.insert("team".into(), "core".into());
+    repo.create(data.clone()).await.expect("create");
+
+    let got = repo
+        .get(data.name.clone())
+        .await
+        .expect("get")
+        .expect("job exists");
+    assert_eq!(got.name, data.name);
+    assert_eq!(got.check_interval, data.check_interval);
+    assert_eq!(got.lock_ttl, data.lock_ttl);
+    assert_eq!(got.state, data.state);
+    assert_eq!(got.state_version, data.state_version);
+    assert_eq!(String::from(got.schedule), String::from(data.schedule));
+    assert_eq!(got.enabled, data.enabled);
+    assert_eq!(got.last_run, data.last_run);
+    assert_eq!(got.metadata, data.metadata);
+
+    let missing = JobName(format!("{}-missing", prefix));
+    assert!(repo.get(missing).await.expect("get missing").is_none());
+}
+
+/// Creating a job that already exists fails instead of overwriting its state.
+#[allow(private_bounds)]
+pub async fn create_existing_fails<R: Repo>(mut repo: R, prefix: &str) {
+    let data = job(prefix, "create-existing");
+    repo.create(data.clone()).await.expect("create");
+    let _lock = acquired(&mut repo, &data.name, "a").await.expect("lock");
+    repo.commit(
+        data.name.clone(),
+        "a".into(),
+        data.last_run,
+        vec![1],
+        0,
+        None,
+    )
+    .await
+    .expect("commit");
+
+    assert!(repo.create(data.clone()).await.is_err());
+    let got = repo.get(data.name).await.expect("get").expect("job exists");
+    assert_eq!(got.state, vec![1]);
+}
+
+/// Only one owner holds the lock at a time, and it cannot acquire the lock again either.
+#[allow(private_bounds)]
+pub async fn lock_contention<R: Repo>(mut repo: R, prefix: &str) {
+    let data = job(prefix, "lock-contention");
+    repo.create(data.clone()).await.expect("create");
+
+    let lock = acquired(&mut repo, &data.name, "a").await;
+    assert!(lock.is_some(), "first lock must be acquired");
+    assert!(acquired(&mut repo, &data.name, "b").await.is_none());
+    assert!(
+        acquired(&mut repo, &data.name, "a").await.is_none(),
+        "owner must not re-acquire its lock"
+    );
+}
+
+/// A lock whose future is polled outlives its TTL.
+#[allow(private_bounds)]
+pub async fn lock_refresh<R>(mut repo: R, prefix: &str)
+where
+    R: Repo,
+    R::Lock: 'static,
+{
+    let data = job(prefix, "lock-refresh");
+    repo.create(data.clone()).await.expect("create");
+
+    let lock = acquired(&mut repo, &data.name, "a").await.expect("lock");
+    let holder = tokio::spawn(lock);
+    sleep(TTL * 2).await;
+    assert!(acquired(&mut repo, &data.name, "b").await.is_none());
+    assert!(!holder.is_finished(), "lock refresh must not fail");
+    holder.abort();
+}
+
+/// A lock that another owner took over can no longer be refreshed by its previous owner, and
+/// the refresh leaves the lock of the new owner alone.
+#[allow(private_bounds)]
+pub async fn lock_taken_over<R>(mut repo: R, prefix: &str)
+where
+    R: Repo,
+    R::Lock: 'static,
+{
+    let data = job(prefix, "lock-taken-over");
+    repo.create(data.clone()).await.expect("create");
+
+    let lock = acquired(&mut repo, &data.name, "a").await.expect("lock");
+    let holder = tokio::spawn(lock);
+    assert!(repo
+        .unlock(data.name.clone(), "a".into())
+        .await
+        .expect("unlock"));
+    let _taken = acquired(&mut repo, &data.name, "b")
+        .await
+        .expect("take over");
+
+    let refreshed = tokio::time::timeout(TTL * 2, holder)
+        .await
+        .expect("lock refresh must fail")
+        .expect("lock task");
+    assert!(matches!(refreshed, Err(Error::LockRefreshFailed(_))));
+    let got = repo
+        .get(data.name.clone())
+        .await
+        .expect("get")
+        .expect("job exists");
+    assert_eq!(got.owner, "b");
+}
+
+/// Dropping a held lock releases it for other owners.
+#[allow(private_bounds)]
+pub async fn lock_released_on_drop<R: Repo>(mut repo: R, prefix: &str) {
+    let data = job(prefix, "lock-drop");
+    repo.create(data.clone()).await.expect("create");
+
+    let lock = acquired(&mut repo, &data.name, "a").await.expect("lock");
+    drop(lock);
+    sleep(Duration::from_millis(500)).await;
+    assert!(acquired(&mut repo, &data.name, "b").await.is_some());
+}
+
+/// Unlocking releases the lock only for the owner holding it.
+#[allow(private_bounds)]
+pub async fn unlock<R: Repo>(mut repo: R, prefix: &str) {
+    let data = job(prefix, "unlock");
+    repo.create(data.clone()).await.expect("create");
+
+    let _lock = acquired(&mut repo, &data.name, "a").await.expect("lock");
+    assert!(!repo
+        .unlock(data.name.clone(), "b".into())
+        .await
+        .expect("unlock as other owner"));
+    assert!(acquired(&mut repo, &data.name, "b").await.is_none());
+
+    assert!(repo
+        .unlock(data.name.clone(), "a".into())
+        .await
+        .expect("unlock"));
+    assert!(acquired(&mut repo, &data.name, "b").await.is_some());
+}
+
+/// Committing stores state and last run like saving but keeps the lock.
+#[allow(private_bounds)]
+pub async fn commit_keeps_lock<R: Repo>(mut repo: R, prefix: &str) {
+    let data = job(prefix, "commit");
+    repo.create(data.clone()).await.expect("create");
+
+    let _lock = acquired(&mut repo, &data.name, "a").await.expect("lock");
+    let last_run = DateTime::from_timestamp(Utc::now().timestamp(), 0).unwrap();
+    repo.commit(data.name.clone(), "a".into(), last_run, vec![1, 2], 1, None)
+        .await
+        .expect("commit");
+
+    let got = repo
+        .get(data.name.clone())
+        .await
+        .expect("get")
+        .expect("job exists");
+    assert_eq!(got.state, vec![1, 2]);
+    assert_eq!(got.state_version, 1);
+    assert_eq!(got.last_run, last_run);
+    assert_eq!(got.next_run, data.schedule.next_after(&last_run));
+    assert_eq!(got.locked_by(Utc::now()), Some("a"));
+    assert!(acquired(&mut repo, &data.name, "b").await.is_none());
+}
+
+/// Saving stores state and last run and releases the lock.
+#[allow(private_bounds)]
+pub async fn save_releases_lock<R: Repo>(mut repo: R, prefix: &str) {
+    let data = job(prefix, "save");
+    repo.create(data.clone()).await.expect("create");
+
+    let _lock = acquired(&mut repo, &data.name, "a").await.expect("lock");
+    let last_run = DateTime::from_timestamp(Utc::now().timestamp(), 0).unwrap();
+    repo.save(data.name.clone(), "a".into(), last_run, vec![3], 1, None)
+        .await
+        .expect("save");
+
+    let got = repo
+        .get(data.name.clone())
+        .await
+        .expect("get")
+        .expect("job exists");
+    assert_eq!(got.state, vec![3]);
+    assert_eq!(got.state_version, 1);
+    assert_eq!(got.last_run, last_run);
+    assert_eq!(got.next_run, data.schedule.next_after(&last_run));
+    assert!(acquired(&mut repo, &data.name, "b").await.is_some());
+}
+
+/// Saving or committing fails for an owner that lost the lock and leaves the job, and the lock
+/// of the new owner, as they are.
+#[allow(private_bounds)]
+pub async fn save_after_lock_lost<R: Repo>(mut repo: R, prefix: &str) {
+    let data = job(prefix, "save-lock-lost");
+    repo.create(data.clone()).await.expect("create");
+
+    let _lost = acquired(&mut repo, &data.name, "a").await.expect("lock");
+    assert!(repo
+        .unlock(data.name.clone(), "a".into())
+        .await
+        .expect("unlock"));
+    let _taken = acquired(&mut repo, &data.name, "b")
+        .await
+        .expect("take over");
+    let last_run = DateTime::from_timestamp(Utc::now().timestamp(), 0).unwrap();
+    let committed = repo
+        .commit(data.name.clone(), "a".into(), last_run, vec![1], 1, None)
+        .await;
+    assert!(matches!(committed, Err(Error::LockRefreshFailed(_))));
+    let saved = repo
+        .save(data.name.clone(), "a".into(), last_run, vec![1], 1, None)
+        .await;
+    assert!(matches!(saved, Err(Error::LockRefreshFailed(_))));
+
+    let got = repo
+        .get(data.name.clone())
+        .await
+        .expect("get")
+        .expect("job exists");
+    assert_eq!(got.state, data.state);
+    assert_eq!(got.last_run, data.last_run);
+    assert_eq!(got.locked_by(Utc::now()), Some("b"));
+}
+
+/// A last run with a fraction of a second is read back with millisecond precision. Not part of
+/// [`run_all`], since some backends keep whole seconds only.
+#[allow(private_bounds)]
+pub async fn last_run_millis<R: Repo>(mut repo: R, prefix: &str) {
+    let data = job(prefix, "last-run-millis");
+    repo.create(data.clone()).await.expect("create");
+
+    let _lock = acquired(&mut repo, &data.name, "a").await.expect("lock");
+    let last_run = DateTime::from_timestamp(Utc::now().timestamp(), 250_000_000).unwrap();
+    repo.save(data.name.clone(), "a".into(), last_run, vec![], 0, None)
+        .await
+        .expect("save");
+
+    let got = repo
+        .get(data.name.clone())
+        .await
+        .expect("get")
+        .expect("job exists");
+    assert_eq!(got.last_run, last_run);
+    assert_eq!(got.next_run, data.schedule.next_after(&last_run));
+}
+
+/// Of many owners racing for a lock exactly one acquires it.
+#[allow(private_bounds)]
+pub async fn concurrent_lock<R>(mut repo: R, prefix: &str)
+where
+    R: Repo + Clone + Send + 'static,
+    R::Lock: 'static,
+{
+    let data = job(prefix, "concurrent-lock");
+    repo.create(data.clone()).await.expect("create");
+
+    let attempts: Vec<_> = (0..10)
+        .map(|i| {
+            let mut repo = repo.clone();
+            let name = data.name.clone();
+            tokio::spawn(async move { acquired(&mut repo, &name, &format!("owner-{}", i)).await })
+        })
+        .collect();
+    let mut locks = Vec::new();
+    for attempt in attempts {
+        locks.extend(attempt.await.expect("lock task"));
+    }
+    assert_eq!(locks.len(), 1);
+}
+
+/// Runs are returned newest first, limited, and can be deleted and pruned.
+#[allow(private_bounds)]
+pub async fn run_history<R: Repo>(mut repo: R, prefix: &str) {
+    let name = job(prefix, "history").name;
+    let now = Utc::now();
+    let old = run(&name, now - ChronoDuration::days(10), RunOutcome::Succeeded);
+    let failed = run(
+        &name,
+        now - ChronoDuration::minutes(2),
+        RunOutcome::Failed("x".into()),
+    );
+    let newest = run(&name, now - ChronoDuration::minutes(1), RunOutcome::Aborted);
+    for record in [&old, &failed, &newest] {
+        repo.record_run(record.clone()).await.expect("record run");
+    }
+
+    let runs = repo.runs(name.clone(), 2).await.expect("runs");
+    assert_eq!(runs, vec![newest.clone(), failed.clone()]);
+
+    let pruned = repo
+        .prune_runs(now - ChronoDuration::days(1))
+        .await
+        .expect("prune runs");
+    assert!(pruned >= 1);
+    let runs = repo.runs(name.clone(), 10).await.expect("runs");
+    assert_eq!(runs, vec![newest, failed]);
+
+    assert_eq!(
+        repo.delete_runs(name.clone()).await.expect("delete runs"),
+        2
+    );
+    assert!(repo.runs(name, 10).await.expect("runs").is_empty());
+}
+
+/// Listing with a filter returns exactly the jobs matching all of its criteria.
+#[allow(private_bounds)]
+pub async fn list_matching<R: Repo>(mut repo: R, prefix: &str) {
+    let namespace = format!("{}-list.", prefix);
+    let mut etl = job(&namespace, "etl");
+    etl.metadata.labels.insert("kind".into(), "etl".into());
+    let mut disabled_etl = job(&namespace, "disabled-etl");
+    disabled_etl.enabled = false;
+    disabled_etl.metadata = etl.metadata.clone();
+    let other = job(&namespace, "other");
+    for data in [&etl, &disabled_etl, &other] {
+        repo.create(data.clone()).await.expect("create");
+    }
+    let _lock = acquired(&mut repo, &other.name, "a").await;
+
+    let names = |jobs: Vec<JobData>| jobs.into_iter().map(|j| j.name).collect::<Vec<_>>();
+    let filter = JobFilter::default()
+        .with_namespace(namespace.as_str())
+        .with_label("kind", "etl")
+        .with_enabled(false);
+    let listed = repo.list_matching(&filter).await.expect("list matching");
+    assert_eq!(names(listed), vec![disabled_etl.name]);
+
+    let filter = JobFilter::default()
+        .with_namespace(namespace.as_str())
+        .with_locked(true);
+    let listed = repo.list_matching(&filter).await.expect("list matching");
+    assert_eq!(names(listed), vec![other.name]);
+}
+
+/// Due jobs are the enabled, unarchived ones whose schedule fired since their last run, the
+/// longest due first, and no more than asked for.
+#[allow(private_bounds)]
+pub async fn due_jobs<R: Repo>(mut repo: R, prefix: &str) {
+    let namespace = format!("{}-due.", prefix);
+    let now = Utc::now();
+    let mut overdue = job(&namespace, "overdue");
+    overdue.last_run = now - ChronoDuration::hours(1);
+    let mut due = job(&namespace, "due");
+    due.last_run = now - ChronoDuration::minutes(5);
+    let mut disabled = job(&namespace, "disabled");
+    disabled.enabled = false;
+    let ran = job(&namespace, "ran");
+    let archived = job(&namespace, "archived");
+    for data in [&due, &overdue, &disabled, &ran, &archived] {
+        repo.create(data.clone()).await.expect("create");
+    }
+    repo.set_archived(archived.name.clone(), true)
+        .await
+        .expect("archive");
+    let _lock = acquired(&mut repo, &ran.name, "a").await.expect("lock");
+    repo.save(ran.name.clone(), "a".into(), now, Vec::new(), 0, None)
+        .await
+        .expect("save");
+
+    let listed = repo.due_jobs(now, 1000).await.expect("due jobs");
+    let names: Vec<_> = listed
+        .into_iter()
+        .map(|j| j.name)
+        .filter(|name| name.as_str().starts_with(&namespace))
+        .collect();
+    assert_eq!(names, vec![overdue.name, due.name]);
+    assert_eq!(repo.due_jobs(now, 1).await.expect("due jobs").len(), 1);
+}
+
+/// Only the pending run can be approved, and only once.
+#[allow(private_bounds)]
+pub async fn approval<R: Repo>(mut repo: R, prefix: &str) {
+    let data = job(prefix, "approval");
+    repo.create(data.clone()).await.expect("create");
+    let pending = Approval {
+        run_id: RunId::generate(),
+        approved: false,
+    };
+    repo.set_approval(data.name.clone(), Some(pending.clone()))
+        .await
+        .expect("set approval");
+
+    let other = RunId::generate();
+    assert!(!repo
+        .approve(data.name.clone(), other)
+        .await
+        .expect("approve"));
+    let run_id = pending.run_id.clone();
+    assert!(repo
+        .approve(data.name.clone(), run_id.clone())
+        .await
+        .expect("approve"));
+    assert!(!repo
+        .approve(data.name.clone(), run_id)
+        .await
+        .expect("approve"));
+    let got = repo
+        .get(data.name.clone())
+        .await
+        .expect("get")
+        .expect("job exists");
+    assert_eq!(
+        got.approval,
+        Some(Approval {
+            approved: true,
+            ..pending
+        })
+    );
+
+    repo.set_approval(data.name.clone(), None)
+        .await
+        .expect("clear approval");
+    let got = repo.get(data.name).await.expect("get").expect("job exists");
+    assert_eq!(got.approval, None);
+}
+
+/// A requested dry run, backfill or triggered run is visible to all instances until it is withdrawn.
+#[allow(private_bounds)]
+pub async fn dry_run<R: Repo>(mut repo: R, prefix: &str) {
+    let data = job(prefix, "dry-run");
+    repo.create(data.clone()).await.expect("create");
+
+    let dry_run = RunRequest {
+        dry_run: true,
+        as_of: None,
+        trace_parent: Some("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01".into()),
+        triggered: false,
+    };
+    repo.request_run(data.name.clone(), Some(dry_run.clone()))
+        .await
+        .expect("request dry run");
+    let got = repo
+        .get(data.name.clone())
+        .await
+        .expect("get")
+        .expect("job exists");
+    assert_eq!(got.requested, Some(dry_run));
+
+    // Backends may store the as-of time with second precision only.
+    let backfill = RunRequest {
+        dry_run: false,
+        as_of: Some(DateTime::from_timestamp(Utc::now().timestamp() - 86400, 0).unwrap()),
+        trace_parent: None,
+        triggered: false,
+    };
+    repo.request_run(data.name.clone(), Some(backfill.clone()))
+        .await
+        .expect("request backfill");
+    let got = repo
+        .get(data.name.clone())
+        .await
+        .expect("get")
+        .expect("job exists");
+    assert_eq!(got.requested, Some(backfill));
+
+    let trigger = RunRequest {
+        triggered: true,
+        ..RunRequest::default()
+    };
+    repo.request_run(data.name.clone(), Some(trigger.clone()))
+        .await
+        .expect("trigger");
+    let got = repo
+        .get(data.name.clone())
+        .await
+        .expect("get")
+        .expect("job exists");
+    assert_eq!(got.requested, Some(trigger));
+
+    repo.request_run(data.name.clone(), None)
+        .await
+        .expect("withdraw request");
+    let got = repo.get(data.name).await.expect("get").expect("job exists");
+    assert_eq!(got.requested, None);
+
+    let missing = JobName(format!("{}-missing", prefix));
+    assert!(repo.request_run(missing, None).await.is_err());
+}
+
+/// The maintenance flag is off until switched on and does not show up as a job.
+#[allow(private_bounds)]
+pub async fn maintenance<R: Repo>(mut repo: R, prefix: &str) {
+    let data = job(prefix, "maintenance");
+    repo.create(data.clone()).await.expect("create");
+    assert!(!repo.maintenance().await.expect("maintenance"));
+
+    repo.set_maintenance(true).await.expect("switch on");
+    assert!(repo.maintenance().await.expect("maintenance"));
+    let listed = repo.list().await.expect("list");
+    assert!(listed.iter().any(|j| j.name == data.name));
+
+    repo.set_maintenance(false).await.expect("switch off");
+    assert!(!repo.maintenance().await.expect("maintenance"));
+}
+
+/// Announcing an instance returns the presence announced before for the same id, and
+/// presences do not show up as jobs.
+#[allow(private_bounds)]
+pub async fn instance_presence<R: Repo>(mut repo: R, prefix: &str) {
+    let jobs = repo.list().await.expect("list").len();
+    // Backends may store timestamps with millisecond precision only.
+    let now = DateTime::from_timestamp_millis(Utc::now().timestamp_millis()).unwrap();
+    let first = InstancePresence {
+        instance: format!("{}-instance", prefix),
+        incarnation: "first".into(),
+        started: now,
+        seen: now,
+    };
+    let previous = repo
+        .announce_instance(first.clone())
+        .await
+        .expect("announce");
+    assert_eq!(previous, None);
+
+    let second = InstancePresence {
+        incarnation: "second".into(),
+        seen: now + ChronoDuration::seconds(1),
+        ..first.clone()
+    };
+    let previous = repo.announce_instance(second).await.expect("announce");
+    assert_eq!(previous, Some(first));
+    assert_eq!(repo.list().await.expect("list").len(), jobs);
+}
+
+/// The latest heartbeat is kept to the millisecond until it is cleared.
+#[allow(private_bounds)]
+pub async fn heartbeat<R: Repo>(mut repo: R, prefix: &str) {
+    let data = job(prefix, "heartbeat");
+    repo.create(data.clone()).await.expect("create");
+    let got = repo
+        .get(data.name.clone())
+        .await
+        .expect("get")
+        .expect("job exists");
+    assert_eq!(got.last_heartbeat, None);
+
+    let at = DateTime::<Utc>::from_timestamp_millis(Utc::now().timestamp_millis()).unwrap();
+    repo.heartbeat(data.name.clone(), Some(at))
+        .await
+        .expect("heartbeat");
+    let got = repo
+        .get(data.name.clone())
+        .await
+        .expect("get")
+        .expect("job exists");
+    assert_eq!(got.last_heartbeat, Some(at));
+
+    repo.heartbeat(data.name.clone(), None)
+        .await
+        .expect("clear heartbeat");
+    let got = repo.get(data.name).await.expect("get").expect("job exists");
+    assert_eq!(got.last_heartbeat, None);
+}
+
+/// A pin keeps other owners out until it expires, and its owner can renew it.
+#[allow(private_bounds)]
+pub async fn pin<R: Repo>(mut repo: R, prefix: &str) {
+    let data = job(prefix, "pin");
+    repo.create(data.clone()).await.expect("create");
+    // Backends may store timestamps with millisecond precision only.
+    let now = DateTime::from_timestamp_millis(Utc::now().timestamp_millis()).unwrap();
+    let until = now + ChronoDuration::minutes(1);
+
+    assert!(repo
+        .pin(data.name.clone(), "a".into(), until)
+        .await
+        .expect("pin"));
+    assert!(!repo
+        .pin(data.name.clone(), "b".into(), until)
+        .await
+        .expect("pin"));
+    let renewed = until + ChronoDuration::minutes(1);
+    assert!(repo
+        .pin(data.name.clone(), "a".into(), renewed)
+        .await
+        .expect("renew"));
+    let got = repo
+        .get(data.name.clone())
+        .await
+        .expect("get")
+        .expect("job exists");
+    assert_eq!(got.pinned_to(now), Some("a"));
+    assert_eq!(got.pin.map(|pin| pin.until), Some(renewed));
+
+    // Releasing the pin lets it expire right away.
+    assert!(repo
+        .pin(data.name.clone(), "a".into(), now)
+        .await
+        .expect("release"));
+    assert!(repo
+        .pin(data.name.clone(), "b".into(), until)
+        .await
+        .expect("take over"));
+
+    let missing = JobName(format!("{}-pin-missing", prefix));
+    assert!(matches!(
+        repo.pin(missing, "a".into(), until).await,
+        Err(Error::NotFound(_))
+    ));
+}
+
+/// Archived jobs keep their data but are neither listed by default nor due, until they are
+/// unarchived.
+#[allow(private_bounds)]
+pub async fn archive<R: Repo>(mut repo: R, prefix: &str) {
+    let data = job(prefix, "archive");
+    repo.create(data.clone()).await.expect("create");
+    let now = Utc::now();
+    let namespace = JobFilter::default().with_namespace(data.name.as_str());
+    let due = |jobs: Vec<JobData>| jobs.iter().any(|jdata| jdata.name == data.name);
+
+    repo.set_archived(data.name.clone(), true)
+        .await
+        .expect("archive");
+    let got = repo
+        .get(data.name.clone())
+        .await
+        .expect("get")
+        .expect("archived job exists");
+    assert!(got.archived);
+    let listed = repo.list_matching(&namespace).await.expect("list matching");
+    assert!(listed.is_empty());
+    let listed = repo
+        .list_matching(&namespace.clone().with_archived(true))
+        .await
+        .expect("list matching archived");
+    assert_eq!(listed.len(), 1);
+    assert!(!due(repo.due_jobs(now, 1000).await.expect("due jobs")));
+
+    repo.set_archived(data.name.clone(), false)
+        .await
+        .expect("unarchive");
+    let listed = repo.list_matching(&namespace).await.expect("list matching");
+    assert_eq!(listed.len(), 1);
+    assert!(due(repo.due_jobs(now, 1000).await.expect("due jobs")));
+
+    let missing = JobName(format!("{}-archive-missing", prefix));
+    assert!(repo.set_archived(missing, true).await.is_err());
+}
+
+/// Paused jobs keep their state but are not due, until they are resumed.
+#[allow(private_bounds)]
+pub async fn pause<R: Repo>(mut repo: R, prefix: &str) {
+    let data = job(prefix, "pause");
+    repo.create(data.clone()).await.expect("create");
+    let now = Utc::now();
+    let due = |jobs: Vec<JobData>| jobs.iter().any(|jdata| jdata.name == data.name);
+
+    repo.set_enabled(data.name.clone(), false)
+        .await
+        .expect("pause");
+    let got = repo
+        .get(data.name.clone())
+        .await
+        .expect("get")
+        .expect("paused job exists");
+    assert!(!got.enabled);
+    assert_eq!(got.state, data.state);
+    assert!(!due(repo.due_jobs(now, 1000).await.expect("due jobs")));
+
+    repo.set_enabled(data.name.clone(), true)
+        .await
+        .expect("resume");
+    assert!(due(repo.due_jobs(now, 1000).await.expect("due jobs")));
+
+    let missing = JobName(format!("{}-pause-missing", prefix));
+    assert!(repo.set_enabled(missing, false).await.is_err());
+}
+
+/// Renaming creates the copy and archives the job under its old name, and a copy whose name
+/// is taken leaves the job as it is.
+#[allow(private_bounds)]
+pub async fn rename<R: Repo>(mut repo: R, prefix: &str) {
+    let mut data = job(prefix, "rename");
+    data.state = vec![7];
+    repo.create(data.clone()).await.expect("create");
+    let taken = job(prefix, "rename-taken");
+    repo.create(taken.clone()).await.expect("create taken");
+
+    assert!(repo
+        .rename(data.name.clone(), data.copied_as(taken.name.clone()))
+        .await
+        .is_err());
+    let got = repo
+        .get(data.name.clone())
+        .await
+        .expect("get")
+        .expect("job exists");
+    assert!(!got.archived);
+
+    let new = JobName(format!("{}-renamed", prefix));
+    repo.rename(data.name.clone(), data.copied_as(new.clone()))
+        .await
+        .expect("rename");
+    let renamed = repo
+        .get(new)
+        .await
+        .expect("get")
+        .expect("renamed job exists");
+    assert_eq!(renamed.state, vec![7]);
+    assert!(!renamed.archived);
+    let old = repo
+        .get(data.name.clone())
+        .await
+        .expect("get")
+        .expect("old job exists");
+    assert!(old.archived);
+}
+
+/// The repo reports a clock exactly if it can read the time of the store.
+#[allow(private_bounds)]
+pub async fn capabilities<R: Repo>(mut repo: R, _prefix: &str) {
+    let capabilities = repo.capabilities().await.expect("capabilities");
+    let server_time = repo.server_time().await.expect("server time");
+    assert_eq!(capabilities.clock, server_time.is_some());
+}
+
+/// Deleted jobs are gone and deleting reports whether the job existed.
+#[allow(private_bounds)]
+pub async fn delete<R: Repo>(mut repo: R, prefix: &str) {
+    let data = job(prefix, "delete");
+    repo.create(data.clone()).await.expect("create");
+
+    assert!(repo.delete(data.name.clone()).await.expect("delete"));
+    assert!(repo.get(data.name.clone()).await.expect("get").is_none());
+    assert!(!repo.delete(data.name).await.expect("delete again"));
+}
+
+/// A snapshot contains listed jobs and runs, and restoring it brings back deleted data.
+#[allow(private_bounds)]
+pub async fn snapshot_and_restore<R: Repo>(mut repo: R, prefix: &str) {
+    let data = job(prefix, "snapshot");
+    repo.create(data.clone()).await.expect("create");
+    let record = run(&data.name, Utc::now(), RunOutcome::Succeeded);
+    repo.record_run(record.clone()).await.expect("record run");
+
+    let listed = repo.list().await.expect("list");
+    assert!(listed.iter().any(|j| j.name == data.name));
+
+    let snapshot = repo.snapshot().await.expect("snapshot");
+    repo.delete(data.name.clone()).await.expect("delete");
+    repo.delete_runs(data.name.clone())
+        .await
+        .expect("delete runs");
+    repo.restore(snapshot).await.expect("restore");
+
+    assert!(repo.get(data.name.clone()).await.expect("get").is_some());
+    assert_eq!(repo.runs(data.name, 10).await.expect("runs"), vec![record]);
+}
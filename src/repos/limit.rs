@@ -0,0 +1,223 @@
+use super::{LockStatus, Repo, RepoCapabilities};
+use crate::error::Result;
+use crate::instance::InstancePresence;
+use crate::job::{Approval, JobData, Outbox, RunRequest};
+use crate::{JobFilter, JobName, RunId, RunRecord, Snapshot};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Repo wrapper the manager hands to the executors to cap the repo operations in flight on
+/// this instance, see [`crate::JobManager::with_max_repo_operations`]. Lock refreshes and
+/// releases run outside of the limit, so that a busy repo does not cost running jobs their
+/// locks.
+#[derive(Clone)]
+pub(crate) struct LimitedRepo<R> {
+    inner: R,
+    permits: Option<Arc<Semaphore>>,
+}
+
+impl<R> LimitedRepo<R> {
+    pub(crate) fn new(inner: R, permits: Option<Arc<Semaphore>>) -> Self {
+        LimitedRepo { inner, permits }
+    }
+}
+
+// Wait until fewer than the maximum of operations are in flight.
+async fn permit(permits: &Option<Arc<Semaphore>>) -> Option<OwnedSemaphorePermit> {
+    match permits {
+        Some(permits) => Some(
+            permits
+                .clone()
+                .acquire_owned()
+                .await
+                .expect("repo operation limiter is never closed"),
+        ),
+        None => None,
+    }
+}
+
+#[async_trait]
+impl<R: Repo + Send> Repo for LimitedRepo<R> {
+    type Lock = R::Lock;
+
+    async fn create(&mut self, data: JobData) -> Result<()> {
+        let _permit = permit(&self.permits).await;
+        self.inner.create(data).await
+    }
+
+    async fn get(&mut self, name: JobName) -> Result<Option<JobData>> {
+        let _permit = permit(&self.permits).await;
+        self.inner.get(name).await
+    }
+
+    async fn commit(
+        &mut self,
+        name: JobName,
+        owner: String,
+        last_run: DateTime<Utc>,
+        state: Vec<u8>,
+        state_version: u32,
+        outbox: Option<Outbox>,
+    ) -> Result<()> {
+        let _permit = permit(&self.permits).await;
+        self.inner
+            .commit(name, owner, last_run, state, state_version, outbox)
+            .await
+    }
+
+    async fn save(
+        &mut self,
+        name: JobName,
+        owner: String,
+        last_run: DateTime<Utc>,
+        state: Vec<u8>,
+        state_version: u32,
+        outbox: Option<Outbox>,
+    ) -> Result<()> {
+        let _permit = permit(&self.permits).await;
+        self.inner
+            .save(name, owner, last_run, state, state_version, outbox)
+            .await
+    }
+
+    async fn set_approval(&mut self, name: JobName, approval: Option<Approval>) -> Result<()> {
+        let _permit = permit(&self.permits).await;
+        self.inner.set_approval(name, approval).await
+    }
+
+    async fn approve(&mut self, name: JobName, run_id: RunId) -> Result<bool> {
+        let _permit = permit(&self.permits).await;
+        self.inner.approve(name, run_id).await
+    }
+
+    async fn request_run(&mut self, name: JobName, request: Option<RunRequest>) -> Result<()> {
+        let _permit = permit(&self.permits).await;
+        self.inner.request_run(name, request).await
+    }
+
+    async fn heartbeat(&mut self, name: JobName, at: Option<DateTime<Utc>>) -> Result<()> {
+        let _permit = permit(&self.permits).await;
+        self.inner.heartbeat(name, at).await
+    }
+
+    async fn set_archived(&mut self, name: JobName, archived: bool) -> Result<()> {
+        let _permit = permit(&self.permits).await;
+        self.inner.set_archived(name, archived).await
+    }
+
+    async fn set_enabled(&mut self, name: JobName, enabled: bool) -> Result<()> {
+        let _permit = permit(&self.permits).await;
+        self.inner.set_enabled(name, enabled).await
+    }
+
+    async fn set_maintenance(&mut self, on: bool) -> Result<()> {
+        let _permit = permit(&self.permits).await;
+        self.inner.set_maintenance(on).await
+    }
+
+    async fn maintenance(&mut self) -> Result<bool> {
+        let _permit = permit(&self.permits).await;
+        self.inner.maintenance().await
+    }
+
+    async fn announce_instance(
+        &mut self,
+        presence: InstancePresence,
+    ) -> Result<Option<InstancePresence>> {
+        let _permit = permit(&self.permits).await;
+        self.inner.announce_instance(presence).await
+    }
+
+    async fn lock(
+        &mut self,
+        name: JobName,
+        owner: String,
+        ttl: Duration,
+    ) -> Result<LockStatus<Self::Lock>> {
+        let _permit = permit(&self.permits).await;
+        self.inner.lock(name, owner, ttl).await
+    }
+
+    async fn unlock(&mut self, name: JobName, owner: String) -> Result<bool> {
+        let _permit = permit(&self.permits).await;
+        self.inner.unlock(name, owner).await
+    }
+
+    async fn pin(&mut self, name: JobName, owner: String, until: DateTime<Utc>) -> Result<bool> {
+        let _permit = permit(&self.permits).await;
+        self.inner.pin(name, owner, until).await
+    }
+
+    async fn server_time(&mut self) -> Result<Option<DateTime<Utc>>> {
+        let _permit = permit(&self.permits).await;
+        self.inner.server_time().await
+    }
+
+    async fn capabilities(&mut self) -> Result<RepoCapabilities> {
+        let _permit = permit(&self.permits).await;
+        self.inner.capabilities().await
+    }
+
+    async fn missing_indexes(&mut self) -> Result<Vec<String>> {
+        let _permit = permit(&self.permits).await;
+        self.inner.missing_indexes().await
+    }
+
+    async fn record_run(&mut self, record: RunRecord) -> Result<()> {
+        let _permit = permit(&self.permits).await;
+        self.inner.record_run(record).await
+    }
+
+    async fn runs(&mut self, name: JobName, limit: usize) -> Result<Vec<RunRecord>> {
+        let _permit = permit(&self.permits).await;
+        self.inner.runs(name, limit).await
+    }
+
+    async fn list(&mut self) -> Result<Vec<JobData>> {
+        let _permit = permit(&self.permits).await;
+        self.inner.list().await
+    }
+
+    async fn list_matching(&mut self, filter: &JobFilter) -> Result<Vec<JobData>> {
+        let _permit = permit(&self.permits).await;
+        self.inner.list_matching(filter).await
+    }
+
+    async fn due_jobs(&mut self, now: DateTime<Utc>, limit: usize) -> Result<Vec<JobData>> {
+        let _permit = permit(&self.permits).await;
+        self.inner.due_jobs(now, limit).await
+    }
+
+    async fn snapshot(&mut self) -> Result<Snapshot> {
+        let _permit = permit(&self.permits).await;
+        self.inner.snapshot().await
+    }
+
+    async fn restore(&mut self, snapshot: Snapshot) -> Result<()> {
+        let _permit = permit(&self.permits).await;
+        self.inner.restore(snapshot).await
+    }
+
+    async fn delete(&mut self, name: JobName) -> Result<bool> {
+        let _permit = permit(&self.permits).await;
+        self.inner.delete(name).await
+    }
+
+    async fn rename(&mut self, old: JobName, renamed: JobData) -> Result<()> {
+        let _permit = permit(&self.permits).await;
+        self.inner.rename(old, renamed).await
+    }
+
+    async fn delete_runs(&mut self, name: JobName) -> Result<u64> {
+        let _permit = permit(&self.permits).await;
+        self.inner.delete_runs(name).await
+    }
+
+    async fn prune_runs(&mut self, before: DateTime<Utc>) -> Result<u64> {
+        let _permit = permit(&self.permits).await;
+        self.inner.prune_runs(before).await
+    }
+}
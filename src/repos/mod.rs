@@ -1,13 +1,27 @@
-use crate::job::JobData;
-use crate::{error, JobName};
+use crate::instance::InstancePresence;
+use crate::job::{Approval, JobData, Outbox, RunRequest};
+use crate::{error, JobFilter, JobName, RunId, RunRecord, Snapshot};
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
 use futures::FutureExt;
 use futures_util::future::BoxFuture;
+use log::warn;
 use std::future::Future;
 use std::pin::Pin;
 use std::task::{Context, Poll};
 use std::time::Duration;
+use tokio::runtime::Handle;
+use tokio::time::{sleep_until, Instant};
+
+pub mod cache;
+
+#[cfg(feature = "chaos")]
+pub mod chaos;
+
+#[cfg(feature = "test-util")]
+pub mod conformance;
+
+pub(crate) mod limit;
 
 #[cfg(feature = "mongodb")]
 pub mod mongo;
@@ -15,8 +29,52 @@ pub mod mongo;
 #[cfg(feature = "pickledb")]
 pub mod pickledb;
 
+#[cfg(feature = "redlock")]
+pub mod redlock;
+
+/// Guarantees the store behind a repo offers, probed at runtime, see
+/// [`crate::JobManager::capabilities`].
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RepoCapabilities {
+    /// Instances in other processes can share the store.
+    pub shared: bool,
+    /// Locks are acquired with an atomic compare-and-set, so no two instances hold a lock at
+    /// the same time while their clocks agree.
+    pub atomic_locks: bool,
+    /// Writes to several documents can be made atomic with a transaction.
+    pub transactions: bool,
+    /// Changes can be watched instead of polled, e.g. with change streams.
+    pub watch: bool,
+    /// The store has a clock of its own to compare the clocks of the instances with, see
+    /// [`crate::JobManager::with_max_clock_skew`].
+    pub clock: bool,
+}
+
 pub(crate) struct Lock {
     fut: BoxFuture<'static, crate::error::Result<()>>,
+    // Clears owner and expiry if we still hold the lock. Spawned when the lock is dropped so
+    // that a canceled or aborted run does not block the job for the remaining TTL.
+    release: Option<BoxFuture<'static, ()>>,
+}
+
+impl Lock {
+    pub(crate) fn new(
+        fut: BoxFuture<'static, crate::error::Result<()>>,
+        release: BoxFuture<'static, ()>,
+    ) -> Self {
+        Lock {
+            fut,
+            release: Some(release),
+        }
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        if let (Some(release), Ok(handle)) = (self.release.take(), Handle::try_current()) {
+            handle.spawn(release);
+        }
+    }
 }
 
 impl Future for Lock {
@@ -27,6 +85,45 @@ impl Future for Lock {
     }
 }
 
+// Validity of a held lock measured with the monotonic clock. The expiry in the store is wall
+// clock time, so a jump of the wall clock could otherwise let this instance believe it still
+// holds a lock that another instance already took over.
+pub(crate) struct Lease {
+    valid_until: Instant,
+    ttl: Duration,
+}
+
+impl Lease {
+    // Start the lease before asking the store for the lock, so that it ends no later than the
+    // expiry the store sets.
+    pub(crate) fn start(ttl: Duration) -> Self {
+        Lease {
+            valid_until: Instant::now() + ttl,
+            ttl,
+        }
+    }
+
+    // Refresh the lock in the store and renew the lease from the start of the refresh if it
+    // succeeds. Fails once the lease ends before the refresh succeeded.
+    pub(crate) async fn refresh<F>(&mut self, refresh: F) -> crate::error::Result<()>
+    where
+        F: Future<Output = crate::error::Result<()>>,
+    {
+        let started = Instant::now();
+        tokio::select! {
+            result = refresh => {
+                result?;
+                self.valid_until = started + self.ttl;
+                Ok(())
+            }
+            _ = sleep_until(self.valid_until) => Err(error::Error::LockRefreshFailed(
+                "lease expired before the lock was refreshed".to_owned(),
+            )),
+        }
+    }
+}
+
+#[allow(clippy::large_enum_variant)]
 pub(crate) enum LockStatus<LOCK> {
     Acquired(JobData, LOCK),
     AlreadyLocked,
@@ -34,25 +131,137 @@ pub(crate) enum LockStatus<LOCK> {
 
 #[async_trait]
 pub(crate) trait Repo {
-    type Lock: Future<Output = error::Result<()>> + Send;
+    type Lock: Future<Output = error::Result<()>> + Send + Unpin;
     // Transactionally create job config entry if it does not exist.
     async fn create(&mut self, data: JobData) -> error::Result<()>;
     // Obtain job data by name without locking
     async fn get(&mut self, name: JobName) -> error::Result<Option<JobData>>;
-    // Save state without unlocking so jobs can do intermediate commits.
-    async fn commit(&mut self, name: JobName, state: Vec<u8>) -> error::Result<()>;
-    // Save the job state after the job ran and release the lock.
+    // Save the job state after the job ran like `save`, but keep the lock, so that the owner
+    // can run the job again without contending for the lock.
+    async fn commit(
+        &mut self,
+        name: JobName,
+        owner: String,
+        last_run: DateTime<Utc>,
+        state: Vec<u8>,
+        state_version: u32,
+        outbox: Option<Outbox>,
+    ) -> error::Result<()>;
+    // Save the job state, written with the given state version, after the job ran and
+    // release the lock. The outbox of the run, if any, is written in the same write. Fails
+    // with `LockRefreshFailed` and leaves the job as it is if the owner lost the lock.
     async fn save(
         &mut self,
         name: JobName,
+        owner: String,
         last_run: DateTime<Utc>,
         state: Vec<u8>,
+        state_version: u32,
+        outbox: Option<Outbox>,
+    ) -> error::Result<()>;
+    // Set or clear the approval of the job's due run.
+    async fn set_approval(
+        &mut self,
+        name: JobName,
+        approval: Option<Approval>,
     ) -> error::Result<()>;
+    // Approve the due run if it is pending approval. Returns whether it was pending.
+    async fn approve(&mut self, name: JobName, run_id: RunId) -> error::Result<bool>;
+    // Request a run of the job outside of its schedule, or withdraw the request with `None`.
+    async fn request_run(
+        &mut self,
+        name: JobName,
+        request: Option<RunRequest>,
+    ) -> error::Result<()>;
+    // Record the latest heartbeat of the job's running run, or clear it with `None` after the
+    // run.
+    async fn heartbeat(&mut self, name: JobName, at: Option<DateTime<Utc>>) -> error::Result<()>;
+    // Archive the job or bring it back from the archive.
+    async fn set_archived(&mut self, name: JobName, archived: bool) -> error::Result<()>;
+    // Pause the job or resume it, keeping its state and schedule.
+    async fn set_enabled(&mut self, name: JobName, enabled: bool) -> error::Result<()>;
+    // Switch the cluster-wide maintenance mode on or off.
+    async fn set_maintenance(&mut self, on: bool) -> error::Result<()>;
+    // Whether the cluster-wide maintenance mode is on.
+    async fn maintenance(&mut self) -> error::Result<bool>;
+    // Record the presence of an incarnation of an instance, returning the presence recorded
+    // before for the same instance id, if any.
+    async fn announce_instance(
+        &mut self,
+        presence: InstancePresence,
+    ) -> error::Result<Option<InstancePresence>>;
     // Get the job data if the lock can be obtained. Return job data and the lock future.
+    // A lock is exclusive, even to the owner already holding it.
     async fn lock(
         &mut self,
         name: JobName,
         owner: String,
         ttl: Duration,
     ) -> error::Result<LockStatus<Self::Lock>>;
+    // Release the lock if the given owner still holds it, without touching the job state.
+    // Returns whether the owner held the lock.
+    async fn unlock(&mut self, name: JobName, owner: String) -> error::Result<bool>;
+    // Pin the job to the owner until the given time, unless another owner holds a pin that
+    // has not expired yet. Returns whether the owner holds the pin.
+    async fn pin(
+        &mut self,
+        name: JobName,
+        owner: String,
+        until: DateTime<Utc>,
+    ) -> error::Result<bool>;
+    // Current time of the store's own clock, `None` if the store has none, e.g. because it
+    // is embedded into this process.
+    async fn server_time(&mut self) -> error::Result<Option<DateTime<Utc>>>;
+    // Guarantees of the store, which may depend on its deployment, e.g. a replica set.
+    async fn capabilities(&mut self) -> error::Result<RepoCapabilities>;
+    // Names of the indexes the repo relies on that are missing in the store, none for stores
+    // without indexes.
+    async fn missing_indexes(&mut self) -> error::Result<Vec<String>>;
+    // Append a finished run to the run history of the job.
+    async fn record_run(&mut self, record: RunRecord) -> error::Result<()>;
+    // Obtain the most recent runs of a job, newest first.
+    async fn runs(&mut self, name: JobName, limit: usize) -> error::Result<Vec<RunRecord>>;
+    // Obtain the data of all jobs without locking.
+    async fn list(&mut self) -> error::Result<Vec<JobData>>;
+    // Obtain the data of the jobs matching the filter without locking.
+    async fn list_matching(&mut self, filter: &JobFilter) -> error::Result<Vec<JobData>>;
+    // Obtain the data of at most `limit` jobs that are enabled, not archived and whose schedule
+    // fired since their last run, the longest due first.
+    async fn due_jobs(&mut self, now: DateTime<Utc>, limit: usize) -> error::Result<Vec<JobData>>;
+    // Copy all job documents and the run history.
+    async fn snapshot(&mut self) -> error::Result<Snapshot>;
+    // Write the jobs and runs of the snapshot, replacing existing documents of the same name
+    // or run id. Restored jobs are unlocked.
+    async fn restore(&mut self, snapshot: Snapshot) -> error::Result<()>;
+    // Delete the job document including its state. Returns whether the document existed.
+    async fn delete(&mut self, name: JobName) -> error::Result<bool>;
+    // Create the renamed copy of the job and archive the job under its old name, in one
+    // transaction if the store supports them, else with `rename_in_steps`.
+    async fn rename(&mut self, old: JobName, renamed: JobData) -> error::Result<()>;
+    // Delete the whole run history of the job. Returns the number of deleted runs.
+    async fn delete_runs(&mut self, name: JobName) -> error::Result<u64>;
+    // Delete the runs of all jobs that finished before the given time. Returns the number of
+    // deleted runs.
+    async fn prune_runs(&mut self, before: DateTime<Utc>) -> error::Result<u64>;
+}
+
+// Rename without a transaction: the copy is created first and removed again if archiving the
+// job under its old name fails. A crash in between leaves both jobs active.
+pub(crate) async fn rename_in_steps<R: Repo + ?Sized>(
+    repo: &mut R,
+    old: JobName,
+    renamed: JobData,
+) -> error::Result<()> {
+    let new = renamed.name.clone();
+    repo.create(renamed).await?;
+    if let Err(e) = repo.set_archived(old, true).await {
+        if let Err(e) = repo.delete(new.clone()).await {
+            warn!(
+                "removing the copy {:?} after failing to rename failed: {}",
+                new, e
+            );
+        }
+        return Err(e);
+    }
+    Ok(())
 }
@@ -1,27 +1,146 @@
-use super::{Lock, LockStatus, Repo};
+use super::{rename_in_steps, Lease, Lock, LockStatus, Repo, RepoCapabilities};
 use crate::error::{Error, Result};
-use crate::job::JobData;
+use crate::instance::InstancePresence;
+use crate::job::{Approval, JobData, Outbox, Pin, RunRequest};
 use crate::schedule::Schedule;
-use crate::JobName;
+use crate::{
+    JobFilter, JobMetadata, JobName, RunEvent, RunId, RunOutcome, RunRecord, SkipReason, Snapshot,
+};
 use async_trait::async_trait;
 use base64::engine::general_purpose::STANDARD;
 use base64::Engine;
-use chrono::{DateTime, Utc};
-use futures::FutureExt;
-use log::trace;
-use mongodb::bson::doc;
-use mongodb::options::{FindOneAndUpdateOptions, ReturnDocument, UpdateOptions};
-use mongodb::Client;
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use futures::{FutureExt, TryStreamExt};
+use log::{trace, warn};
+use mongodb::bson::spec::BinarySubtype;
+use mongodb::bson::{doc, to_bson, Binary, Bson, DateTime as BsonDateTime, Document};
+use mongodb::gridfs::{FilesCollectionDocument, GridFsBucket};
+use mongodb::options::GridFsBucketOptions;
+use mongodb::options::{
+    FindOneAndReplaceOptions, FindOneAndUpdateOptions, FindOneOptions, FindOptions, ReadPreference,
+    ReplaceOptions, ReturnDocument, SelectionCriteria, UpdateOptions,
+};
+use mongodb::{Client, IndexModel};
 use serde::{Deserialize, Serialize};
 use std::str::FromStr;
-use std::time::{Duration, UNIX_EPOCH};
+use std::time::Duration;
 use tokio::time::sleep;
 
+/// Repo keeping one document per job in the given collection, the run history in
+/// `<collection>_runs` and cluster-wide settings in `<collection>_settings`.
+///
+/// The field names of the documents are a stability guarantee, so that other tools can query
+/// them and the names can be checked against naming conventions: fields are never renamed or
+/// removed, only added, with defaults for documents written before. Changes of the content of
+/// a field come with a new [`DocumentFormat`]. The job documents have these fields:
+///
+/// | Field            | Content                                                         |
+/// |------------------|-----------------------------------------------------------------|
+/// | `_id`            | job name                                                        |
+/// | `format`         | [`DocumentFormat`] version, 1 or 2                              |
+/// | `check_interval` | seconds                                                         |
+/// | `lock_ttl`       | seconds                                                         |
+/// | `state`          | depends on the format, or `{spilled}` for spilled states        |
+/// | `state_version`  | version of the state                                            |
+/// | `schedule`       | depends on the format                                           |
+/// | `enabled`        | bool                                                            |
+/// | `last_run`       | depends on the format                                           |
+/// | `next_run`       | as `last_run`, null if the schedule never fires again           |
+/// | `owner`          | instance holding the lock, empty if unlocked                    |
+/// | `expires`        | when the lock expires, as `last_run`, or 0 if unlocked          |
+/// | `version`        | 0                                                               |
+/// | `metadata`       | `{description, owner_team, labels}`                             |
+/// | `approval`       | `{run_id, approved}` of a run waiting for approval, or null     |
+/// | `dry_run`        | bool, whether the requested run is a dry run                    |
+/// | `as_of`          | backfill time of the requested run, as `last_heartbeat`         |
+/// | `trace_parent`   | W3C traceparent of the requested run, or null                   |
+/// | `triggered`      | bool, whether the requested run was triggered                   |
+/// | `last_heartbeat` | depends on the format, or null                                  |
+/// | `outbox`         | `{run_id, events}` of the last run, or null                     |
+/// | `archived`       | bool, absent in documents written before archiving existed      |
+/// | `pinned_to`      | instance the job is pinned to, or null                          |
+/// | `pinned_until`   | when the pin expires, as `last_heartbeat`, or null              |
+///
+/// The run documents have the fields `_id` (run id), `job`, `instance`, `started` and
+/// `finished` (milliseconds since the epoch), `outcome`, `error`, `log`, `log_truncated`,
+/// `dry_run`, `as_of`, `trace_parent`, `events` and `result`.
 #[derive(Clone)]
 pub struct MongoRepo {
     client: Client,
     database: String,
     collection: String,
+    read_preference: Option<ReadPreference>,
+    document_format: DocumentFormat,
+    spill_threshold: Option<usize>,
+}
+
+/// Layout of the job documents. Both formats are read, so a collection can be switched to
+/// another format while instances are running and migrated with
+/// [`MongoRepo::migrate_documents`].
+///
+/// Releases before the structured format only read the legacy one. To switch a collection,
+/// first upgrade all instances using it, then opt in with [`MongoRepo::with_document_format`]
+/// and call [`MongoRepo::migrate_documents`] from one of them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DocumentFormat {
+    /// Format 1: the schedule as cron string, the state base64 encoded, the last run and
+    /// lock expiry in seconds and the other times in milliseconds since the epoch. The
+    /// truncation to seconds can make schedules firing every few seconds skip or repeat a
+    /// time.
+    #[default]
+    Legacy,
+    /// Format 2: the schedule as sub-document `{cron}`, the state as binary and all times as
+    /// dates with millisecond precision, so that the database can query them, e.g. for all
+    /// jobs that last ran before a given time.
+    Structured,
+}
+
+impl DocumentFormat {
+    fn version(self) -> i32 {
+        match self {
+            DocumentFormat::Legacy => 1,
+            DocumentFormat::Structured => 2,
+        }
+    }
+    fn schedule(self, schedule: &Schedule) -> Bson {
+        let cron: String = schedule.clone().into();
+        match self {
+            DocumentFormat::Legacy => Bson::String(cron),
+            DocumentFormat::Structured => Bson::Document(doc! {"cron": cron}),
+        }
+    }
+    fn state(self, state: &[u8]) -> Bson {
+        match self {
+            DocumentFormat::Legacy => Bson::String(STANDARD.encode(state)),
+            DocumentFormat::Structured => Bson::Binary(Binary {
+                subtype: BinarySubtype::Generic,
+                bytes: state.to_vec(),
+            }),
+        }
+    }
+    fn timestamp(self, timestamp: DateTime<Utc>) -> Bson {
+        match self {
+            DocumentFormat::Legacy => Bson::Int64(timestamp.timestamp()),
+            DocumentFormat::Structured => Bson::DateTime(mongodb::bson::DateTime::from_millis(
+                timestamp.timestamp_millis(),
+            )),
+        }
+    }
+    // The times the legacy format kept in milliseconds rather than seconds.
+    fn time(self, time: DateTime<Utc>) -> Bson {
+        match self {
+            DocumentFormat::Legacy => Bson::Int64(time.timestamp_millis()),
+            DocumentFormat::Structured => {
+                Bson::DateTime(BsonDateTime::from_millis(time.timestamp_millis()))
+            }
+        }
+    }
+    // Next time the schedule fires after the last run, null if it never fires again.
+    fn next_run(self, schedule: &Schedule, last_run: DateTime<Utc>) -> Bson {
+        schedule
+            .next_after(&last_run)
+            .map_or(Bson::Null, |next_run| self.timestamp(next_run))
+    }
 }
 
 impl MongoRepo {
@@ -34,37 +153,399 @@ impl MongoRepo {
             client,
             database: database.into(),
             collection: collection.into(),
+            read_preference: None,
+            document_format: DocumentFormat::default(),
+            spill_threshold: None,
+        }
+    }
+    /// Route the frequent polling reads (due checks and history queries) according to the
+    /// given read preference, e.g. to secondaries with a tolerated staleness. Lock acquisition
+    /// and all writes always go to the primary.
+    pub fn with_read_preference(mut self, read_preference: ReadPreference) -> Self {
+        self.read_preference = Some(read_preference);
+        self
+    }
+    /// Write job documents in the given format. Defaults to [`DocumentFormat::Legacy`], which
+    /// all releases read.
+    pub fn with_document_format(mut self, document_format: DocumentFormat) -> Self {
+        self.document_format = document_format;
+        self
+    }
+    /// Store states larger than `threshold` bytes in the GridFS bucket `<collection>_state`
+    /// and only a reference to them in the job documents, keeping the documents small and
+    /// locking fast. Spilled states are read by all repos on the collection, whether they
+    /// spill or not, but only repos that spill delete the states they replace.
+    pub fn with_state_spillover(mut self, threshold: usize) -> Self {
+        self.spill_threshold = Some(threshold);
+        self
+    }
+    /// Rewrite the job documents written in another format in the format of this repo and
+    /// return how many were rewritten. Documents changed while migrating are left as they
+    /// are, so call it until it returns 0.
+    pub async fn migrate_documents(&self) -> Result<u64> {
+        let format = self.document_format;
+        let outdated: Vec<JobDto> = self
+            .jobs_collection()
+            .find(doc! {"format": {"$ne": format.version()}}, None)
+            .await
+            .map_err(|e| Error::Repo(e.to_string()))?
+            .try_collect()
+            .await
+            .map_err(|e| Error::Repo(e.to_string()))?;
+        let mut migrated = 0;
+        for dto in outdated {
+            let filter_doc = doc! {
+                "_id": dto._id.as_str(),
+                "state": dto.state.clone(),
+                "last_run": dto.last_run.clone(),
+                "as_of": dto.as_of.clone(),
+                "last_heartbeat": dto.last_heartbeat.clone(),
+                "pinned_until": dto.pinned_until.clone(),
+            };
+            // Spilled states stay where they are.
+            let spilled = spilled_state(&dto.state).is_some();
+            let state = dto.state.clone();
+            let jdata = self.load(dto).await?;
+            let update_doc = doc! { "$set": doc! {
+                "format": format.version(),
+                "schedule": format.schedule(&jdata.schedule),
+                "state": if spilled { state } else { format.state(&jdata.state) },
+                "last_run": format.timestamp(jdata.last_run),
+                "next_run": format.next_run(&jdata.schedule, jdata.last_run),
+                "as_of": jdata.requested.and_then(|request| request.as_of).map(|as_of| format.time(as_of)),
+                "last_heartbeat": jdata.last_heartbeat.map(|heartbeat| format.time(heartbeat)),
+                "pinned_until": jdata.pin.map(|pin| format.time(pin.until)),
+            }};
+            migrated += self
+                .jobs_collection()
+                .update_one(filter_doc, update_doc, None)
+                .await
+                .map_err(|e| Error::Repo(e.to_string()))?
+                .modified_count;
+        }
+        Ok(migrated)
+    }
+    /// Create the index that selects the due jobs by their next run, for the manager and for
+    /// external monitoring of upcoming runs. Creating an existing index does nothing.
+    pub async fn create_indexes(&self) -> Result<()> {
+        let index = IndexModel::builder().keys(due_index_keys()).build();
+        self.jobs_collection()
+            .create_index(index, None)
+            .await
+            .map(|_| ())
+            .map_err(|e| Error::Repo(e.to_string()))
+    }
+    fn read_criteria(&self) -> Option<SelectionCriteria> {
+        self.read_preference
+            .clone()
+            .map(SelectionCriteria::ReadPreference)
+    }
+}
+
+impl MongoRepo {
+    fn jobs_collection(&self) -> mongodb::Collection<JobDto> {
+        self.client
+            .database(self.database.as_str())
+            .collection::<JobDto>(self.collection.as_str())
+    }
+    // The job documents for reading single fields of them.
+    fn job_documents(&self) -> mongodb::Collection<Document> {
+        self.client
+            .database(self.database.as_str())
+            .collection::<Document>(self.collection.as_str())
+    }
+    // Cluster-wide settings such as the maintenance flag, one document per setting.
+    fn settings_collection(&self) -> mongodb::Collection<Document> {
+        self.client
+            .database(self.database.as_str())
+            .collection::<Document>(format!("{}_settings", self.collection).as_str())
+    }
+    fn runs_collection(&self) -> mongodb::Collection<RunDto> {
+        self.client
+            .database(self.database.as_str())
+            .collection::<RunDto>(format!("{}_runs", self.collection).as_str())
+    }
+    // Large states spilled out of the job documents, named after their jobs.
+    fn state_bucket(&self) -> GridFsBucket {
+        self.client.database(self.database.as_str()).gridfs_bucket(
+            GridFsBucketOptions::builder()
+                .bucket_name(format!("{}_state", self.collection))
+                .build(),
+        )
+    }
+    // The state as stored in the job document, spilling it to the bucket when it is too large.
+    async fn store_state(&self, name: &JobName, state: &[u8]) -> Result<Bson> {
+        match self.spill_threshold {
+            Some(threshold) if state.len() > threshold => {
+                let id = self
+                    .state_bucket()
+                    .upload_from_futures_0_3_reader(name.as_str(), state, None)
+                    .await
+                    .map_err(|e| Error::Repo(e.to_string()))?;
+                trace!(
+                    "spilled {} bytes of state of job {}",
+                    state.len(),
+                    name.as_str()
+                );
+                Ok(Bson::Document(doc! {"spilled": id}))
+            }
+            _ => Ok(self.document_format.state(state)),
+        }
+    }
+    // Delete the spilled states of the job except the one its document refers to, which
+    // readers of the document replaced just now may still be loading.
+    async fn drop_spilled_states(&self, name: &JobName, keep: &Bson) -> Result<()> {
+        if self.spill_threshold.is_none() {
+            return Ok(());
+        }
+        let bucket = self.state_bucket();
+        let files: Vec<FilesCollectionDocument> = bucket
+            .find(doc! {"filename": name.as_str()}, None)
+            .await
+            .map_err(|e| Error::Repo(e.to_string()))?
+            .try_collect()
+            .await
+            .map_err(|e| Error::Repo(e.to_string()))?;
+        for file in files {
+            if Some(&file.id) != spilled_state(keep) {
+                bucket
+                    .delete(file.id)
+                    .await
+                    .map_err(|e| Error::Repo(e.to_string()))?;
+            }
+        }
+        Ok(())
+    }
+    // Read the job data of the document, loading its state from the bucket if it was spilled.
+    async fn load(&self, mut dto: JobDto) -> Result<JobData> {
+        if let Some(id) = spilled_state(&dto.state) {
+            let mut state = Vec::new();
+            self.state_bucket()
+                .download_to_futures_0_3_writer(id.clone(), &mut state)
+                .await
+                .map_err(|e| {
+                    Error::Repo(format!("job {}: loading spilled state: {}", dto._id, e))
+                })?;
+            dto.state = Bson::Binary(Binary {
+                subtype: BinarySubtype::Generic,
+                bytes: state,
+            });
+        }
+        JobData::try_from(dto)
+    }
+    async fn load_all(&self, dtos: Vec<JobDto>) -> Result<Vec<JobData>> {
+        let mut jobs = Vec::with_capacity(dtos.len());
+        for dto in dtos {
+            jobs.push(self.load(dto).await?);
+        }
+        Ok(jobs)
+    }
+    // Store the state and last run of the job after it ran if the owner still holds the lock,
+    // releasing it if `release`. The whole document is written in the format of this repo, so
+    // that its `format` stays true.
+    #[allow(clippy::too_many_arguments)]
+    async fn store_run(
+        &self,
+        name: JobName,
+        owner: String,
+        last_run: DateTime<Utc>,
+        state: Vec<u8>,
+        state_version: u32,
+        outbox: Option<Outbox>,
+        release: bool,
+    ) -> Result<()> {
+        let opts: UpdateOptions = UpdateOptions::builder().upsert(false).build();
+        let format = self.document_format;
+        let state = self.store_state(&name, &state).await?;
+        let outbox = outbox
+            .map(|outbox| to_bson(&outbox).map_err(|e| Error::Serialization(e.to_string())))
+            .transpose()?;
+
+        // The times written elsewhere are rewritten as read, unless they changed meanwhile.
+        loop {
+            let job = self
+                .job_documents()
+                .find_one(
+                    doc! {"_id": name.as_str()},
+                    FindOneOptions::builder()
+                        .projection(doc! {
+                            "owner": 1, "schedule": 1, "as_of": 1, "last_heartbeat": 1,
+                            "pinned_until": 1,
+                        })
+                        .build(),
+                )
+                .await
+                .map_err(|e| Error::Repo(e.to_string()))?
+                .ok_or_else(|| Error::NotFound(name.clone()))?;
+            if job.get_str("owner").ok() != Some(owner.as_str()) {
+                return Err(Error::LockRefreshFailed(
+                    "lock taken over by another owner".to_owned(),
+                ));
+            }
+            let read = |field: &str| job.get(field).cloned().unwrap_or(Bson::Null);
+            let schedule = parse_schedule(name.as_str(), &read("schedule"))?;
+            let filter_doc = doc! {
+                "_id": name.as_str(),
+                "owner": owner.as_str(),
+                "schedule": read("schedule"),
+                "as_of": read("as_of"),
+                "last_heartbeat": read("last_heartbeat"),
+                "pinned_until": read("pinned_until"),
+            };
+            let rewrite = |field: &str| parse_time(&read(field)).map(|time| format.time(time));
+            let mut fields = doc! {
+                "format": format.version(),
+                "schedule": format.schedule(&schedule),
+                "state": state.clone(),
+                "state_version": state_version,
+                "last_run": format.timestamp(last_run),
+                "next_run": format.next_run(&schedule, last_run),
+                "as_of": rewrite("as_of"),
+                "last_heartbeat": rewrite("last_heartbeat"),
+                "pinned_until": rewrite("pinned_until"),
+            };
+            if release {
+                fields.insert("owner", String::default());
+                fields.insert("expires", 0);
+            }
+            if let Some(outbox) = &outbox {
+                fields.insert("outbox", outbox.clone());
+            }
+            let update_doc = doc! { "$set": fields };
+
+            let res = self
+                .jobs_collection()
+                .update_one(filter_doc, update_doc, opts.clone())
+                .await
+                .map_err(|e| Error::Repo(e.to_string()))?;
+            if res.matched_count > 0 {
+                return self.drop_spilled_states(&name, &state).await;
+            }
         }
     }
+    // The server's description of itself and its deployment.
+    async fn hello(&self) -> Result<Document> {
+        self.client
+            .database(self.database.as_str())
+            .run_command(doc! {"hello": 1}, None)
+            .await
+            .map_err(|e| Error::Repo(e.to_string()))
+    }
 }
 
+// Schedule, state and last run are kept as BSON to read documents of all formats.
 #[derive(Clone, Serialize, Debug, Deserialize, PartialEq)]
 struct JobDto {
     pub _id: String,
+    #[serde(default = "legacy_format")]
+    pub format: i32,
     pub check_interval: u64,
     pub lock_ttl: u64,
-    pub state: String,
-    pub schedule: String,
+    pub state: Bson,
+    #[serde(default)]
+    pub state_version: u32,
+    pub schedule: Bson,
     pub enabled: bool,
-    pub last_run: u64,
+    pub last_run: Bson,
+    // Kept for selecting the due jobs in the database, absent in documents written before.
+    #[serde(default)]
+    pub next_run: Bson,
     pub owner: String,
-    pub expires: i64,
+    pub expires: Bson,
     pub version: i8,
+    #[serde(default)]
+    pub metadata: JobMetadata,
+    #[serde(default)]
+    pub approval: Option<Approval>,
+    #[serde(default)]
+    pub dry_run: bool,
+    // As-of time of a requested run.
+    #[serde(default)]
+    pub as_of: Option<Bson>,
+    #[serde(default)]
+    pub trace_parent: Option<String>,
+    #[serde(default)]
+    pub triggered: bool,
+    // Latest heartbeat of the running run.
+    #[serde(default)]
+    pub last_heartbeat: Option<Bson>,
+    #[serde(default)]
+    pub outbox: Option<Outbox>,
+    #[serde(default)]
+    pub archived: bool,
+    // Instance the job is pinned to and when the pin expires.
+    #[serde(default)]
+    pub pinned_to: Option<String>,
+    #[serde(default)]
+    pub pinned_until: Option<Bson>,
+}
+
+fn legacy_format() -> i32 {
+    DocumentFormat::Legacy.version()
+}
+
+// A time in seconds since the epoch as written by the legacy format, or as date.
+fn parse_timestamp(bson: &Bson) -> Option<DateTime<Utc>> {
+    match bson {
+        Bson::Int32(secs) => DateTime::<Utc>::from_timestamp(*secs as i64, 0),
+        Bson::Int64(secs) => DateTime::<Utc>::from_timestamp(*secs, 0),
+        Bson::DateTime(date) => DateTime::<Utc>::from_timestamp_millis(date.timestamp_millis()),
+        _ => None,
+    }
 }
 
-impl From<JobData> for JobDto {
-    fn from(value: JobData) -> Self {
+// A time in milliseconds since the epoch as written by the legacy format, or as date.
+fn parse_time(bson: &Bson) -> Option<DateTime<Utc>> {
+    match bson {
+        Bson::Int32(millis) => DateTime::<Utc>::from_timestamp_millis(*millis as i64),
+        Bson::Int64(millis) => DateTime::<Utc>::from_timestamp_millis(*millis),
+        Bson::DateTime(date) => DateTime::<Utc>::from_timestamp_millis(date.timestamp_millis()),
+        _ => None,
+    }
+}
+
+// Conditions on the lock expiry matching both formats, whose expiries the database does not
+// compare with each other. Unlocked documents have 0 in both formats.
+fn expires(op: &str, time: DateTime<Utc>) -> [Document; 2] {
+    [
+        doc! {"expires": {op: DocumentFormat::Legacy.timestamp(time)}},
+        doc! {"expires": {op: DocumentFormat::Structured.timestamp(time)}},
+    ]
+}
+
+// Keys of the index selecting the due jobs, see `MongoRepo::create_indexes`.
+fn due_index_keys() -> Document {
+    doc! {"enabled": 1, "next_run": 1}
+}
+
+impl JobDto {
+    fn new(value: JobData, format: DocumentFormat) -> Self {
+        let requested = value.requested.unwrap_or_default();
         Self {
             _id: value.name.0,
+            format: format.version(),
             check_interval: value.check_interval.as_secs(),
             lock_ttl: value.lock_ttl.as_secs(),
-            state: STANDARD.encode(&value.state),
-            schedule: value.schedule.into(),
+            state: format.state(&value.state),
+            state_version: value.state_version,
+            schedule: format.schedule(&value.schedule),
             enabled: value.enabled,
-            last_run: value.last_run.timestamp() as u64,
+            next_run: format.next_run(&value.schedule, value.last_run),
+            last_run: format.timestamp(value.last_run),
             owner: "".to_string(),
-            expires: 0,
+            expires: Bson::Int64(0),
             version: 0,
+            metadata: value.metadata,
+            approval: value.approval,
+            dry_run: requested.dry_run,
+            as_of: requested.as_of.map(|as_of| format.time(as_of)),
+            trace_parent: requested.trace_parent,
+            triggered: requested.triggered,
+            last_heartbeat: value.last_heartbeat.map(|heartbeat| format.time(heartbeat)),
+            outbox: None,
+            archived: value.archived,
+            pinned_to: value.pin.as_ref().map(|pin| pin.owner.clone()),
+            pinned_until: value.pin.map(|pin| format.time(pin.until)),
         }
     }
 }
@@ -73,26 +554,254 @@ impl TryFrom<JobDto> for JobData {
     type Error = Error;
 
     fn try_from(value: JobDto) -> std::result::Result<Self, Self::Error> {
-        let schedule = Schedule::from_str(value.schedule.as_str())?;
-        let state = STANDARD.decode(&value.state).map_err(|_e| Error::TODO)?;
+        let invalid = |field: &str, bson: &Bson| {
+            Error::Serialization(format!("job {}: invalid {} {}", value._id, field, bson))
+        };
+        let schedule = parse_schedule(&value._id, &value.schedule)?;
+        let state = match &value.state {
+            Bson::String(encoded) => STANDARD
+                .decode(encoded)
+                .map_err(|e| Error::CorruptState(format!("job {}: {}", value._id, e)))?,
+            Bson::Binary(binary) => binary.bytes.clone(),
+            other => return Err(invalid("state", other)),
+        };
+        let last_run =
+            parse_timestamp(&value.last_run).ok_or_else(|| invalid("last run", &value.last_run))?;
+        let expires =
+            parse_timestamp(&value.expires).ok_or_else(|| invalid("expiry", &value.expires))?;
+        let next_run = match &value.next_run {
+            Bson::Int32(secs) => DateTime::<Utc>::from_timestamp(*secs as i64, 0),
+            Bson::Int64(secs) => DateTime::<Utc>::from_timestamp(*secs, 0),
+            Bson::DateTime(date) => DateTime::<Utc>::from_timestamp_millis(date.timestamp_millis()),
+            // Written before the next run was kept, or the schedule never fires again.
+            _ => schedule.next_after(&last_run),
+        };
         Ok(Self {
             name: JobName(value._id),
             check_interval: Duration::from_secs(value.check_interval),
             lock_ttl: Duration::from_secs(value.lock_ttl),
             state,
+            state_version: value.state_version,
             schedule,
             enabled: value.enabled,
-            last_run: DateTime::<Utc>::from(UNIX_EPOCH + Duration::from_secs(value.last_run)),
+            last_run,
+            next_run,
+            owner: value.owner,
+            expires,
+            metadata: value.metadata,
+            approval: value.approval,
+            requested: RunRequest::from_fields(
+                value.dry_run,
+                value.as_of.as_ref().and_then(parse_time),
+                value.trace_parent,
+                value.triggered,
+            ),
+            last_heartbeat: value.last_heartbeat.as_ref().and_then(parse_time),
+            archived: value.archived,
+            pin: Pin::from_fields(
+                value.pinned_to,
+                value.pinned_until.as_ref().and_then(parse_time),
+            ),
         })
     }
 }
 
+#[derive(Clone, Serialize, Debug, Deserialize, PartialEq)]
+struct RunDto {
+    pub _id: String,
+    pub job: String,
+    pub instance: String,
+    pub started: i64,
+    pub finished: i64,
+    pub outcome: String,
+    pub error: Option<String>,
+    pub log: String,
+    pub log_truncated: bool,
+    #[serde(default)]
+    pub dry_run: bool,
+    #[serde(default)]
+    pub as_of: Option<i64>,
+    #[serde(default)]
+    pub trace_parent: Option<String>,
+    #[serde(default)]
+    pub events: Vec<RunEvent>,
+    #[serde(default)]
+    pub result: Option<Binary>,
+}
+
+impl From<RunRecord> for RunDto {
+    fn from(value: RunRecord) -> Self {
+        let (outcome, error) = match value.outcome {
+            RunOutcome::Succeeded => ("succeeded", None),
+            RunOutcome::Failed(e) => ("failed", Some(e)),
+            RunOutcome::Interrupted(e) => ("interrupted", Some(e)),
+            RunOutcome::Aborted => ("aborted", None),
+            RunOutcome::Skipped(reason) => ("skipped", Some(reason.to_string())),
+        };
+        Self {
+            _id: value.run_id.0,
+            job: value.name.0,
+            instance: value.instance,
+            started: value.started.timestamp_millis(),
+            finished: value.finished.timestamp_millis(),
+            outcome: outcome.to_string(),
+            error,
+            log: value.log,
+            log_truncated: value.log_truncated,
+            dry_run: value.dry_run,
+            as_of: value.as_of.map(|as_of| as_of.timestamp_millis()),
+            trace_parent: value.trace_parent,
+            events: value.events,
+            result: value.result.map(|bytes| Binary {
+                subtype: BinarySubtype::Generic,
+                bytes,
+            }),
+        }
+    }
+}
+
+impl TryFrom<RunDto> for RunRecord {
+    type Error = Error;
+
+    fn try_from(value: RunDto) -> std::result::Result<Self, Self::Error> {
+        let error = value.error.unwrap_or_default();
+        let outcome =
+            match value.outcome.as_str() {
+                "succeeded" => RunOutcome::Succeeded,
+                "failed" => RunOutcome::Failed(error),
+                "interrupted" => RunOutcome::Interrupted(error),
+                "aborted" => RunOutcome::Aborted,
+                "skipped" => RunOutcome::Skipped(parse_skip_reason(&error).ok_or_else(|| {
+                    Error::Serialization(format!("unknown skip reason '{}'", error))
+                })?),
+                other => {
+                    return Err(Error::Serialization(format!(
+                        "unknown run outcome '{}'",
+                        other
+                    )))
+                }
+            };
+        let timestamp = |millis| {
+            DateTime::<Utc>::from_timestamp_millis(millis)
+                .ok_or_else(|| Error::Serialization(format!("invalid run timestamp {}", millis)))
+        };
+        Ok(Self {
+            run_id: RunId(value._id),
+            name: JobName(value.job),
+            instance: value.instance,
+            started: timestamp(value.started)?,
+            finished: timestamp(value.finished)?,
+            outcome,
+            log: value.log,
+            log_truncated: value.log_truncated,
+            dry_run: value.dry_run,
+            as_of: value.as_of.map(timestamp).transpose()?,
+            trace_parent: value.trace_parent,
+            events: value.events,
+            result: value.result.map(|result| result.bytes),
+        })
+    }
+}
+
+// Id of the file in the state bucket if the state was spilled there.
+fn spilled_state(state: &Bson) -> Option<&Bson> {
+    match state {
+        Bson::Document(pointer) => pointer.get("spilled"),
+        _ => None,
+    }
+}
+
+fn parse_schedule(name: &str, schedule: &Bson) -> Result<Schedule> {
+    let cron = match schedule {
+        Bson::String(cron) => Some(cron.as_str()),
+        Bson::Document(schedule) => schedule.get_str("cron").ok(),
+        _ => None,
+    };
+    match cron {
+        Some(cron) => Ok(Schedule::from_str(cron)?),
+        None => Err(Error::Serialization(format!(
+            "job {}: invalid schedule {}",
+            name, schedule
+        ))),
+    }
+}
+
+// Clear owner and expiry if the owner still holds the lock.
+async fn release(
+    collection: &mongodb::Collection<JobDto>,
+    name: &JobName,
+    owner: &str,
+) -> Result<bool> {
+    let filter_doc = doc! {"_id":name.as_str(), "owner": owner};
+    let update_doc = doc! { "$set": doc! { "owner": "", "expires": 0 }};
+    let result = collection
+        .update_one(filter_doc, update_doc, None)
+        .await
+        .map_err(|e| Error::Repo(e.to_string()))?;
+    Ok(result.matched_count > 0)
+}
+
+fn parse_skip_reason(s: &str) -> Option<SkipReason> {
+    [
+        SkipReason::LockedElsewhere,
+        SkipReason::QuotaExhausted,
+        SkipReason::Disabled,
+        SkipReason::Rejected,
+        SkipReason::Maintenance,
+        SkipReason::Gated,
+        SkipReason::ClockSkew,
+        SkipReason::Shed,
+    ]
+    .into_iter()
+    .find(|reason| reason.as_str() == s)
+}
+
+// Query for the criteria of the filter that can be evaluated by the database. Whether a job is
+// overdue depends on its schedule and is checked after the query.
+fn filter_doc(filter: &JobFilter, now: DateTime<Utc>) -> Document {
+    let mut query = Document::new();
+    // Documents written before archiving existed lack the field.
+    match filter.archived {
+        Some(true) => query.insert("archived", true),
+        _ => query.insert("archived", doc! {"$ne": true}),
+    };
+    if let Some(enabled) = filter.enabled {
+        query.insert("enabled", enabled);
+    }
+    if let Some((key, value)) = &filter.label {
+        query.insert(format!("metadata.labels.{}", key), value.as_str());
+    }
+    if let Some(prefix) = &filter.namespace {
+        let escaped: String = prefix
+            .chars()
+            .flat_map(|c| {
+                let escape = "\\^$.|?*+()[]{}".contains(c);
+                escape.then_some('\\').into_iter().chain([c])
+            })
+            .collect();
+        query.insert("_id", doc! {"$regex": format!("^{}", escaped)});
+    }
+    match filter.locked {
+        Some(true) => {
+            query.insert("owner", doc! {"$ne": ""});
+            query.insert("$or", expires("$gt", now).to_vec());
+        }
+        Some(false) => {
+            let mut unlocked = vec![doc! {"owner": ""}];
+            unlocked.extend(expires("$lte", now));
+            query.insert("$or", unlocked);
+        }
+        None => {}
+    }
+    query
+}
+
 #[async_trait]
 impl Repo for MongoRepo {
     type Lock = Lock;
 
     async fn create(&mut self, data: JobData) -> Result<()> {
-        let job: JobDto = data.into();
+        let job = JobDto::new(data, self.document_format);
         self.client
             .database(self.database.as_str())
             .collection::<JobDto>(self.collection.as_str())
@@ -107,51 +816,190 @@ impl Repo for MongoRepo {
             .client
             .database(self.database.as_str())
             .collection::<JobDto>(self.collection.as_str())
-            .find_one(doc! {"_id":name.as_ref().to_string()}, None)
+            .find_one(
+                doc! {"_id":name.as_ref().to_string()},
+                FindOneOptions::builder()
+                    .selection_criteria(self.read_criteria())
+                    .build(),
+            )
             .await
             .map_err(|e| Error::Repo(e.to_string()))?;
 
         match j {
             None => Ok(None),
-            Some(d) => {
-                let jd: Result<JobData> = d.try_into();
-                match jd {
-                    Ok(k) => Ok(Some(k)),
-                    Err(e) => Err(e),
-                }
-            }
+            Some(d) => self.load(d).await.map(Some),
         }
     }
 
-    async fn commit(&mut self, name: JobName, state: Vec<u8>) -> Result<()> {
-        let opts: UpdateOptions = UpdateOptions::builder().upsert(false).build();
-        let update_doc = doc! { "$set": doc! { "state": STANDARD.encode(&state) }};
-        self.client
-            .database(self.database.as_str())
-            .collection::<JobDto>(self.collection.as_str())
-            .update_one(doc! {"_id":name.as_str()}, update_doc, opts)
+    async fn commit(
+        &mut self,
+        name: JobName,
+        owner: String,
+        last_run: DateTime<Utc>,
+        state: Vec<u8>,
+        state_version: u32,
+        outbox: Option<Outbox>,
+    ) -> Result<()> {
+        self.store_run(name, owner, last_run, state, state_version, outbox, false)
             .await
-            .map(|_| Ok(()))
-            .map_err(|e| Error::Repo(e.to_string()))?
     }
 
-    async fn save(&mut self, name: JobName, last_run: DateTime<Utc>, state: Vec<u8>) -> Result<()> {
-        let opts: UpdateOptions = UpdateOptions::builder().upsert(false).build();
+    async fn save(
+        &mut self,
+        name: JobName,
+        owner: String,
+        last_run: DateTime<Utc>,
+        state: Vec<u8>,
+        state_version: u32,
+        outbox: Option<Outbox>,
+    ) -> Result<()> {
+        self.store_run(name, owner, last_run, state, state_version, outbox, true)
+            .await
+    }
 
+    async fn set_approval(&mut self, name: JobName, approval: Option<Approval>) -> Result<()> {
+        let approval = to_bson(&approval).map_err(|e| Error::Serialization(e.to_string()))?;
+        let update_doc = doc! { "$set": doc! { "approval": approval }};
+        self.jobs_collection()
+            .update_one(doc! {"_id":name.as_str()}, update_doc, None)
+            .await
+            .map(|_| ())
+            .map_err(|e| Error::Repo(e.to_string()))
+    }
+
+    async fn approve(&mut self, name: JobName, run_id: RunId) -> Result<bool> {
+        let filter_doc = doc! {
+            "_id": name.as_str(),
+            "approval.run_id": run_id.as_str(),
+            "approval.approved": false,
+        };
+        let update_doc = doc! { "$set": doc! { "approval.approved": true }};
+        self.jobs_collection()
+            .update_one(filter_doc, update_doc, None)
+            .await
+            .map(|res| res.modified_count > 0)
+            .map_err(|e| Error::Repo(e.to_string()))
+    }
+
+    async fn request_run(&mut self, name: JobName, request: Option<RunRequest>) -> Result<()> {
+        let request = request.unwrap_or_default();
         let update_doc = doc! { "$set": doc! {
-            "state": STANDARD.encode(&state),
-            "last_run": last_run.timestamp(),
-            "owner": String::default(),
-            "expires": 0,
+            "dry_run": request.dry_run,
+            "as_of": request.as_of.map(|as_of| self.document_format.time(as_of)),
+            "trace_parent": request.trace_parent,
+            "triggered": request.triggered,
         }};
+        let res = self
+            .jobs_collection()
+            .update_one(doc! {"_id":name.as_str()}, update_doc, None)
+            .await
+            .map_err(|e| Error::Repo(e.to_string()))?;
+        if res.matched_count == 0 {
+            return Err(Error::NotFound(name));
+        }
+        Ok(())
+    }
 
-        self.client
-            .database(self.database.as_str())
-            .collection::<JobDto>(self.collection.as_str())
-            .update_one(doc! {"_id":name.as_str()}, update_doc, opts)
+    async fn heartbeat(&mut self, name: JobName, at: Option<DateTime<Utc>>) -> Result<()> {
+        let update_doc = doc! { "$set": doc! {
+            "last_heartbeat": at.map(|at| self.document_format.time(at)),
+        }};
+        let res = self
+            .jobs_collection()
+            .update_one(doc! {"_id":name.as_str()}, update_doc, None)
             .await
-            .map(|_| Ok(()))
-            .map_err(|e| Error::Repo(e.to_string()))?
+            .map_err(|e| Error::Repo(e.to_string()))?;
+        if res.matched_count == 0 {
+            return Err(Error::NotFound(name));
+        }
+        Ok(())
+    }
+
+    async fn set_archived(&mut self, name: JobName, archived: bool) -> Result<()> {
+        let update_doc = doc! { "$set": doc! { "archived": archived }};
+        let res = self
+            .jobs_collection()
+            .update_one(doc! {"_id":name.as_str()}, update_doc, None)
+            .await
+            .map_err(|e| Error::Repo(e.to_string()))?;
+        if res.matched_count == 0 {
+            return Err(Error::NotFound(name));
+        }
+        Ok(())
+    }
+
+    async fn set_enabled(&mut self, name: JobName, enabled: bool) -> Result<()> {
+        let update_doc = doc! { "$set": doc! { "enabled": enabled }};
+        let res = self
+            .jobs_collection()
+            .update_one(doc! {"_id":name.as_str()}, update_doc, None)
+            .await
+            .map_err(|e| Error::Repo(e.to_string()))?;
+        if res.matched_count == 0 {
+            return Err(Error::NotFound(name));
+        }
+        Ok(())
+    }
+
+    async fn set_maintenance(&mut self, on: bool) -> Result<()> {
+        let opts = UpdateOptions::builder().upsert(true).build();
+        self.settings_collection()
+            .update_one(
+                doc! {"_id": "maintenance"},
+                doc! { "$set": doc! { "on": on }},
+                opts,
+            )
+            .await
+            .map(|_| ())
+            .map_err(|e| Error::Repo(e.to_string()))
+    }
+
+    async fn maintenance(&mut self) -> Result<bool> {
+        let setting = self
+            .settings_collection()
+            .find_one(doc! {"_id": "maintenance"}, None)
+            .await
+            .map_err(|e| Error::Repo(e.to_string()))?;
+        Ok(setting
+            .and_then(|setting| setting.get_bool("on").ok())
+            .unwrap_or_default())
+    }
+
+    async fn announce_instance(
+        &mut self,
+        presence: InstancePresence,
+    ) -> Result<Option<InstancePresence>> {
+        let id = format!("instance:{}", presence.instance);
+        let opts = FindOneAndReplaceOptions::builder()
+            .upsert(true)
+            .return_document(ReturnDocument::Before)
+            .build();
+        let previous = self
+            .settings_collection()
+            .find_one_and_replace(
+                doc! {"_id": &id},
+                doc! {
+                    "_id": &id,
+                    "incarnation": &presence.incarnation,
+                    "started": BsonDateTime::from_millis(presence.started.timestamp_millis()),
+                    "seen": BsonDateTime::from_millis(presence.seen.timestamp_millis()),
+                },
+                opts,
+            )
+            .await
+            .map_err(|e| Error::Repo(e.to_string()))?;
+        let time = |previous: &Document, key| {
+            let millis = previous.get_datetime(key).ok()?.timestamp_millis();
+            DateTime::<Utc>::from_timestamp_millis(millis)
+        };
+        Ok(previous.and_then(|previous| {
+            Some(InstancePresence {
+                instance: presence.instance,
+                incarnation: previous.get_str("incarnation").ok()?.to_owned(),
+                started: time(&previous, "started")?,
+                seen: time(&previous, "seen")?,
+            })
+        }))
     }
 
     async fn lock(
@@ -160,16 +1008,21 @@ impl Repo for MongoRepo {
         owner: String,
         ttl: Duration,
     ) -> Result<LockStatus<Self::Lock>> {
+        let mut lease = Lease::start(ttl);
         let opts = FindOneAndUpdateOptions::builder()
             .return_document(Some(ReturnDocument::After))
             .build();
 
-        let filter_doc = doc! {"_id":name.as_str(), "expires": {"$lt" : Utc::now().timestamp()}  };
-        // let filter_doc = doc! {"_id":name.as_str()  };
+        let now = Utc::now();
+        let filter_doc = doc! {"_id":name.as_str(), "$or": expires("$lt", now).to_vec()};
 
+        let format = self.document_format;
+        let expiry = move |from: DateTime<Utc>| {
+            format.timestamp(from + ChronoDuration::from_std(ttl).unwrap_or_default())
+        };
         let update_doc = doc! { "$set": doc! {
-            "owner": owner,
-            "expires": Utc::now().timestamp() + ttl.as_secs() as i64
+            "owner": owner.as_str(),
+            "expires": expiry(now),
         }};
 
         match self
@@ -183,36 +1036,56 @@ impl Repo for MongoRepo {
                 let name = res._id.clone();
                 let db = self.client.clone();
 
-                let jd: Result<JobData> = res.try_into();
+                let jd = self.load(res).await;
                 let database = self.database.clone();
                 let collection = self.collection.clone();
+                let refresh_owner = owner.clone();
                 match jd {
                     Ok(k) => {
                         let fut = async move {
                             trace!("starting lock refresh");
                             loop {
-                                let refresh_interval = Duration::from_secs(ttl.as_secs() / 2);
-                                sleep(refresh_interval).await;
+                                sleep(ttl / 2).await;
 
                                 let opts: UpdateOptions =
                                     UpdateOptions::builder().upsert(false).build();
-                                let expires = Utc::now().timestamp() + ttl.as_secs() as i64;
-                                let update_doc = doc! { "$set": doc! { "expires": expires }};
-                                match db
+                                let update_doc =
+                                    doc! { "$set": doc! { "expires": expiry(Utc::now()) }};
+                                let jobs = db
                                     .database(database.as_str())
-                                    .collection::<JobDto>(collection.as_str())
-                                    .update_one(doc! {"_id":name.as_str()}, update_doc, opts) // TODO maybe check for owner
-                                    .await
-                                {
-                                    Ok(_) => {}
-                                    Err(e) => return Err(Error::LockRefreshFailed(e.to_string())),
-                                }
+                                    .collection::<JobDto>(collection.as_str());
+                                // Another owner took the lock over if this one no longer holds
+                                // it, e.g. after the clock of this instance jumped.
+                                let filter =
+                                    doc! {"_id": name.as_str(), "owner": refresh_owner.as_str()};
+                                let update =
+                                    jobs.update_one(filter, update_doc, opts).map(|result| {
+                                        match result {
+                                            Ok(res) if res.matched_count == 0 => {
+                                                Err(Error::LockRefreshFailed(
+                                                    "lock taken over by another owner".to_owned(),
+                                                ))
+                                            }
+                                            Ok(_) => Ok(()),
+                                            Err(e) => Err(Error::LockRefreshFailed(e.to_string())),
+                                        }
+                                    });
+                                lease.refresh(update).await?;
                                 trace!("lock refreshed");
                             }
                         }
                         .boxed();
 
-                        let lock = Lock { fut };
+                        let collection = self.jobs_collection();
+                        let name = k.name.clone();
+                        let release = async move {
+                            if let Err(e) = release(&collection, &name, &owner).await {
+                                warn!("releasing lock failed: {}", e);
+                            }
+                        }
+                        .boxed();
+
+                        let lock = Lock::new(fut, release);
                         Ok(LockStatus::Acquired(k, lock))
                     }
                     Err(e) => Err(e),
@@ -225,4 +1098,283 @@ impl Repo for MongoRepo {
             Err(e) => Err(Error::Repo(e.to_string())),
         }
     }
+
+    async fn unlock(&mut self, name: JobName, owner: String) -> Result<bool> {
+        release(&self.jobs_collection(), &name, &owner).await
+    }
+
+    async fn pin(&mut self, name: JobName, owner: String, until: DateTime<Utc>) -> Result<bool> {
+        // The database does not compare the expiries of both formats with each other.
+        let now = Utc::now();
+        let filter = doc! {
+            "_id": name.as_str(),
+            "$or": [
+                {"pinned_to": null},
+                {"pinned_to": owner.as_str()},
+                {"pinned_until": {"$lte": DocumentFormat::Legacy.time(now)}},
+                {"pinned_until": {"$lte": DocumentFormat::Structured.time(now)}},
+            ],
+        };
+        let update_doc = doc! { "$set": doc! {
+            "pinned_to": owner.as_str(),
+            "pinned_until": self.document_format.time(until),
+        }};
+        let res = self
+            .jobs_collection()
+            .update_one(filter, update_doc, None)
+            .await
+            .map_err(|e| Error::Repo(e.to_string()))?;
+        if res.matched_count > 0 {
+            return Ok(true);
+        }
+        // Tell a job pinned to another owner from a missing one.
+        let exists = self
+            .jobs_collection()
+            .count_documents(doc! {"_id": name.as_str()}, None)
+            .await
+            .map_err(|e| Error::Repo(e.to_string()))?;
+        if exists == 0 {
+            return Err(Error::NotFound(name));
+        }
+        Ok(false)
+    }
+
+    async fn server_time(&mut self) -> Result<Option<DateTime<Utc>>> {
+        let reply = self.hello().await?;
+        let time = reply
+            .get_datetime("localTime")
+            .map_err(|e| Error::Repo(format!("server time missing: {}", e)))?;
+        Ok(DateTime::<Utc>::from_timestamp_millis(
+            time.timestamp_millis(),
+        ))
+    }
+
+    async fn capabilities(&mut self) -> Result<RepoCapabilities> {
+        let reply = self.hello().await?;
+        // Transactions and change streams need a replica set or a sharded cluster, whose
+        // routers identify themselves as "isdbgrid".
+        let replicated = reply.contains_key("setName") || reply.get_str("msg") == Ok("isdbgrid");
+        Ok(RepoCapabilities {
+            shared: true,
+            atomic_locks: true,
+            transactions: replicated,
+            watch: replicated,
+            clock: true,
+        })
+    }
+
+    async fn missing_indexes(&mut self) -> Result<Vec<String>> {
+        let keys = due_index_keys();
+        let indexes = self
+            .jobs_collection()
+            .list_indexes(None)
+            .await
+            .map_err(|e| Error::Repo(e.to_string()))?
+            .try_collect::<Vec<IndexModel>>()
+            .await
+            .map_err(|e| Error::Repo(e.to_string()))?;
+        if indexes.iter().any(|index| index.keys == keys) {
+            Ok(Vec::new())
+        } else {
+            Ok(vec!["enabled_1_next_run_1".to_owned()])
+        }
+    }
+
+    async fn record_run(&mut self, record: RunRecord) -> Result<()> {
+        let run: RunDto = record.into();
+        self.runs_collection()
+            .insert_one(&run, None)
+            .await
+            .map(|_| Ok(()))
+            .map_err(|e| Error::Repo(e.to_string()))?
+    }
+
+    async fn runs(&mut self, name: JobName, limit: usize) -> Result<Vec<RunRecord>> {
+        let opts = FindOptions::builder()
+            .sort(doc! {"started": -1})
+            .limit(limit as i64)
+            .selection_criteria(self.read_criteria())
+            .build();
+        let runs: Vec<RunDto> = self
+            .runs_collection()
+            .find(doc! {"job": name.as_str()}, opts)
+            .await
+            .map_err(|e| Error::Repo(e.to_string()))?
+            .try_collect()
+            .await
+            .map_err(|e| Error::Repo(e.to_string()))?;
+        runs.into_iter().map(RunRecord::try_from).collect()
+    }
+
+    async fn prune_runs(&mut self, before: DateTime<Utc>) -> Result<u64> {
+        self.runs_collection()
+            .delete_many(doc! {"finished": {"$lt": before.timestamp_millis()}}, None)
+            .await
+            .map(|res| res.deleted_count)
+            .map_err(|e| Error::Repo(e.to_string()))
+    }
+
+    async fn delete(&mut self, name: JobName) -> Result<bool> {
+        let deleted = self
+            .client
+            .database(self.database.as_str())
+            .collection::<JobDto>(self.collection.as_str())
+            .delete_one(doc! {"_id":name.as_str()}, None)
+            .await
+            .map(|res| res.deleted_count > 0)
+            .map_err(|e| Error::Repo(e.to_string()))?;
+        self.drop_spilled_states(&name, &Bson::Null).await?;
+        Ok(deleted)
+    }
+
+    async fn rename(&mut self, old: JobName, renamed: JobData) -> Result<()> {
+        if !self.capabilities().await?.transactions {
+            return rename_in_steps(self, old, renamed).await;
+        }
+        let job = JobDto::new(renamed, self.document_format);
+        let mut session = self
+            .client
+            .start_session(None)
+            .await
+            .map_err(|e| Error::Repo(e.to_string()))?;
+        session
+            .start_transaction(None)
+            .await
+            .map_err(|e| Error::Repo(e.to_string()))?;
+        // Dropping the session on an error aborts the transaction.
+        self.jobs_collection()
+            .insert_one_with_session(&job, None, &mut session)
+            .await
+            .map_err(|e| Error::Repo(e.to_string()))?;
+        let res = self
+            .jobs_collection()
+            .update_one_with_session(
+                doc! {"_id": old.as_str()},
+                doc! { "$set": doc! { "archived": true }},
+                None,
+                &mut session,
+            )
+            .await
+            .map_err(|e| Error::Repo(e.to_string()))?;
+        if res.matched_count == 0 {
+            return Err(Error::NotFound(old));
+        }
+        session
+            .commit_transaction()
+            .await
+            .map_err(|e| Error::Repo(e.to_string()))
+    }
+
+    async fn delete_runs(&mut self, name: JobName) -> Result<u64> {
+        self.runs_collection()
+            .delete_many(doc! {"job": name.as_str()}, None)
+            .await
+            .map(|res| res.deleted_count)
+            .map_err(|e| Error::Repo(e.to_string()))
+    }
+
+    async fn list(&mut self) -> Result<Vec<JobData>> {
+        let jobs: Vec<JobDto> = self
+            .jobs_collection()
+            .find(doc! {}, None)
+            .await
+            .map_err(|e| Error::Repo(e.to_string()))?
+            .try_collect()
+            .await
+            .map_err(|e| Error::Repo(e.to_string()))?;
+        self.load_all(jobs).await
+    }
+
+    async fn list_matching(&mut self, filter: &JobFilter) -> Result<Vec<JobData>> {
+        let now = Utc::now();
+        let jobs: Vec<JobDto> = self
+            .jobs_collection()
+            .find(filter_doc(filter, now), None)
+            .await
+            .map_err(|e| Error::Repo(e.to_string()))?
+            .try_collect()
+            .await
+            .map_err(|e| Error::Repo(e.to_string()))?;
+        let jobs = self.load_all(jobs).await?;
+        Ok(jobs
+            .into_iter()
+            .filter(|jdata| filter.matches(jdata, now))
+            .collect())
+    }
+
+    async fn due_jobs(&mut self, now: DateTime<Utc>, limit: usize) -> Result<Vec<JobData>> {
+        let query = doc! {
+            "enabled": true,
+            "archived": {"$ne": true},
+            "$or": [
+                {"next_run": {"$lte": DocumentFormat::Legacy.timestamp(now)}},
+                {"next_run": {"$lte": DocumentFormat::Structured.timestamp(now)}},
+                {"next_run": {"$exists": false}},
+            ],
+        };
+        let opts = FindOptions::builder()
+            .selection_criteria(self.read_criteria())
+            .build();
+        let jobs: Vec<JobDto> = self
+            .jobs_collection()
+            .find(query, opts)
+            .await
+            .map_err(|e| Error::Repo(e.to_string()))?
+            .try_collect()
+            .await
+            .map_err(|e| Error::Repo(e.to_string()))?;
+        // Documents without next run are checked here, and the formats keep the next run in
+        // different types, which the database does not order by time, so the due jobs are
+        // ordered and limited here too.
+        let mut jobs = self.load_all(jobs).await?;
+        jobs.retain(|jdata| jdata.due(now));
+        jobs.sort_by_key(|jdata| jdata.next_run);
+        jobs.truncate(limit);
+        Ok(jobs)
+    }
+
+    async fn snapshot(&mut self) -> Result<Snapshot> {
+        let jobs = self.list().await?;
+        let runs: Vec<RunDto> = self
+            .runs_collection()
+            .find(doc! {}, None)
+            .await
+            .map_err(|e| Error::Repo(e.to_string()))?
+            .try_collect()
+            .await
+            .map_err(|e| Error::Repo(e.to_string()))?;
+        Ok(Snapshot {
+            jobs: jobs.into_iter().map(Into::into).collect(),
+            runs: runs
+                .into_iter()
+                .map(RunRecord::try_from)
+                .collect::<Result<_>>()?,
+        })
+    }
+
+    async fn restore(&mut self, snapshot: Snapshot) -> Result<()> {
+        let opts = ReplaceOptions::builder().upsert(true).build();
+        for job in snapshot.jobs {
+            let jdata = JobData::try_from(job)?;
+            let name = jdata.name.clone();
+            let state = self.store_state(&name, &jdata.state).await?;
+            let job = JobDto {
+                state: state.clone(),
+                ..JobDto::new(jdata, self.document_format)
+            };
+            self.jobs_collection()
+                .replace_one(doc! {"_id": job._id.as_str()}, &job, opts.clone())
+                .await
+                .map_err(|e| Error::Repo(e.to_string()))?;
+            self.drop_spilled_states(&name, &state).await?;
+        }
+        for run in snapshot.runs {
+            let run: RunDto = run.into();
+            self.runs_collection()
+                .replace_one(doc! {"_id": run._id.as_str()}, &run, opts.clone())
+                .await
+                .map_err(|e| Error::Repo(e.to_string()))?;
+        }
+        Ok(())
+    }
 }
@@ -1,61 +1,282 @@
-use super::{Lock, LockStatus, Repo};
+use super::{rename_in_steps, Lease, Lock, LockStatus, Repo, RepoCapabilities};
 use crate::error::Error;
-use crate::job::JobData;
+use crate::instance::InstancePresence;
+use crate::job::{Approval, JobData, Outbox, Pin, RunRequest};
 use crate::schedule::Schedule;
-use crate::JobName;
+use crate::{JobFilter, JobMetadata, JobName, RunId, RunRecord, Snapshot};
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
 use futures::FutureExt;
-use log::trace;
+use log::{trace, warn};
 use pickledb::PickleDb;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::str::FromStr;
 use std::sync::Arc;
 use std::time::{Duration, UNIX_EPOCH};
 use tokio::sync::RwLock;
 use tokio::time::sleep;
-use AsRef;
 
 #[derive(Clone)]
 pub struct PickleDbRepo {
     pub(crate) db: Arc<RwLock<PickleDb>>,
+    refresh_retries: u32,
+    key_prefix: String,
 }
 
 impl PickleDbRepo {
     pub fn new(db: PickleDb) -> Self {
         Self {
             db: Arc::new(RwLock::new(db)),
+            refresh_retries: 0,
+            key_prefix: String::default(),
         }
     }
+    /// Retry a failed lock refresh up to `retries` times before giving up the lock.
+    pub fn with_refresh_retries(mut self, retries: u32) -> Self {
+        self.refresh_retries = retries;
+        self
+    }
+    /// Keep all keys of this repo, including the maintenance flag, under `prefix`, so that
+    /// several managers or tenants can share one database. Clones of the repo share the
+    /// database, so each tenant can use a clone with its own prefix:
+    /// ```rust,ignore
+    ///     let repo = PickleDbRepo::new(db);
+    ///     let billing = JobManager::new(instance.clone(), repo.clone().with_key_prefix("billing:"));
+    ///     let reports = JobManager::new(instance, repo.with_key_prefix("reports:"));
+    /// ```
+    /// No prefix may be the start of another one, and a repo without prefix sees the keys of
+    /// all tenants.
+    pub fn with_key_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.key_prefix = prefix.into();
+        self
+    }
+
+    /// Names of the jobs in this repo's keyspace that start with `prefix`.
+    pub async fn list_prefix(&self, prefix: &str) -> Vec<JobName> {
+        let r = self.db.read().await;
+        self.job_keys(&r)
+            .into_iter()
+            .filter_map(|key| r.get::<JobDto>(&key))
+            .map(|j| j.name)
+            .filter(|name| name.as_str().starts_with(prefix))
+            .collect()
+    }
+
+    /// Delete the jobs in this repo's keyspace whose names start with `prefix`, together with
+    /// their run history. Returns the number of deleted jobs.
+    pub async fn delete_prefix(&self, prefix: &str) -> crate::error::Result<u64> {
+        let names = self.list_prefix(prefix).await;
+        let mut w = self.db.write().await;
+
+        for name in &names {
+            w.rem(&self.key(name))
+                .map_err(|e| Error::Repo(e.to_string()))?;
+            w.rem(&self.runs_key(name))
+                .map_err(|e| Error::Repo(e.to_string()))?;
+        }
+        Ok(names.len() as u64)
+    }
+
+    fn key(&self, name: &JobName) -> String {
+        format!("{}{}", self.key_prefix, name.as_str())
+    }
+
+    fn runs_key(&self, name: &JobName) -> String {
+        format!("{}{}{}", self.key_prefix, name.as_str(), RUNS_KEY_SUFFIX)
+    }
+
+    fn maintenance_key(&self) -> String {
+        format!("{}{}", self.key_prefix, MAINTENANCE_KEY)
+    }
+
+    fn instance_key(&self, instance: &str) -> String {
+        format!("{}{}{}", self.key_prefix, INSTANCE_KEY_PREFIX, instance)
+    }
+
+    // Keys of the job documents in this repo's keyspace.
+    fn job_keys(&self, db: &PickleDb) -> Vec<String> {
+        let maintenance_key = self.maintenance_key();
+        let instance_keys = self.instance_key("");
+        db.get_all()
+            .into_iter()
+            .filter(|key| {
+                key.starts_with(&self.key_prefix)
+                    && !key.ends_with(RUNS_KEY_SUFFIX)
+                    && *key != maintenance_key
+                    && !key.starts_with(&instance_keys)
+            })
+            .collect()
+    }
+
+    // Store the state and last run of the job after it ran, releasing the lock if `release`.
+    #[allow(clippy::too_many_arguments)]
+    async fn store_run(
+        &self,
+        name: JobName,
+        owner: String,
+        last_run: DateTime<Utc>,
+        state: Vec<u8>,
+        state_version: u32,
+        outbox: Option<Outbox>,
+        release: bool,
+    ) -> crate::error::Result<()> {
+        let mut w = self.db.write().await;
+
+        let key = self.key(&name);
+        let mut j = w
+            .get::<JobDto>(&key)
+            .ok_or_else(|| Error::NotFound(name.clone()))?;
+        if j.owner != owner {
+            return Err(Error::LockRefreshFailed(
+                "lock taken over by another owner".to_owned(),
+            ));
+        }
+        j.last_run = last_run.timestamp() as u64;
+        j.next_run = Schedule::from_str(j.schedule.as_str())?
+            .next_after(&last_run)
+            .map(|next_run| next_run.timestamp());
+        if release {
+            j.owner = String::default();
+            j.expires = 0;
+        }
+        j.state = state;
+        j.state_version = state_version;
+        j.version = 0;
+        if outbox.is_some() {
+            j.outbox = outbox;
+        }
+
+        w.set(&key, &j).map_err(|e| Error::Repo(e.to_string()))
+    }
+
+    // Keys of the run histories in this repo's keyspace.
+    fn runs_keys(&self, db: &PickleDb) -> Vec<String> {
+        db.get_all()
+            .into_iter()
+            .filter(|key| key.starts_with(&self.key_prefix) && key.ends_with(RUNS_KEY_SUFFIX))
+            .collect()
+    }
+}
+
+async fn refresh(
+    db: &RwLock<PickleDb>,
+    key: &str,
+    name: &JobName,
+    owner: &str,
+    ttl: Duration,
+) -> crate::error::Result<()> {
+    let mut w = db.write().await;
+    let mut j = w
+        .get::<JobDto>(key)
+        .ok_or_else(|| Error::NotFound(name.clone()))?;
+    if j.owner != owner {
+        return Err(Error::LockRefreshFailed(
+            "lock taken over by another owner".to_owned(),
+        ));
+    }
+    j.expires = Utc::now().timestamp() + ttl.as_secs() as i64;
+    w.set(key, &j).map_err(|e| Error::Repo(e.to_string()))
 }
 
+// Clear owner and expiry if the owner still holds the lock.
+async fn release(db: &RwLock<PickleDb>, key: &str, owner: &str) -> crate::error::Result<bool> {
+    let mut w = db.write().await;
+    match w.get::<JobDto>(key) {
+        Some(mut j) if j.owner == owner => {
+            j.owner = String::default();
+            j.expires = 0;
+            w.set(key, &j).map_err(|e| Error::Repo(e.to_string()))?;
+            Ok(true)
+        }
+        _ => Ok(false),
+    }
+}
+
+const RUNS_KEY_SUFFIX: &str = "#runs";
+// Reserved key of the cluster-wide maintenance flag.
+const MAINTENANCE_KEY: &str = "#maintenance";
+// Reserved start of the keys of the instance presences.
+const INSTANCE_KEY_PREFIX: &str = "#instance:";
+
 #[derive(Clone, Serialize, Debug, Deserialize, PartialEq)]
 struct JobDto {
     pub name: JobName,
     pub check_interval: u64,
     pub lock_ttl: u64,
     pub state: Vec<u8>,
+    #[serde(default)]
+    pub state_version: u32,
     pub schedule: String,
     pub enabled: bool,
     pub last_run: u64,
+    // Kept with the job so that readers need not evaluate the schedule, absent in documents
+    // written before.
+    #[serde(default)]
+    pub next_run: Option<i64>,
     pub owner: String,
     pub expires: i64,
     pub version: i8,
+    #[serde(default)]
+    pub metadata: JobMetadata,
+    #[serde(default)]
+    pub approval: Option<Approval>,
+    #[serde(default)]
+    pub dry_run: bool,
+    // Seconds since the epoch of the as-of time of a requested run.
+    #[serde(default)]
+    pub as_of: Option<i64>,
+    #[serde(default)]
+    pub trace_parent: Option<String>,
+    #[serde(default)]
+    pub triggered: bool,
+    // Milliseconds since the epoch of the latest heartbeat of the running run.
+    #[serde(default)]
+    pub last_heartbeat: Option<i64>,
+    #[serde(default)]
+    pub outbox: Option<Outbox>,
+    #[serde(default)]
+    pub archived: bool,
+    // Instance the job is pinned to and milliseconds since the epoch when the pin expires.
+    #[serde(default)]
+    pub pinned_to: Option<String>,
+    #[serde(default)]
+    pub pinned_until: Option<i64>,
 }
 
 impl From<JobData> for JobDto {
     fn from(value: JobData) -> Self {
+        let next_run = value
+            .schedule
+            .next_after(&value.last_run)
+            .map(|next_run| next_run.timestamp());
+        let requested = value.requested.unwrap_or_default();
         Self {
             name: value.name,
             check_interval: value.check_interval.as_secs(),
             lock_ttl: value.lock_ttl.as_secs(),
             state: value.state,
-            schedule: value.schedule.to_string(),
+            state_version: value.state_version,
+            schedule: value.schedule.into(),
             enabled: value.enabled,
             last_run: value.last_run.timestamp() as u64,
+            next_run,
             owner: "".to_string(),
             expires: 0,
             version: 0,
+            metadata: value.metadata,
+            approval: value.approval,
+            dry_run: requested.dry_run,
+            as_of: requested.as_of.map(|as_of| as_of.timestamp()),
+            trace_parent: requested.trace_parent,
+            triggered: requested.triggered,
+            last_heartbeat: value
+                .last_heartbeat
+                .map(|heartbeat| heartbeat.timestamp_millis()),
+            outbox: None,
+            archived: value.archived,
+            pinned_to: value.pin.as_ref().map(|pin| pin.owner.clone()),
+            pinned_until: value.pin.map(|pin| pin.until.timestamp_millis()),
         }
     }
 }
@@ -64,20 +285,44 @@ impl TryFrom<JobDto> for JobData {
     type Error = Error;
 
     fn try_from(value: JobDto) -> std::result::Result<Self, Self::Error> {
-        let schedule = Schedule::from_str(value.schedule.as_str()).map_err(|e| {
-            Error::InvalidCronExpression {
-                expression: value.schedule,
-                msg: e.to_string(),
-            }
-        })?;
+        let schedule = Schedule::from_str(value.schedule.as_str())?;
+        let last_run = DateTime::<Utc>::from(UNIX_EPOCH + Duration::from_secs(value.last_run));
+        let next_run = match value.next_run {
+            Some(next_run) => DateTime::<Utc>::from_timestamp(next_run, 0),
+            None => schedule.next_after(&last_run),
+        };
         Ok(Self {
             name: value.name,
             check_interval: Duration::from_secs(value.check_interval),
             lock_ttl: Duration::from_secs(value.lock_ttl),
             state: value.state,
+            state_version: value.state_version,
             schedule,
             enabled: value.enabled,
-            last_run: DateTime::<Utc>::from(UNIX_EPOCH + Duration::from_secs(value.last_run)),
+            last_run,
+            next_run,
+            owner: value.owner,
+            expires: DateTime::<Utc>::from_timestamp(value.expires, 0).unwrap_or_default(),
+            metadata: value.metadata,
+            approval: value.approval,
+            requested: RunRequest::from_fields(
+                value.dry_run,
+                value
+                    .as_of
+                    .and_then(|as_of| DateTime::<Utc>::from_timestamp(as_of, 0)),
+                value.trace_parent,
+                value.triggered,
+            ),
+            last_heartbeat: value
+                .last_heartbeat
+                .and_then(DateTime::<Utc>::from_timestamp_millis),
+            archived: value.archived,
+            pin: Pin::from_fields(
+                value.pinned_to,
+                value
+                    .pinned_until
+                    .and_then(DateTime::<Utc>::from_timestamp_millis),
+            ),
         })
     }
 }
@@ -88,16 +333,17 @@ impl Repo for PickleDbRepo {
 
     async fn create(&mut self, job_config: JobData) -> crate::error::Result<()> {
         let job: JobDto = job_config.into();
-        self.db
-            .write()
-            .await
-            .set(job.name.as_ref(), &job)
-            .map(|_| Ok(()))
-            .map_err(|e| Error::Repo(e.to_string()))?
+        let mut w = self.db.write().await;
+
+        let key = self.key(&job.name);
+        if w.exists(&key) {
+            return Err(Error::Repo(format!("job {:?} already exists", job.name)));
+        }
+        w.set(&key, &job).map_err(|e| Error::Repo(e.to_string()))
     }
 
     async fn get(&mut self, name: JobName) -> crate::error::Result<Option<JobData>> {
-        let j = self.db.write().await.get::<JobDto>(name.as_ref());
+        let j = self.db.write().await.get::<JobDto>(&self.key(&name));
 
         match j {
             None => Ok(None),
@@ -111,74 +357,408 @@ impl Repo for PickleDbRepo {
         }
     }
 
-    async fn commit(&mut self, _name: JobName, _state: Vec<u8>) -> crate::error::Result<()> {
-        todo!()
+    async fn commit(
+        &mut self,
+        name: JobName,
+        owner: String,
+        last_run: DateTime<Utc>,
+        state: Vec<u8>,
+        state_version: u32,
+        outbox: Option<Outbox>,
+    ) -> crate::error::Result<()> {
+        self.store_run(name, owner, last_run, state, state_version, outbox, false)
+            .await
     }
 
     async fn save(
         &mut self,
         name: JobName,
+        owner: String,
         last_run: DateTime<Utc>,
         state: Vec<u8>,
+        state_version: u32,
+        outbox: Option<Outbox>,
+    ) -> crate::error::Result<()> {
+        self.store_run(name, owner, last_run, state, state_version, outbox, true)
+            .await
+    }
+
+    async fn set_approval(
+        &mut self,
+        name: JobName,
+        approval: Option<Approval>,
     ) -> crate::error::Result<()> {
         let mut w = self.db.write().await;
 
-        let mut j = w.get::<JobDto>(name.as_ref()).ok_or(Error::TODO)?;
-        j.last_run = last_run.timestamp() as u64;
-        j.owner = String::default();
-        j.state = state;
-        j.expires = 0;
-        j.version = 0;
+        let key = self.key(&name);
+        let mut j = w
+            .get::<JobDto>(&key)
+            .ok_or_else(|| Error::NotFound(name.clone()))?;
+        j.approval = approval;
+
+        w.set(&key, &j).map_err(|e| Error::Repo(e.to_string()))
+    }
 
-        w.set(name.as_ref(), &j)
+    async fn approve(&mut self, name: JobName, run_id: RunId) -> crate::error::Result<bool> {
+        let mut w = self.db.write().await;
+
+        let key = self.key(&name);
+        let mut j = w
+            .get::<JobDto>(&key)
+            .ok_or_else(|| Error::NotFound(name.clone()))?;
+        match &mut j.approval {
+            Some(approval) if approval.run_id == run_id && !approval.approved => {
+                approval.approved = true;
+            }
+            _ => return Ok(false),
+        }
+
+        w.set(&key, &j)
+            .map(|()| true)
             .map_err(|e| Error::Repo(e.to_string()))
     }
 
+    async fn request_run(
+        &mut self,
+        name: JobName,
+        request: Option<RunRequest>,
+    ) -> crate::error::Result<()> {
+        let mut w = self.db.write().await;
+
+        let key = self.key(&name);
+        let mut j = w
+            .get::<JobDto>(&key)
+            .ok_or_else(|| Error::NotFound(name.clone()))?;
+        let request = request.unwrap_or_default();
+        j.dry_run = request.dry_run;
+        j.as_of = request.as_of.map(|as_of| as_of.timestamp());
+        j.trace_parent = request.trace_parent;
+        j.triggered = request.triggered;
+
+        w.set(&key, &j).map_err(|e| Error::Repo(e.to_string()))
+    }
+
+    async fn heartbeat(
+        &mut self,
+        name: JobName,
+        at: Option<DateTime<Utc>>,
+    ) -> crate::error::Result<()> {
+        let mut w = self.db.write().await;
+
+        let key = self.key(&name);
+        let mut j = w
+            .get::<JobDto>(&key)
+            .ok_or_else(|| Error::NotFound(name.clone()))?;
+        j.last_heartbeat = at.map(|at| at.timestamp_millis());
+
+        w.set(&key, &j).map_err(|e| Error::Repo(e.to_string()))
+    }
+
+    async fn set_archived(&mut self, name: JobName, archived: bool) -> crate::error::Result<()> {
+        let mut w = self.db.write().await;
+
+        let key = self.key(&name);
+        let mut j = w
+            .get::<JobDto>(&key)
+            .ok_or_else(|| Error::NotFound(name.clone()))?;
+        j.archived = archived;
+
+        w.set(&key, &j).map_err(|e| Error::Repo(e.to_string()))
+    }
+
+    async fn set_enabled(&mut self, name: JobName, enabled: bool) -> crate::error::Result<()> {
+        let mut w = self.db.write().await;
+
+        let key = self.key(&name);
+        let mut j = w
+            .get::<JobDto>(&key)
+            .ok_or_else(|| Error::NotFound(name.clone()))?;
+        j.enabled = enabled;
+
+        w.set(&key, &j).map_err(|e| Error::Repo(e.to_string()))
+    }
+
+    async fn set_maintenance(&mut self, on: bool) -> crate::error::Result<()> {
+        self.db
+            .write()
+            .await
+            .set(&self.maintenance_key(), &on)
+            .map_err(|e| Error::Repo(e.to_string()))
+    }
+
+    async fn maintenance(&mut self) -> crate::error::Result<bool> {
+        Ok(self
+            .db
+            .read()
+            .await
+            .get::<bool>(&self.maintenance_key())
+            .unwrap_or_default())
+    }
+
+    async fn announce_instance(
+        &mut self,
+        presence: InstancePresence,
+    ) -> crate::error::Result<Option<InstancePresence>> {
+        let mut w = self.db.write().await;
+
+        let key = self.instance_key(&presence.instance);
+        let previous = w.get::<InstancePresence>(&key);
+        w.set(&key, &presence)
+            .map_err(|e| Error::Repo(e.to_string()))?;
+        Ok(previous)
+    }
+
     async fn lock(
         &mut self,
         name: JobName,
         owner: String,
         ttl: Duration,
     ) -> crate::error::Result<LockStatus<Self::Lock>> {
+        let mut lease = Lease::start(ttl);
         let mut w = self.db.write().await;
 
-        let mut jdto = w.get::<JobDto>(name.as_ref()).ok_or(Error::TODO)?;
+        let key = self.key(&name);
+        let mut jdto = w
+            .get::<JobDto>(&key)
+            .ok_or_else(|| Error::NotFound(name.clone()))?;
         if jdto.expires > Utc::now().timestamp() {
             Ok(LockStatus::AlreadyLocked)
         } else {
             jdto.owner = owner;
             jdto.expires = Utc::now().timestamp() + ttl.as_secs() as i64;
             jdto.version = 0;
-            w.set(name.as_ref(), &jdto)
-                .map_err(|e| Error::Repo(e.to_string()))
-                .unwrap();
+            w.set(&key, &jdto).map_err(|e| Error::Repo(e.to_string()))?;
 
+            let refresh_key = key.clone();
             let name = jdto.name.clone();
             let owner = jdto.owner.clone();
             let db = self.db.clone();
 
+            let retries = self.refresh_retries;
             let fut = async move {
                 trace!("starting lock refresh");
+                let refresh_interval = ttl / 2;
+                let mut wait = refresh_interval;
+                let mut failures = 0;
                 loop {
-                    let refresh_interval = Duration::from_secs(ttl.as_secs() / 2);
-                    sleep(refresh_interval).await;
-                    let mut w = db.write().await;
-                    let mut j = w.get::<JobDto>(name.as_ref()).ok_or(Error::TODO).unwrap();
-                    j.expires = Utc::now().timestamp() + ttl.as_secs() as i64;
-                    j.owner = owner.clone();
-                    match w.set(name.0.as_str(), &j) {
-                        Ok(()) => {}
+                    sleep(wait).await;
+                    match lease
+                        .refresh(refresh(&db, &refresh_key, &name, &owner, ttl))
+                        .await
+                    {
+                        Ok(()) => {
+                            trace!("lock refreshed");
+                            failures = 0;
+                            wait = refresh_interval;
+                        }
+                        Err(e @ Error::LockRefreshFailed(_)) => return Err(e),
+                        Err(e) if failures < retries => {
+                            failures += 1;
+                            warn!("lock refresh attempt {} failed: {}, retrying", failures, e);
+                            wait = refresh_interval / 4;
+                        }
                         Err(e) => return Err(Error::LockRefreshFailed(e.to_string())),
                     }
-                    trace!("lock refreshed");
                 }
             }
             .boxed();
 
-            let lock = Lock { fut };
+            let owner = jdto.owner.clone();
+            let db = self.db.clone();
+            let release = async move {
+                if let Err(e) = release(&db, &key, &owner).await {
+                    warn!("releasing lock failed: {}", e);
+                }
+            }
+            .boxed();
+
+            let lock = Lock::new(fut, release);
 
             let job_config: JobData = jdto.try_into()?;
             Ok(LockStatus::Acquired(job_config, lock))
         }
     }
+
+    async fn unlock(&mut self, name: JobName, owner: String) -> crate::error::Result<bool> {
+        release(&self.db, &self.key(&name), &owner).await
+    }
+
+    async fn pin(
+        &mut self,
+        name: JobName,
+        owner: String,
+        until: DateTime<Utc>,
+    ) -> crate::error::Result<bool> {
+        let mut w = self.db.write().await;
+
+        let key = self.key(&name);
+        let mut j = w
+            .get::<JobDto>(&key)
+            .ok_or_else(|| Error::NotFound(name.clone()))?;
+        let now = Utc::now().timestamp_millis();
+        let taken = j.pinned_to.as_ref().is_some_and(|pinned| *pinned != owner)
+            && j.pinned_until
+                .is_some_and(|pinned_until| pinned_until > now);
+        if taken {
+            return Ok(false);
+        }
+        j.pinned_to = Some(owner);
+        j.pinned_until = Some(until.timestamp_millis());
+
+        w.set(&key, &j).map_err(|e| Error::Repo(e.to_string()))?;
+        Ok(true)
+    }
+
+    async fn server_time(&mut self) -> crate::error::Result<Option<DateTime<Utc>>> {
+        Ok(None)
+    }
+
+    async fn capabilities(&mut self) -> crate::error::Result<RepoCapabilities> {
+        // All access goes through the lock of the database within this process.
+        Ok(RepoCapabilities {
+            shared: false,
+            atomic_locks: true,
+            transactions: false,
+            watch: false,
+            clock: false,
+        })
+    }
+
+    async fn missing_indexes(&mut self) -> crate::error::Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    async fn record_run(&mut self, record: RunRecord) -> crate::error::Result<()> {
+        let mut w = self.db.write().await;
+
+        let key = self.runs_key(&record.name);
+        let mut runs = w.get::<Vec<RunRecord>>(&key).unwrap_or_default();
+        runs.push(record);
+
+        w.set(&key, &runs).map_err(|e| Error::Repo(e.to_string()))
+    }
+
+    async fn runs(&mut self, name: JobName, limit: usize) -> crate::error::Result<Vec<RunRecord>> {
+        let runs = self
+            .db
+            .read()
+            .await
+            .get::<Vec<RunRecord>>(&self.runs_key(&name))
+            .unwrap_or_default();
+
+        Ok(runs.into_iter().rev().take(limit).collect())
+    }
+
+    async fn prune_runs(&mut self, before: DateTime<Utc>) -> crate::error::Result<u64> {
+        let mut w = self.db.write().await;
+
+        let mut pruned = 0;
+        for key in self.runs_keys(&w) {
+            let mut runs = w.get::<Vec<RunRecord>>(&key).unwrap_or_default();
+            let len = runs.len();
+            runs.retain(|run| run.finished >= before);
+            if runs.len() < len {
+                pruned += (len - runs.len()) as u64;
+                w.set(&key, &runs).map_err(|e| Error::Repo(e.to_string()))?;
+            }
+        }
+        Ok(pruned)
+    }
+
+    async fn delete(&mut self, name: JobName) -> crate::error::Result<bool> {
+        self.db
+            .write()
+            .await
+            .rem(&self.key(&name))
+            .map_err(|e| Error::Repo(e.to_string()))
+    }
+
+    async fn rename(&mut self, old: JobName, renamed: JobData) -> crate::error::Result<()> {
+        rename_in_steps(self, old, renamed).await
+    }
+
+    async fn delete_runs(&mut self, name: JobName) -> crate::error::Result<u64> {
+        let mut w = self.db.write().await;
+
+        let key = self.runs_key(&name);
+        let deleted = w.get::<Vec<RunRecord>>(&key).unwrap_or_default().len() as u64;
+        w.rem(&key).map_err(|e| Error::Repo(e.to_string()))?;
+        Ok(deleted)
+    }
+
+    async fn list(&mut self) -> crate::error::Result<Vec<JobData>> {
+        let r = self.db.read().await;
+
+        self.job_keys(&r)
+            .iter()
+            .filter_map(|key| r.get::<JobDto>(key))
+            .map(JobData::try_from)
+            .collect()
+    }
+
+    async fn list_matching(&mut self, filter: &JobFilter) -> crate::error::Result<Vec<JobData>> {
+        let now = Utc::now();
+        let jobs = self.list().await?;
+        Ok(jobs
+            .into_iter()
+            .filter(|jdata| filter.matches(jdata, now))
+            .collect())
+    }
+
+    async fn due_jobs(
+        &mut self,
+        now: DateTime<Utc>,
+        limit: usize,
+    ) -> crate::error::Result<Vec<JobData>> {
+        let mut jobs: Vec<JobData> = self
+            .list()
+            .await?
+            .into_iter()
+            .filter(|jdata| jdata.due(now))
+            .collect();
+        jobs.sort_by_key(|jdata| jdata.next_run);
+        jobs.truncate(limit);
+        Ok(jobs)
+    }
+
+    async fn snapshot(&mut self) -> crate::error::Result<Snapshot> {
+        let jobs = self.list().await?;
+        let r = self.db.read().await;
+
+        let runs = self
+            .runs_keys(&r)
+            .iter()
+            .flat_map(|key| r.get::<Vec<RunRecord>>(key).unwrap_or_default())
+            .collect();
+        Ok(Snapshot {
+            jobs: jobs.into_iter().map(Into::into).collect(),
+            runs,
+        })
+    }
+
+    async fn restore(&mut self, snapshot: Snapshot) -> crate::error::Result<()> {
+        let mut w = self.db.write().await;
+
+        for job in snapshot.jobs {
+            let job: JobDto = JobData::try_from(job)?.into();
+            w.set(&self.key(&job.name), &job)
+                .map_err(|e| Error::Repo(e.to_string()))?;
+        }
+
+        let mut runs_by_job: HashMap<String, Vec<RunRecord>> = HashMap::new();
+        for run in snapshot.runs {
+            runs_by_job
+                .entry(self.runs_key(&run.name))
+                .or_default()
+                .push(run);
+        }
+        for (key, restored) in runs_by_job {
+            let mut runs = w.get::<Vec<RunRecord>>(&key).unwrap_or_default();
+            runs.retain(|run| !restored.iter().any(|r| r.run_id == run.run_id));
+            runs.extend(restored);
+            runs.sort_by_key(|run| run.started);
+            w.set(&key, &runs).map_err(|e| Error::Repo(e.to_string()))?;
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,396 @@
+//! Locking with the Redlock algorithm across independent Redis nodes. A lock is held when a
+//! majority of the nodes granted it within its validity, so single node failures neither
+//! block the jobs nor let two instances run the same job.
+//!
+//! ```rust,ignore
+//!     let repo = RedlockRepo::new(
+//!         PickleDbRepo::new(db),
+//!         &["redis://redis-a:6379", "redis://redis-b:6379", "redis://redis-c:6379"],
+//!     )?;
+//!     let mut manager = JobManager::new(InstanceId::generate(), repo);
+//! ```
+use super::{Lease, Lock, LockStatus, Repo, RepoCapabilities};
+use crate::error::{Error, Result};
+use crate::instance::InstancePresence;
+use crate::job::{Approval, JobData, Outbox, RunRequest};
+use crate::{JobFilter, JobName, RunId, RunRecord, Snapshot};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures::FutureExt;
+use futures_util::future::join_all;
+use log::trace;
+use redis::{Client, Script};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::{sleep, timeout, Instant};
+
+const DEFAULT_NODE_TIMEOUT: Duration = Duration::from_millis(100);
+
+// Take the lock unless another owner holds it. The current owner extends it instead.
+const ACQUIRE_SCRIPT: &str = r#"
+if redis.call("get", KEYS[1]) == ARGV[1] then
+    return redis.call("pexpire", KEYS[1], ARGV[2])
+elseif redis.call("set", KEYS[1], ARGV[1], "NX", "PX", ARGV[2]) then
+    return 1
+end
+return 0
+"#;
+const EXTEND_SCRIPT: &str = r#"
+if redis.call("get", KEYS[1]) == ARGV[1] then
+    return redis.call("pexpire", KEYS[1], ARGV[2])
+end
+return 0
+"#;
+const RELEASE_SCRIPT: &str = r#"
+if redis.call("get", KEYS[1]) == ARGV[1] then
+    return redis.call("del", KEYS[1])
+end
+return 0
+"#;
+
+/// Repo wrapper that locks jobs with Redlock on a set of independent Redis nodes and keeps
+/// job data and run history in the wrapped repo. The lock owner is not written to the wrapped
+/// repo, so [`crate::JobStatus::running_on`] stays empty.
+#[derive(Clone)]
+pub struct RedlockRepo<R> {
+    inner: R,
+    redlock: Redlock,
+    // Owners of the locks acquired through this repo by job name, to release them on save.
+    held: Arc<Mutex<HashMap<String, String>>>,
+}
+
+impl<R> RedlockRepo<R> {
+    /// Lock on the Redis nodes with the given URLs. Use an odd number of nodes, at least 3.
+    pub fn new(inner: R, node_urls: &[&str]) -> Result<Self> {
+        let nodes = node_urls
+            .iter()
+            .map(|url| Client::open(*url).map_err(|e| Error::Repo(e.to_string())))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(RedlockRepo {
+            inner,
+            redlock: Redlock {
+                nodes: Arc::new(nodes),
+                node_timeout: DEFAULT_NODE_TIMEOUT,
+            },
+            held: Arc::default(),
+        })
+    }
+    /// Give up on a node that does not answer within `timeout`, so that a slow node does not
+    /// eat up the validity of the lock. Defaults to 100ms.
+    pub fn with_node_timeout(mut self, timeout: Duration) -> Self {
+        self.redlock.node_timeout = timeout;
+        self
+    }
+    fn held(&self) -> std::sync::MutexGuard<'_, HashMap<String, String>> {
+        self.held.lock().expect("redlock owners lock poisoned")
+    }
+}
+
+fn lock_key(name: &JobName) -> String {
+    format!("ply_jobs:lock:{}", name.as_str())
+}
+
+#[derive(Clone)]
+struct Redlock {
+    nodes: Arc<Vec<Client>>,
+    node_timeout: Duration,
+}
+
+impl Redlock {
+    fn quorum(&self) -> usize {
+        self.nodes.len() / 2 + 1
+    }
+
+    // Run the script on all nodes and count the nodes that granted and that failed.
+    async fn on_all(&self, script: &str, key: &str, owner: &str, ttl: Duration) -> (usize, usize) {
+        let script = Script::new(script);
+        let ttl_ms = ttl.as_millis() as u64;
+        let calls = self.nodes.iter().map(|node| {
+            let script = &script;
+            async move {
+                let call = async {
+                    let mut con = node.get_multiplexed_async_connection().await?;
+                    script
+                        .key(key)
+                        .arg(owner)
+                        .arg(ttl_ms)
+                        .invoke_async::<_, i64>(&mut con)
+                        .await
+                };
+                match timeout(self.node_timeout, call).await {
+                    Ok(Ok(granted)) => Ok(granted == 1),
+                    Ok(Err(e)) => Err(e.to_string()),
+                    Err(_) => Err("timed out".to_owned()),
+                }
+            }
+        });
+        let mut granted = 0;
+        let mut failed = 0;
+        for result in join_all(calls).await {
+            match result {
+                Ok(true) => granted += 1,
+                Ok(false) => {}
+                Err(e) => {
+                    trace!("redlock node failed: {}", e);
+                    failed += 1;
+                }
+            }
+        }
+        (granted, failed)
+    }
+
+    // Take or extend the lock on a quorum of the nodes within the lock's validity. Fails when
+    // too many nodes are unavailable to ever reach a quorum.
+    async fn acquire(&self, script: &str, key: &str, owner: &str, ttl: Duration) -> Result<bool> {
+        let started = Instant::now();
+        let (granted, failed) = self.on_all(script, key, owner, ttl).await;
+        // Clocks of the nodes drift apart, so part of the TTL cannot be relied upon.
+        let drift = ttl / 100 + Duration::from_millis(2);
+        let valid = ttl.saturating_sub(started.elapsed() + drift) > Duration::ZERO;
+        if granted >= self.quorum() && valid {
+            return Ok(true);
+        }
+        self.release(key, owner).await;
+        if failed > self.nodes.len() - self.quorum() {
+            return Err(Error::Repo(format!(
+                "{} of {} redlock nodes unavailable",
+                failed,
+                self.nodes.len()
+            )));
+        }
+        Ok(false)
+    }
+
+    async fn release(&self, key: &str, owner: &str) {
+        self.on_all(RELEASE_SCRIPT, key, owner, Duration::ZERO)
+            .await;
+    }
+}
+
+#[async_trait]
+impl<R: Repo + Send + Sync> Repo for RedlockRepo<R> {
+    type Lock = Lock;
+
+    async fn create(&mut self, data: JobData) -> Result<()> {
+        self.inner.create(data).await
+    }
+
+    async fn get(&mut self, name: JobName) -> Result<Option<JobData>> {
+        self.inner.get(name).await
+    }
+
+    async fn commit(
+        &mut self,
+        name: JobName,
+        owner: String,
+        last_run: DateTime<Utc>,
+        state: Vec<u8>,
+        state_version: u32,
+        outbox: Option<Outbox>,
+    ) -> Result<()> {
+        self.inner
+            .commit(name, owner, last_run, state, state_version, outbox)
+            .await
+    }
+
+    async fn save(
+        &mut self,
+        name: JobName,
+        owner: String,
+        last_run: DateTime<Utc>,
+        state: Vec<u8>,
+        state_version: u32,
+        outbox: Option<Outbox>,
+    ) -> Result<()> {
+        self.inner
+            .save(name.clone(), owner, last_run, state, state_version, outbox)
+            .await?;
+        let owner = self.held().remove(name.as_str());
+        if let Some(owner) = owner {
+            self.redlock.release(&lock_key(&name), &owner).await;
+        }
+        Ok(())
+    }
+
+    async fn set_approval(&mut self, name: JobName, approval: Option<Approval>) -> Result<()> {
+        self.inner.set_approval(name, approval).await
+    }
+
+    async fn approve(&mut self, name: JobName, run_id: RunId) -> Result<bool> {
+        self.inner.approve(name, run_id).await
+    }
+
+    async fn request_run(&mut self, name: JobName, request: Option<RunRequest>) -> Result<()> {
+        self.inner.request_run(name, request).await
+    }
+
+    async fn heartbeat(&mut self, name: JobName, at: Option<DateTime<Utc>>) -> Result<()> {
+        self.inner.heartbeat(name, at).await
+    }
+
+    async fn set_archived(&mut self, name: JobName, archived: bool) -> Result<()> {
+        self.inner.set_archived(name, archived).await
+    }
+
+    async fn set_enabled(&mut self, name: JobName, enabled: bool) -> Result<()> {
+        self.inner.set_enabled(name, enabled).await
+    }
+
+    async fn set_maintenance(&mut self, on: bool) -> Result<()> {
+        self.inner.set_maintenance(on).await
+    }
+
+    async fn maintenance(&mut self) -> Result<bool> {
+        self.inner.maintenance().await
+    }
+
+    async fn announce_instance(
+        &mut self,
+        presence: InstancePresence,
+    ) -> Result<Option<InstancePresence>> {
+        self.inner.announce_instance(presence).await
+    }
+
+    async fn lock(
+        &mut self,
+        name: JobName,
+        owner: String,
+        ttl: Duration,
+    ) -> Result<LockStatus<Self::Lock>> {
+        let mut lease = Lease::start(ttl);
+        let key = lock_key(&name);
+        if !self
+            .redlock
+            .acquire(ACQUIRE_SCRIPT, &key, &owner, ttl)
+            .await?
+        {
+            return Ok(LockStatus::AlreadyLocked);
+        }
+        let jdata = match self.inner.get(name.clone()).await {
+            Ok(Some(jdata)) => jdata,
+            Ok(None) => {
+                self.redlock.release(&key, &owner).await;
+                return Err(Error::NotFound(name));
+            }
+            Err(e) => {
+                self.redlock.release(&key, &owner).await;
+                return Err(e);
+            }
+        };
+        self.held().insert(name.as_str().to_owned(), owner.clone());
+
+        let redlock = self.redlock.clone();
+        let (refresh_key, refresh_owner) = (key.clone(), owner.clone());
+        let fut = async move {
+            trace!("starting redlock refresh");
+            loop {
+                sleep(ttl / 3).await;
+                let extend = async {
+                    match redlock
+                        .acquire(EXTEND_SCRIPT, &refresh_key, &refresh_owner, ttl)
+                        .await
+                    {
+                        Ok(true) => Ok(()),
+                        Ok(false) => {
+                            Err(Error::LockRefreshFailed("redlock quorum lost".to_owned()))
+                        }
+                        Err(e) => Err(Error::LockRefreshFailed(e.to_string())),
+                    }
+                };
+                lease.refresh(extend).await?;
+                trace!("redlock refreshed");
+            }
+        }
+        .boxed();
+
+        let redlock = self.redlock.clone();
+        let held = self.held.clone();
+        let release = async move {
+            {
+                let mut held = held.lock().expect("redlock owners lock poisoned");
+                if held.get(name.as_str()) == Some(&owner) {
+                    held.remove(name.as_str());
+                }
+            }
+            redlock.release(&key, &owner).await;
+        }
+        .boxed();
+
+        Ok(LockStatus::Acquired(jdata, Lock::new(fut, release)))
+    }
+
+    async fn unlock(&mut self, name: JobName, owner: String) -> Result<bool> {
+        let held = {
+            let mut held = self.held();
+            if held.get(name.as_str()) == Some(&owner) {
+                held.remove(name.as_str());
+                true
+            } else {
+                false
+            }
+        };
+        self.redlock.release(&lock_key(&name), &owner).await;
+        Ok(held)
+    }
+
+    async fn pin(&mut self, name: JobName, owner: String, until: DateTime<Utc>) -> Result<bool> {
+        self.inner.pin(name, owner, until).await
+    }
+
+    async fn server_time(&mut self) -> Result<Option<DateTime<Utc>>> {
+        self.inner.server_time().await
+    }
+
+    async fn capabilities(&mut self) -> Result<RepoCapabilities> {
+        self.inner.capabilities().await
+    }
+
+    async fn missing_indexes(&mut self) -> Result<Vec<String>> {
+        self.inner.missing_indexes().await
+    }
+
+    async fn record_run(&mut self, record: RunRecord) -> Result<()> {
+        self.inner.record_run(record).await
+    }
+
+    async fn runs(&mut self, name: JobName, limit: usize) -> Result<Vec<RunRecord>> {
+        self.inner.runs(name, limit).await
+    }
+
+    async fn list(&mut self) -> Result<Vec<JobData>> {
+        self.inner.list().await
+    }
+
+    async fn list_matching(&mut self, filter: &JobFilter) -> Result<Vec<JobData>> {
+        self.inner.list_matching(filter).await
+    }
+
+    async fn due_jobs(&mut self, now: DateTime<Utc>, limit: usize) -> Result<Vec<JobData>> {
+        self.inner.due_jobs(now, limit).await
+    }
+
+    async fn snapshot(&mut self) -> Result<Snapshot> {
+        self.inner.snapshot().await
+    }
+
+    async fn restore(&mut self, snapshot: Snapshot) -> Result<()> {
+        self.inner.restore(snapshot).await
+    }
+
+    async fn delete(&mut self, name: JobName) -> Result<bool> {
+        self.inner.delete(name).await
+    }
+
+    async fn rename(&mut self, old: JobName, renamed: JobData) -> Result<()> {
+        self.inner.rename(old, renamed).await
+    }
+
+    async fn delete_runs(&mut self, name: JobName) -> Result<u64> {
+        self.inner.delete_runs(name).await
+    }
+
+    async fn prune_runs(&mut self, before: DateTime<Utc>) -> Result<u64> {
+        self.inner.prune_runs(before).await
+    }
+}
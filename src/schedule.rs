@@ -1,54 +1,609 @@
-use chrono::{DateTime, Utc};
+use chrono::{
+    DateTime, Duration as ChronoDuration, LocalResult, NaiveDateTime, NaiveTime, TimeZone,
+    Timelike, Utc,
+};
+pub use chrono_tz::Tz;
+use cron::TimeUnitSpec;
+use rand::Rng;
 use std::fmt::{Debug, Display, Formatter};
+use std::ops::Range;
 use std::str::FromStr;
+use std::time::Duration;
+
+// Times a schedule that fires when all of its schedules fire is advanced to the next time
+// one of them fires before giving up on finding a time they all fire at.
+const MAX_INTERSECTION_STEPS: usize = 10_000;
+
+#[derive(Clone, Debug)]
+pub struct Schedule(Expr);
 
 #[derive(Clone, Debug)]
-pub struct Schedule(cron::Schedule);
+enum Expr {
+    Cron(Box<cron::Schedule>),
+    // Fires whenever any of the schedules fires.
+    Any(Vec<Schedule>),
+    // Fires whenever all of the schedules fire at the same time.
+    All(Vec<Schedule>),
+    // The schedule with its cron expressions read in the time zone.
+    Zoned(Tz, Box<Schedule>),
+}
 
 impl Schedule {
+    /// Schedule firing whenever this or the other schedule fires, e.g. at 08:00 on weekdays
+    /// or at 10:00 on weekends. Written as `any(<schedule>; <schedule>)`.
+    pub fn or(self, other: Schedule) -> Schedule {
+        match self.0 {
+            Expr::Any(mut schedules) => {
+                schedules.push(other);
+                Schedule(Expr::Any(schedules))
+            }
+            _ => Schedule(Expr::Any(vec![self, other])),
+        }
+    }
+    /// Schedule firing whenever this and the other schedule fire at the same time, e.g. every
+    /// 5 minutes but only within business hours. Written as `all(<schedule>; <schedule>)`.
+    pub fn and(self, other: Schedule) -> Schedule {
+        match self.0 {
+            Expr::All(mut schedules) => {
+                schedules.push(other);
+                Schedule(Expr::All(schedules))
+            }
+            _ => Schedule(Expr::All(vec![self, other])),
+        }
+    }
+    /// Schedule reading its cron expressions in the given time zone instead of in UTC, e.g.
+    /// `0 0 9 * * *` at 09:00 in Europe/Berlin, in winter as in summer. Written as
+    /// `CRON_TZ=Europe/Berlin <schedule>`. Around the switches of daylight saving time the
+    /// schedule fires like cron: a local time that occurs twice fires once, at its first
+    /// occurrence, and a local time that is skipped fires at the first instant after the
+    /// skip, e.g. 02:30 at 03:00 on the day summer time starts in Europe/Berlin.
+    pub fn in_time_zone(self, tz: Tz) -> Schedule {
+        match self.0 {
+            Expr::Zoned(_, schedule) => Schedule(Expr::Zoned(tz, schedule)),
+            _ => Schedule(Expr::Zoned(tz, Box::new(self))),
+        }
+    }
+    /// Time zone in which the cron expressions are read, if not UTC, see
+    /// [`Schedule::in_time_zone`].
+    pub fn time_zone(&self) -> Option<Tz> {
+        match &self.0 {
+            Expr::Zoned(tz, _) => Some(*tz),
+            _ => None,
+        }
+    }
+    /// Next time the schedule fires after the given time, if any.
+    pub fn next_after(&self, after: &DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.next_after_in(after, &Tz::UTC)
+    }
+    fn next_after_in(&self, after: &DateTime<Utc>, zone: &Tz) -> Option<DateTime<Utc>> {
+        match &self.0 {
+            // The local times the expression fires at, as UTC times standing in for them, and
+            // the instants they resolve to. Local times within an hour repeated by daylight
+            // saving time resolve to instants up to the hour before `after`.
+            Expr::Cron(cron) => {
+                let local = after.with_timezone(zone).naive_local();
+                cron.after(&Utc.from_utc_datetime(&local))
+                    .map(|t| resolve_local(t.naive_utc(), zone))
+                    .find(|t| t > after)
+            }
+            Expr::Zoned(tz, schedule) => schedule.next_after_in(after, tz),
+            Expr::Any(schedules) => schedules
+                .iter()
+                .filter_map(|s| s.next_after_in(after, zone))
+                .min(),
+            Expr::All(schedules) => {
+                // Advance to the latest of the next times until all schedules agree on it.
+                // Schedules fire at whole seconds, so a schedule firing at the latest time
+                // finds it as its next time after the second before.
+                let mut after = *after;
+                for _ in 0..MAX_INTERSECTION_STEPS {
+                    let next = schedules
+                        .iter()
+                        .map(|s| s.next_after_in(&after, zone))
+                        .collect::<Option<Vec<_>>>()?;
+                    let latest = *next.iter().max()?;
+                    if next.iter().all(|t| *t == latest) {
+                        return Some(latest);
+                    }
+                    after = latest - ChronoDuration::seconds(1);
+                }
+                None
+            }
+        }
+    }
+    /// Latest time the schedule fired before the given time, if any.
+    pub fn previous_before(&self, before: &DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.previous_before_in(before, &Tz::UTC)
+    }
+    fn previous_before_in(&self, before: &DateTime<Utc>, zone: &Tz) -> Option<DateTime<Utc>> {
+        match &self.0 {
+            Expr::Cron(cron) => {
+                // cron looks back from the whole second before the given time.
+                let ceiled = match before.with_nanosecond(0) {
+                    Some(second) if second < *before => second + ChronoDuration::seconds(1),
+                    _ => *before,
+                };
+                // Within the second pass of an hour repeated by daylight saving time, the
+                // first pass of the later local times is before the given time too.
+                let local = ceiled.with_timezone(zone).naive_local();
+                let local = match zone.from_local_datetime(&local) {
+                    LocalResult::Ambiguous(earliest, latest) if latest == ceiled => {
+                        local + (latest - earliest)
+                    }
+                    _ => local,
+                };
+                cron.after(&Utc.from_utc_datetime(&local))
+                    .rev()
+                    .map(|t| resolve_local(t.naive_utc(), zone))
+                    .find(|t| t < before)
+            }
+            Expr::Zoned(tz, schedule) => schedule.previous_before_in(before, tz),
+            Expr::Any(schedules) => schedules
+                .iter()
+                .filter_map(|s| s.previous_before_in(before, zone))
+                .max(),
+            Expr::All(schedules) => {
+                // The reverse of finding the next time they all fire, see `next_after`.
+                let mut before = *before;
+                for _ in 0..MAX_INTERSECTION_STEPS {
+                    let previous = schedules
+                        .iter()
+                        .map(|s| s.previous_before_in(&before, zone))
+                        .collect::<Option<Vec<_>>>()?;
+                    let earliest = *previous.iter().min()?;
+                    if previous.iter().all(|t| *t == earliest) {
+                        return Some(earliest);
+                    }
+                    before = earliest + ChronoDuration::seconds(1);
+                }
+                None
+            }
+        }
+    }
+    /// All times the schedule fires within the range.
+    pub fn between(&self, range: Range<DateTime<Utc>>) -> impl Iterator<Item = DateTime<Utc>> + '_ {
+        let end = range.end;
+        std::iter::successors(self.next_after(&range.start), move |t| self.next_after(t))
+            .take_while(move |t| t < &end)
+    }
     pub fn due(&self, last: &DateTime<Utc>, now: DateTime<Utc>) -> bool {
-        self.0
-            .after(last)
-            .next()
-            .unwrap_or_else(|| DateTime::default())
-            .lt(&now)
+        self.due_within(last, now, Duration::ZERO)
+    }
+    /// Like [`Schedule::due`], but times the schedule fires within `tolerance` after the last
+    /// run do not count, while times within `tolerance` after now already do. This absorbs
+    /// small differences between the clocks of the instances and the store, which otherwise
+    /// delay a run to the next check or let a run stored as finished just before the time the
+    /// schedule fires be followed by a second run right away.
+    pub fn due_within(
+        &self,
+        last: &DateTime<Utc>,
+        now: DateTime<Utc>,
+        tolerance: Duration,
+    ) -> bool {
+        let tolerance = ChronoDuration::from_std(tolerance).unwrap_or_default();
+        self.next_after(&(*last + tolerance))
+            .unwrap_or_default()
+            .lt(&(now + tolerance))
+    }
+    /// Human-readable explanation of when the schedule fires, e.g. "at 02:00 every Monday".
+    pub fn describe(&self) -> String {
+        let describe_all = |schedules: &[Schedule]| {
+            let descriptions: Vec<_> = schedules.iter().map(|s| s.describe()).collect();
+            descriptions.join("; ")
+        };
+        match &self.0 {
+            Expr::Cron(cron) => describe_cron(cron),
+            Expr::Any(schedules) => format!("whenever any of ({}) fires", describe_all(schedules)),
+            Expr::All(schedules) => format!("whenever all of ({}) fire", describe_all(schedules)),
+            Expr::Zoned(tz, schedule) => format!("{} ({})", schedule.describe(), tz.name()),
+        }
+    }
+    /// Warnings about suspicious properties of the schedule, e.g. that it never fires again.
+    pub fn lint(&self) -> Vec<ScheduleLint> {
+        let mut lints = Vec::new();
+        if self.next_after(&Utc::now()).is_none() {
+            lints.push(ScheduleLint::NeverFires);
+        }
+        if self.fires_every_second() {
+            lints.push(ScheduleLint::EverySecond);
+        }
+        lints
+    }
+    fn fires_every_second(&self) -> bool {
+        match &self.0 {
+            Expr::Cron(cron) => cron.seconds().is_all(),
+            Expr::Any(schedules) => schedules.iter().any(Schedule::fires_every_second),
+            Expr::All(schedules) => schedules.iter().all(Schedule::fires_every_second),
+            Expr::Zoned(_, schedule) => schedule.fires_every_second(),
+        }
+    }
+}
+
+// The instant a local time of the zone stands for. A local time that occurs twice stands for
+// its first occurrence, and a local time skipped by daylight saving time for the first instant
+// after the skip. Resolving is monotonic, so the instants keep the order of the local times.
+fn resolve_local(local: NaiveDateTime, zone: &Tz) -> DateTime<Utc> {
+    match zone.from_local_datetime(&local) {
+        LocalResult::Single(t) => t.with_timezone(&Utc),
+        LocalResult::Ambiguous(earliest, _) => earliest.with_timezone(&Utc),
+        LocalResult::None => {
+            // Search the first instant whose local time is not before the skipped one. UTC
+            // offsets range from -12 to +14 hours.
+            let seconds = local.and_utc().timestamp();
+            let (mut low, mut high) = (seconds - 15 * 3600, seconds + 13 * 3600);
+            let at = |seconds| DateTime::from_timestamp(seconds, 0).unwrap_or_default();
+            while high - low > 1 {
+                let middle = low + (high - low) / 2;
+                if at(middle).with_timezone(zone).naive_local() < local {
+                    low = middle;
+                } else {
+                    high = middle;
+                }
+            }
+            at(high)
+        }
+    }
+}
+
+fn describe_cron(cron: &cron::Schedule) -> String {
+    let seconds = Unit::of(cron.seconds(), 0, 59);
+    let minutes = Unit::of(cron.minutes(), 0, 59);
+    let hours = Unit::of(cron.hours(), 0, 23);
+    let mut time = Vec::new();
+    match (&seconds, &minutes, &hours) {
+        (Unit::Single(0), Unit::Single(m), Unit::Single(h)) => {
+            time.push(format!("at {:02}:{:02}", h, m))
+        }
+        (Unit::Single(s), Unit::Single(m), Unit::Single(h)) => {
+            time.push(format!("at {:02}:{:02}:{:02}", h, m, s))
+        }
+        _ => {
+            // An "every" of a finer unit makes the "every" of coarser units redundant.
+            let mut repeating = false;
+            for (unit, name) in [(&seconds, "second"), (&minutes, "minute"), (&hours, "hour")] {
+                match unit {
+                    Unit::All if repeating => continue,
+                    Unit::Single(0) if name == "second" => continue,
+                    _ => {}
+                }
+                let clause = unit.describe(name);
+                time.push(match unit {
+                    Unit::Single(_) | Unit::List(_) if time.is_empty() => {
+                        format!("at {}", clause)
+                    }
+                    _ if time.is_empty() => clause,
+                    _ => format!("of {}", clause),
+                });
+                repeating |= matches!(unit, Unit::All | Unit::Step(_));
+            }
+        }
+    }
+    let mut clauses = vec![time.join(" ")];
+    let fixed_time = time.len() == 1 && time[0].starts_with("at ");
+    let weekdays = cron.days_of_week();
+    let days = Unit::of(cron.days_of_month(), 1, 31);
+    match (weekdays.is_all(), &days) {
+        (true, Unit::All) if fixed_time => clauses.push("every day".to_owned()),
+        (true, Unit::All) => {}
+        (true, days) => clauses.push(format!("on {} of the month", days.describe("day"))),
+        (false, _) => {
+            let names: Vec<_> = weekdays.iter().map(weekday_name).collect();
+            clauses.push(format!("every {}", names.join(", ")));
+        }
+    }
+    if !cron.months().is_all() {
+        let names: Vec<_> = cron.months().iter().map(month_name).collect();
+        clauses.push(format!("in {}", names.join(", ")));
+    }
+    if !cron.years().is_all() {
+        let years: Vec<_> = cron.years().iter().map(|y| y.to_string()).collect();
+        clauses.push(format!("in {}", years.join(", ")));
     }
+    clauses.join(" ")
 }
 
+/// Suspicious property of a schedule found by [`Schedule::lint`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScheduleLint {
+    /// The schedule does not fire at any time from now on, e.g. on February 30th or only in
+    /// past years.
+    NeverFires,
+    /// The schedule fires every second, which is usually a mistake for `0 * * * * *`.
+    EverySecond,
+}
+
+impl Display for ScheduleLint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ScheduleLint::NeverFires => "schedule never fires",
+            ScheduleLint::EverySecond => "schedule fires every second",
+        })
+    }
+}
+
+// Shape of the values a field of a cron expression matches.
+enum Unit {
+    All,
+    Single(u32),
+    // Every nth value starting at the smallest value of the field.
+    Step(u32),
+    List(Vec<u32>),
+}
+
+impl Unit {
+    fn of(spec: &impl TimeUnitSpec, min: u32, max: u32) -> Unit {
+        let values: Vec<u32> = spec.iter().collect();
+        match values.as_slice() {
+            _ if spec.is_all() => Unit::All,
+            [value] => Unit::Single(*value),
+            [first, second, ..] => {
+                let step = second - first;
+                let stepped = *first == min
+                    && values.windows(2).all(|w| w[1] - w[0] == step)
+                    && values[values.len() - 1] + step > max;
+                if stepped {
+                    Unit::Step(step)
+                } else {
+                    Unit::List(values)
+                }
+            }
+            [] => Unit::List(values),
+        }
+    }
+
+    fn describe(&self, name: &str) -> String {
+        match self {
+            Unit::All => format!("every {}", name),
+            Unit::Single(value) => format!("{} {}", name, value),
+            Unit::Step(step) => format!("every {} {}s", step, name),
+            Unit::List(values) => {
+                let values: Vec<_> = values.iter().map(|v| v.to_string()).collect();
+                format!("{}s {}", name, values.join(", "))
+            }
+        }
+    }
+}
+
+fn weekday_name(day: u32) -> &'static str {
+    const NAMES: [&str; 7] = [
+        "Sunday",
+        "Monday",
+        "Tuesday",
+        "Wednesday",
+        "Thursday",
+        "Friday",
+        "Saturday",
+    ];
+    NAMES[(day as usize + 6) % 7]
+}
+
+fn month_name(month: u32) -> &'static str {
+    const NAMES: [&str; 12] = [
+        "January",
+        "February",
+        "March",
+        "April",
+        "May",
+        "June",
+        "July",
+        "August",
+        "September",
+        "October",
+        "November",
+        "December",
+    ];
+    NAMES[(month as usize + 11) % 12]
+}
+
+/// Parses cron expressions with seconds, `sec min hour day month weekday [year]`, and
+/// standard 5-field crontab expressions, `min hour day month weekday`, which fire at second 0.
+/// Weekdays of crontab expressions count from 0 or 7 for Sunday as in crontab, while
+/// expressions with seconds count from 1 for Sunday. Schedules combined with
+/// [`Schedule::or`] and [`Schedule::and`] are written as `any(<schedule>; ...)` and
+/// `all(<schedule>; ...)`. A schedule prefixed with `CRON_TZ=<time zone>`, with a time zone
+/// of the IANA database like `Europe/Berlin`, is read in that time zone, see
+/// [`Schedule::in_time_zone`].
 impl FromStr for Schedule {
     type Err = InvalidCronExpression;
 
     fn from_str(s: &str) -> std::result::Result<Schedule, InvalidCronExpression> {
-        cron::Schedule::from_str(s)
-            .map_err(|e| InvalidCronExpression {
-                expression: s.to_owned(),
-                msg: e.to_string(),
-            })
-            .map(Schedule)
+        let invalid = |msg: String| InvalidCronExpression {
+            expression: s.to_owned(),
+            msg,
+        };
+        let trimmed = s.trim();
+        if let Some(zoned) = trimmed.strip_prefix("CRON_TZ=") {
+            let (tz, schedule) = zoned
+                .split_once(char::is_whitespace)
+                .ok_or_else(|| invalid("missing schedule after the time zone".to_owned()))?;
+            let tz =
+                Tz::from_str(tz).map_err(|_| invalid(format!("unknown time zone {:?}", tz)))?;
+            return Ok(Schedule::from_str(schedule)?.in_time_zone(tz));
+        }
+        for (prefix, combine) in [
+            ("any(", Expr::Any as fn(Vec<Schedule>) -> Expr),
+            ("all(", Expr::All),
+        ] {
+            let Some(inner) = trimmed.strip_prefix(prefix) else {
+                continue;
+            };
+            let inner = inner
+                .strip_suffix(')')
+                .ok_or_else(|| invalid("missing closing parenthesis".to_owned()))?;
+            let schedules = split_top_level(inner)
+                .map_err(invalid)?
+                .into_iter()
+                .map(Schedule::from_str)
+                .collect::<std::result::Result<Vec<_>, _>>()?;
+            if schedules.is_empty() {
+                return Err(invalid("no schedules to combine".to_owned()));
+            }
+            return Ok(Schedule(combine(schedules)));
+        }
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        let expression = match fields.len() {
+            1 if trimmed.starts_with('@') => trimmed.to_owned(),
+            5 => {
+                let weekday = crontab_weekdays(fields[4]).map_err(invalid)?;
+                format!("0 {} {}", fields[..4].join(" "), weekday)
+            }
+            // Trimmed, so that the schedules of a combined one read back as they were written.
+            6 | 7 => trimmed.to_owned(),
+            n => {
+                return Err(invalid(format!(
+                    "expected 5 fields (min hour day month weekday) or 6 to 7 fields with \
+                     seconds (sec min hour day month weekday [year]), got {}",
+                    n
+                )))
+            }
+        };
+        cron::Schedule::from_str(&expression)
+            .map_err(|e| invalid(e.to_string()))
+            .map(|cron| Schedule(Expr::Cron(Box::new(cron))))
     }
 }
 
+// Split the schedules of a combined schedule at the semicolons outside of nested parentheses.
+fn split_top_level(s: &str) -> std::result::Result<Vec<&str>, String> {
+    let mut parts = Vec::new();
+    let mut depth = 0usize;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth = depth
+                    .checked_sub(1)
+                    .ok_or_else(|| "unbalanced parentheses".to_owned())?
+            }
+            ';' if depth == 0 => {
+                parts.push(&s[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if depth != 0 {
+        return Err("unbalanced parentheses".to_owned());
+    }
+    parts.push(&s[start..]);
+    Ok(parts
+        .into_iter()
+        .filter(|part| !part.trim().is_empty())
+        .collect())
+}
+
+// Translate the weekday field of a crontab expression, where Sunday is 0 or 7, to the
+// numbering of expressions with seconds, where Sunday is 1. Names are kept as they are.
+fn crontab_weekdays(field: &str) -> std::result::Result<String, String> {
+    let weekday = |day: &str| -> std::result::Result<Option<u32>, String> {
+        match day.trim().parse::<u32>() {
+            Ok(day @ 0..=7) => Ok(Some(day)),
+            Ok(day) => Err(format!("weekday {} out of range 0-7", day)),
+            Err(_) => Ok(None),
+        }
+    };
+    let mut items = Vec::new();
+    for item in field.split(',') {
+        let (days, step) = match item.split_once('/') {
+            Some((days, step)) => (days, Some(step)),
+            None => (item, None),
+        };
+        let days = match days.split_once('-') {
+            Some((from, to)) => match (weekday(from)?, weekday(to)?) {
+                (Some(0), Some(7)) => "1-7".to_owned(),
+                // A range ending on Sunday, as 7 or as 0 after another day, wraps around to
+                // Sunday as 1, which is included if the step lands on it.
+                (Some(from @ 1..=7), Some(0 | 7)) => {
+                    let step = match step {
+                        Some(step) => step
+                            .trim()
+                            .parse::<u32>()
+                            .ok()
+                            .filter(|step| *step > 0)
+                            .ok_or_else(|| format!("invalid step {}", step))?,
+                        None => 1,
+                    };
+                    if from == 7 {
+                        "1-1".to_owned()
+                    } else {
+                        if (7 - from) % step == 0 {
+                            items.push("1".to_owned());
+                        }
+                        format!("{}-7", from + 1)
+                    }
+                }
+                (Some(from), Some(to)) => format!("{}-{}", from % 7 + 1, to % 7 + 1),
+                _ => days.to_owned(),
+            },
+            None => match weekday(days)? {
+                Some(day) => (day % 7 + 1).to_string(),
+                None => days.to_owned(),
+            },
+        };
+        items.push(match step {
+            Some(step) => format!("{}/{}", days, step),
+            None => days,
+        });
+    }
+    Ok(items.join(","))
+}
+
 impl From<Schedule> for String {
     fn from(value: Schedule) -> Self {
-        value.0.to_string()
+        let join = |schedules: Vec<Schedule>| {
+            let expressions: Vec<String> = schedules.into_iter().map(String::from).collect();
+            expressions.join("; ")
+        };
+        match value.0 {
+            Expr::Cron(cron) => cron.to_string(),
+            Expr::Any(schedules) => format!("any({})", join(schedules)),
+            Expr::All(schedules) => format!("all({})", join(schedules)),
+            Expr::Zoned(tz, schedule) => {
+                format!("CRON_TZ={} {}", tz.name(), String::from(*schedule))
+            }
+        }
     }
 }
 
 pub fn secondly() -> Schedule {
-    Schedule(
+    Schedule(Expr::Cron(Box::new(
         cron::Schedule::from_str("* * * * * *").expect("secondly cron expression should parse"),
-    )
+    )))
 }
 pub fn minutely() -> Schedule {
-    Schedule(
+    Schedule(Expr::Cron(Box::new(
         cron::Schedule::from_str("0 * * * * *").expect("minutely cron expression should parse"),
-    )
+    )))
 }
 pub fn every_five_minutes() -> Schedule {
-    Schedule(
+    Schedule(Expr::Cron(Box::new(
         cron::Schedule::from_str("0 */5 * * * *")
             .expect("every_five_minutes cron expression should parse"),
-    )
+    )))
+}
+
+/// Once a day at a random time within the window from `start` to `end` in UTC, which wraps
+/// around midnight if `end` is not after `start`. Registering many similar jobs, e.g. one per
+/// tenant, with such a schedule spreads their runs over the window instead of starting them
+/// all at once.
+///
+/// The time is picked when the schedule is created. The instance that creates the job's
+/// document persists the schedule with it, and all instances follow the persisted schedule,
+/// so the time stays the same across restarts and instances.
+pub fn daily_random_between(start: NaiveTime, end: NaiveTime) -> Schedule {
+    const DAY_SECS: u32 = 24 * 60 * 60;
+    let start_secs = start.num_seconds_from_midnight();
+    let window = match end.num_seconds_from_midnight() {
+        end_secs if end_secs > start_secs => end_secs - start_secs,
+        end_secs => DAY_SECS - start_secs + end_secs,
+    };
+    let at = (start_secs + rand::thread_rng().gen_range(0..window.max(1))) % DAY_SECS;
+    let expression = format!("{} {} {} * * *", at % 60, at / 60 % 60, at / 3600);
+    Schedule(Expr::Cron(Box::new(
+        cron::Schedule::from_str(&expression)
+            .expect("daily_random_between cron expression should parse"),
+    )))
 }
 
 pub struct InvalidCronExpression {
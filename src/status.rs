@@ -0,0 +1,123 @@
+use crate::job::{lock_ttl_tight, JobData};
+use crate::{JobMetadata, JobName, RunId, RunOutcome, RunRecord};
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+
+/// Current status of a job as derived from its repo document and run history.
+#[derive(Clone, Debug, PartialEq)]
+pub struct JobStatus {
+    pub name: JobName,
+    pub enabled: bool,
+    /// Time of the last completed run, `None` if the job never completed a run.
+    pub last_run: Option<DateTime<Utc>>,
+    /// Next time the schedule fires after the last run. May lie in the past for due jobs.
+    pub next_run: Option<DateTime<Utc>>,
+    pub is_due_now: bool,
+    /// Instance holding the job's lock, i.e. running the job right now.
+    pub running_on: Option<String>,
+    /// Number of runs since the last successful one, not counting skipped and dry runs.
+    pub consecutive_failures: u32,
+    /// Whether a recent run lasted at least half the lock TTL, see
+    /// [`crate::JobEvent::LockTtlTight`].
+    pub lock_ttl_tight: bool,
+    pub metadata: JobMetadata,
+    /// Due run waiting for [`crate::JobManager::approve`].
+    pub pending_approval: Option<RunId>,
+    /// Latest heartbeat of the running run, see [`crate::JobContext::heartbeat`].
+    pub last_heartbeat: Option<DateTime<Utc>>,
+    /// Whether the job is archived, see [`crate::JobManager::archive`].
+    pub archived: bool,
+    /// Instance the job is pinned to, see [`crate::JobConfig::with_pinning`].
+    pub pinned_to: Option<String>,
+}
+
+impl JobStatus {
+    // `runs` are the most recent runs of the job, newest first.
+    pub(crate) fn new(jdata: &JobData, runs: &[RunRecord], now: DateTime<Utc>) -> Self {
+        let never_ran = jdata.last_run == DateTime::<Utc>::default();
+        JobStatus {
+            name: jdata.name.clone(),
+            enabled: jdata.enabled,
+            last_run: (!never_ran).then_some(jdata.last_run),
+            next_run: jdata.next_run,
+            is_due_now: jdata.due(now),
+            running_on: jdata.locked_by(now).map(str::to_owned),
+            consecutive_failures: runs
+                .iter()
+                .filter(|run| {
+                    !run.dry_run
+                        && run.as_of.is_none()
+                        && !matches!(run.outcome, RunOutcome::Skipped(_))
+                })
+                .take_while(|run| run.outcome != RunOutcome::Succeeded)
+                .count() as u32,
+            lock_ttl_tight: runs
+                .iter()
+                .filter(|run| !matches!(run.outcome, RunOutcome::Skipped(_)))
+                .filter_map(|run| (run.finished - run.started).to_std().ok())
+                .any(|duration| lock_ttl_tight(jdata.lock_ttl, duration)),
+            metadata: jdata.metadata.clone(),
+            pending_approval: jdata
+                .approval
+                .as_ref()
+                .filter(|approval| !approval.approved)
+                .map(|approval| approval.run_id.clone()),
+            last_heartbeat: jdata.last_heartbeat,
+            archived: jdata.archived,
+            pinned_to: jdata.pinned_to(now).map(str::to_owned),
+        }
+    }
+}
+
+/// Cluster-wide view of the jobs as recorded in the repo, the same whichever instance reads
+/// it, see [`crate::JobManager::cluster_overview`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClusterOverview {
+    pub as_of: DateTime<Utc>,
+    /// Jobs running right now by the instance holding their locks.
+    pub running: BTreeMap<String, Vec<JobName>>,
+    /// Enabled jobs that are due but not running, the longest due first.
+    pub overdue: Vec<JobName>,
+    /// Running jobs whose last heartbeat is older than their lock TTL, so that their lock
+    /// is still refreshed but the run seems to make no progress.
+    pub stale_locks: Vec<JobName>,
+    pub statuses: Vec<JobStatus>,
+}
+
+impl ClusterOverview {
+    // `jobs` pairs every job with its most recent runs, newest first.
+    pub(crate) fn new(jobs: &[(JobData, Vec<RunRecord>)], now: DateTime<Utc>) -> Self {
+        let mut running: BTreeMap<String, Vec<JobName>> = BTreeMap::new();
+        let mut overdue = Vec::new();
+        let mut stale_locks = Vec::new();
+        for (jdata, _) in jobs {
+            match jdata.locked_by(now) {
+                Some(owner) => {
+                    running
+                        .entry(owner.to_owned())
+                        .or_default()
+                        .push(jdata.name.clone());
+                    let stale = jdata.last_heartbeat.is_some_and(|beat| {
+                        (now - beat).to_std().unwrap_or_default() > jdata.lock_ttl
+                    });
+                    if stale {
+                        stale_locks.push(jdata.name.clone());
+                    }
+                }
+                None if jdata.due(now) => overdue.push((jdata.next_run, jdata.name.clone())),
+                None => {}
+            }
+        }
+        overdue.sort_by_key(|(next_run, _)| *next_run);
+        ClusterOverview {
+            as_of: now,
+            running,
+            overdue: overdue.into_iter().map(|(_, name)| name).collect(),
+            stale_locks,
+            statuses: jobs
+                .iter()
+                .map(|(jdata, runs)| JobStatus::new(jdata, runs, now))
+                .collect(),
+        }
+    }
+}
@@ -0,0 +1,181 @@
+use crate::backoff::{Backoff, Jitter};
+use crate::executor::{self, Settings};
+use crate::repos::Repo;
+use crate::{Job, JobConfig, JobContext, JobError, JobEvent, JobName, JobOutput};
+use async_trait::async_trait;
+use log::{error, trace, warn};
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::oneshot::Receiver;
+use tokio::sync::{oneshot, Mutex};
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, timeout};
+
+const RESTART_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_RESTART_BACKOFF: Duration = Duration::from_secs(60);
+// Time a canceled executor gets beyond the job's cancel deadline to record the aborted run.
+const ABORT_GRACE: Duration = Duration::from_secs(10);
+
+/// The job's action shared by all incarnations of its executor, so that a restarted executor
+/// picks up the action of the one that panicked, and kept by the manager, so that a stopped job
+/// can be started again.
+#[derive(Clone)]
+pub(crate) struct SharedJob {
+    action: Arc<Mutex<Box<dyn Job + Send>>>,
+    state_version: u32,
+}
+
+impl SharedJob {
+    pub(crate) fn new(action: Box<dyn Job + Send>) -> Self {
+        SharedJob {
+            state_version: action.state_version(),
+            action: Arc::new(Mutex::new(action)),
+        }
+    }
+}
+
+#[async_trait]
+impl Job for SharedJob {
+    async fn call(&mut self, ctx: &JobContext, state: Vec<u8>) -> Result<JobOutput, JobError> {
+        self.action.lock().await.call(ctx, state).await
+    }
+    fn state_version(&self) -> u32 {
+        self.state_version
+    }
+    async fn migrate_state(
+        &mut self,
+        from_version: u32,
+        state: Vec<u8>,
+    ) -> Result<Vec<u8>, JobError> {
+        self.action
+            .lock()
+            .await
+            .migrate_state(from_version, state)
+            .await
+    }
+    async fn shrink_state(&mut self, state: Vec<u8>, max_size: usize) -> Result<Vec<u8>, JobError> {
+        self.action.lock().await.shrink_state(state, max_size).await
+    }
+}
+
+/// Run the job's executor and respawn it after a backoff whenever its task panics or is
+/// aborted, until it exits on its own, the job is canceled or `restart_limit` is used up.
+pub(crate) async fn supervise<J: Repo + Clone + Send + 'static>(
+    settings: Settings,
+    config: JobConfig,
+    action: SharedJob,
+    repo: J,
+    mut cancel: Receiver<()>,
+    delay: Duration,
+) {
+    let name = config.name.clone();
+    let events = settings.events.clone();
+    let restart_limit = settings.restart_limit;
+    let mut backoff = Backoff::new(RESTART_BACKOFF, MAX_RESTART_BACKOFF).with_jitter(Jitter::Equal);
+    let mut delay = delay;
+    loop {
+        let (tx, rx) = oneshot::channel();
+        let mut handle = spawn_executor(
+            &name,
+            executor::run(
+                settings.clone(),
+                config.clone(),
+                Box::new(action.clone()),
+                repo.clone(),
+                rx,
+                delay,
+            ),
+        );
+        let (joined, canceled) = tokio::select! {
+            joined = &mut handle => (joined, false),
+            _ = &mut cancel => {
+                // Forward the cancel and let the executor wind down its run. An executor that
+                // does not even get to abort the run, e.g. because the job blocks its thread,
+                // is aborted itself.
+                let _ = tx.send(());
+                match timeout(config.cancel_deadline + ABORT_GRACE, &mut handle).await {
+                    Ok(joined) => (joined, true),
+                    Err(_) => {
+                        error!("executor of job {:?} did not stop, aborting it", &name);
+                        handle.abort();
+                        if let Err(e) = repo.clone().unlock(name.clone(), settings.instance.clone()).await {
+                            error!("releasing the lock of job {:?} failed: {}", &name, e);
+                        }
+                        events.emit(JobEvent::Stopped { name });
+                        return;
+                    }
+                }
+            }
+        };
+        let e = match joined {
+            Ok(Ok(())) => {
+                trace!("job {:?} stopped", &name);
+                return;
+            }
+            Ok(Err(e)) => {
+                warn!("job {:?} stopped with an error: {:?}", &name, e);
+                return;
+            }
+            Err(e) => e,
+        };
+        events.emit(JobEvent::ExecutorPanicked {
+            name: name.clone(),
+            error: e.to_string(),
+        });
+        if canceled {
+            events.emit(JobEvent::Stopped { name });
+            return;
+        }
+        if backoff.attempts() >= restart_limit {
+            error!(
+                "executor of job {:?} failed: {}, restart limit of {} reached, giving up",
+                &name, e, restart_limit
+            );
+            events.emit(JobEvent::Stopped { name });
+            return;
+        }
+        let restart_delay = backoff.next_delay();
+        error!(
+            "executor of job {:?} failed: {}, restarting in {}ms ({} of {})",
+            &name,
+            e,
+            restart_delay.as_millis(),
+            backoff.attempts(),
+            restart_limit
+        );
+        let mut draining = settings.draining.clone();
+        let canceled = tokio::select! {
+            _ = sleep(restart_delay) => false,
+            _ = &mut cancel => true,
+            _ = draining.wait_for(|draining| *draining) => true
+        };
+        if canceled {
+            events.emit(JobEvent::Stopped { name });
+            return;
+        }
+        delay = Duration::ZERO;
+    }
+}
+
+/// Spawn an executor task, named "executor:<job name>" for tokio-console when the
+/// `tokio-console` feature is enabled and tokio is built with `tokio_unstable`.
+fn spawn_executor<F>(name: &JobName, fut: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    #[cfg(all(tokio_unstable, feature = "tokio-console"))]
+    {
+        let task_name = format!("executor:{}", name.as_str());
+        tokio::task::Builder::new()
+            .name(task_name.as_str())
+            .spawn(fut)
+            .expect("spawning executor task should succeed")
+    }
+    #[cfg(not(all(tokio_unstable, feature = "tokio-console")))]
+    {
+        let _ = name;
+        tokio::spawn(fut)
+    }
+}
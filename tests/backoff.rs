@@ -0,0 +1,71 @@
+use ply_jobs::{Backoff, Jitter};
+use std::time::Duration;
+
+fn millis(delays: &[u64]) -> Vec<Duration> {
+    delays.iter().copied().map(Duration::from_millis).collect()
+}
+
+#[test]
+fn delays_double_up_to_the_max() {
+    let mut backoff = Backoff::new(Duration::from_millis(100), Duration::from_secs(1));
+    let delays: Vec<_> = (0..6).map(|_| backoff.next_delay()).collect();
+    assert_eq!(delays, millis(&[100, 200, 400, 800, 1000, 1000]));
+    assert_eq!(backoff.attempts(), 6);
+}
+
+#[test]
+fn max_below_base_is_the_base() {
+    let mut backoff = Backoff::new(Duration::from_secs(2), Duration::from_secs(1));
+    assert_eq!(backoff.next_delay(), Duration::from_secs(2));
+    assert_eq!(backoff.next_delay(), Duration::from_secs(2));
+}
+
+#[test]
+fn many_attempts_do_not_overflow() {
+    let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(60));
+    for _ in 0..100 {
+        assert!(backoff.next_delay() <= Duration::from_secs(60));
+    }
+    assert_eq!(backoff.next_delay(), Duration::from_secs(60));
+}
+
+#[test]
+fn reset_starts_over_from_the_base() {
+    let mut backoff = Backoff::new(Duration::from_millis(100), Duration::from_secs(1));
+    for _ in 0..4 {
+        backoff.next_delay();
+    }
+    backoff.reset();
+    assert_eq!(backoff.attempts(), 0);
+    let delays: Vec<_> = (0..2).map(|_| backoff.next_delay()).collect();
+    assert_eq!(delays, millis(&[100, 200]));
+}
+
+#[test]
+fn jittered_delays_stay_within_their_bounds() {
+    let base = Duration::from_millis(100);
+    let max = Duration::from_secs(1);
+    for jitter in [Jitter::Full, Jitter::Equal, Jitter::Decorrelated] {
+        let mut backoff = Backoff::new(base, max).with_jitter(jitter);
+        for attempt in 0..10 {
+            let exponential = (base * 2u32.pow(attempt)).min(max);
+            let delay = backoff.next_delay();
+            let low = match jitter {
+                Jitter::Full => Duration::ZERO,
+                Jitter::Equal => exponential / 2,
+                _ => base,
+            };
+            let high = match jitter {
+                Jitter::Decorrelated => max,
+                _ => exponential,
+            };
+            assert!(
+                (low..=high).contains(&delay),
+                "{:?} delay {:?} of attempt {}",
+                jitter,
+                delay,
+                attempt
+            );
+        }
+    }
+}
@@ -0,0 +1,15 @@
+use pickledb::{PickleDb, PickleDbDumpPolicy, SerializationMethod};
+use ply_jobs::{conformance, CachedRepo, PickleDbRepo};
+use std::time::Duration;
+
+#[tokio::test]
+async fn cached_repo_conforms() {
+    let path = std::env::temp_dir().join(format!("ply-jobs-cache-{}.db", std::process::id()));
+    let db = PickleDb::new(
+        path,
+        PickleDbDumpPolicy::NeverDump,
+        SerializationMethod::Json,
+    );
+    let repo = CachedRepo::new(PickleDbRepo::new(db)).with_ttl(Duration::from_secs(5));
+    conformance::run_all(repo, "cache").await;
+}
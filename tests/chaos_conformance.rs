@@ -0,0 +1,15 @@
+use pickledb::{PickleDb, PickleDbDumpPolicy, SerializationMethod};
+use ply_jobs::{conformance, ChaosRepo, PickleDbRepo};
+use std::time::Duration;
+
+#[tokio::test]
+async fn delayed_chaos_repo_conforms() {
+    let path = std::env::temp_dir().join(format!("ply-jobs-chaos-{}.db", std::process::id()));
+    let db = PickleDb::new(
+        path,
+        PickleDbDumpPolicy::NeverDump,
+        SerializationMethod::Json,
+    );
+    let repo = ChaosRepo::new(PickleDbRepo::new(db)).with_delays(0.2, Duration::from_millis(20));
+    conformance::run_all(repo, "chaos").await;
+}
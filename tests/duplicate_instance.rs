@@ -0,0 +1,79 @@
+use pickledb::{PickleDb, PickleDbDumpPolicy, SerializationMethod};
+use ply_jobs::schedule::Schedule;
+use ply_jobs::{
+    Job, JobConfig, JobContext, JobError, JobManager, JobOutput, OnDuplicateInstance, PickleDbRepo,
+};
+use std::str::FromStr;
+use std::time::Duration;
+
+struct Noop;
+
+#[async_trait::async_trait]
+impl Job for Noop {
+    async fn call(&mut self, _ctx: &JobContext, state: Vec<u8>) -> Result<JobOutput, JobError> {
+        Ok(state.into())
+    }
+}
+
+fn manager(repo: &PickleDbRepo, job: &str) -> JobManager<PickleDbRepo> {
+    let mut manager = JobManager::new("shared-id", repo.clone())
+        .with_duplicate_instance_check(OnDuplicateInstance::Refuse);
+    let daily = Schedule::from_str("0 0 0 * * *").unwrap();
+    manager.register(JobConfig::new(job, daily), Noop);
+    manager
+}
+
+// Whether an executor of the manager exited, as it does when the manager drains.
+fn drained(manager: &JobManager<PickleDbRepo>) -> bool {
+    manager
+        .transition_metrics()
+        .samples
+        .iter()
+        .any(|sample| sample.labels.get("to").map(String::as_str) == Some("done"))
+}
+
+#[test]
+fn restart_after_crash_is_no_duplicate() {
+    let path = std::env::temp_dir().join(format!("ply-jobs-duplicate-{}.db", std::process::id()));
+    let db = PickleDb::new(
+        path,
+        PickleDbDumpPolicy::NeverDump,
+        SerializationMethod::Json,
+    );
+    let repo = PickleDbRepo::new(db);
+
+    // The first incarnation announces itself and crashes without draining.
+    let crashed = tokio::runtime::Runtime::new().unwrap();
+    crashed.block_on(async {
+        let mut first = manager(&repo, "first");
+        first.start_all();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        std::mem::forget(first);
+    });
+    crashed.shutdown_background();
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .start_paused(true)
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        let mut restarted = manager(&repo, "restarted");
+        restarted.start_all();
+        tokio::time::sleep(Duration::from_secs(35)).await;
+        assert!(
+            !drained(&restarted),
+            "the restarted instance must keep running"
+        );
+
+        // A live duplicate started later still gives way.
+        let mut duplicate = manager(&repo, "duplicate");
+        duplicate.start_all();
+        tokio::time::sleep(Duration::from_secs(35)).await;
+        assert!(drained(&duplicate), "the duplicate must drain");
+        assert!(
+            !drained(&restarted),
+            "the restarted instance must keep running"
+        );
+    });
+}
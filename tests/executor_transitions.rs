@@ -0,0 +1,108 @@
+use async_trait::async_trait;
+use chrono::{DateTime, TimeZone, Utc};
+use pickledb::{PickleDb, PickleDbDumpPolicy, SerializationMethod};
+use ply_jobs::schedule::Schedule;
+use ply_jobs::{ExecutorHarness, Job, JobConfig, JobContext, JobError, JobOutput, PickleDbRepo};
+use std::str::FromStr;
+use std::time::Duration;
+
+struct CountJob;
+
+#[async_trait]
+impl Job for CountJob {
+    async fn call(&mut self, _ctx: &JobContext, state: Vec<u8>) -> Result<JobOutput, JobError> {
+        let count = state.first().copied().unwrap_or_default();
+        Ok(vec![count + 1].into())
+    }
+}
+
+fn repo(test: &str) -> PickleDbRepo {
+    let path = std::env::temp_dir().join(format!("ply-jobs-{}-{}.db", test, std::process::id()));
+    let db = PickleDb::new(
+        path,
+        PickleDbDumpPolicy::NeverDump,
+        SerializationMethod::Json,
+    );
+    PickleDbRepo::new(db)
+}
+
+fn hourly(name: &str) -> JobConfig {
+    JobConfig::new(name, Schedule::from_str("0 0 * * * *").unwrap())
+        .with_check_interval(Duration::from_secs(60))
+}
+
+fn half_past_midnight() -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2026, 1, 1, 0, 30, 0).unwrap()
+}
+
+#[tokio::test(start_paused = true)]
+async fn due_job_runs_once_and_sleeps_until_due_again() {
+    let mut harness = ExecutorHarness::new(
+        "instance-a",
+        hourly("hourly"),
+        CountJob,
+        repo("transitions-due"),
+        half_past_midnight(),
+    );
+    assert_eq!(
+        harness.run_until("sleeping", 10).await,
+        ["start", "check_due", "try_lock", "run", "sleeping"]
+    );
+    // Checked every minute until the job is due again at 1:00.
+    let states = harness.run_until("run", 100).await;
+    assert_eq!(
+        states.iter().filter(|state| **state == "sleeping").count(),
+        30
+    );
+    assert!(states.ends_with(&["check_due", "try_lock", "run"]));
+}
+
+#[tokio::test(start_paused = true)]
+async fn stopped_executor_is_done() {
+    let mut harness = ExecutorHarness::new(
+        "instance-a",
+        hourly("stopped"),
+        CountJob,
+        repo("transitions-stopped"),
+        half_past_midnight(),
+    );
+    harness.run_until("sleeping", 10).await;
+    harness.stop();
+    assert_eq!(harness.step().await, "done");
+    assert_eq!(harness.step().await, "done");
+}
+
+#[tokio::test(start_paused = true)]
+async fn fast_path_keeps_the_lock_between_runs() {
+    let config = JobConfig::new("fast", Schedule::from_str("*/5 * * * * *").unwrap())
+        .with_check_interval(Duration::from_secs(60))
+        .with_fast_path();
+    let repo = repo("transitions-fast-path");
+    let mut a = ExecutorHarness::new(
+        "instance-a",
+        config.clone(),
+        CountJob,
+        repo.clone(),
+        half_past_midnight(),
+    );
+    let mut b = ExecutorHarness::new("instance-b", config, CountJob, repo, half_past_midnight());
+    assert_eq!(
+        a.run_until("sleeping", 10).await,
+        ["start", "check_due", "try_lock", "run", "sleeping"]
+    );
+    // The job fires again while instance a still sleeps, but a kept the lock.
+    tokio::time::advance(Duration::from_secs(6)).await;
+    assert_eq!(
+        b.run_until("sleeping", 10).await,
+        ["start", "check_due", "try_lock", "sleeping"]
+    );
+    assert_eq!(
+        a.run_until("run", 10).await,
+        ["check_due", "try_lock", "run"]
+    );
+    // Stopping releases the kept lock.
+    assert_eq!(a.step().await, "sleeping");
+    a.stop();
+    assert_eq!(a.step().await, "done");
+    assert!(b.run_until("run", 10).await.ends_with(&["try_lock", "run"]));
+}
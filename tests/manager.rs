@@ -0,0 +1,240 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Duration as ChronoDuration, TimeZone, Utc};
+use futures::StreamExt;
+use pickledb::{PickleDb, PickleDbDumpPolicy, SerializationMethod};
+use ply_jobs::schedule::Schedule;
+use ply_jobs::{
+    Error, ExecutorHarness, Job, JobConfig, JobContext, JobError, JobEvent, JobManager, JobName,
+    JobOutput, PickleDbRepo, SkipReason,
+};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+
+struct Noop;
+
+#[async_trait]
+impl Job for Noop {
+    async fn call(&mut self, _ctx: &JobContext, state: Vec<u8>) -> Result<JobOutput, JobError> {
+        Ok(state.into())
+    }
+}
+
+// Records the start of its runs, holding the run until released if given a hold.
+struct Recorded {
+    name: &'static str,
+    runs: Arc<Mutex<Vec<&'static str>>>,
+    hold: Option<Arc<Notify>>,
+}
+
+#[async_trait]
+impl Job for Recorded {
+    async fn call(&mut self, _ctx: &JobContext, state: Vec<u8>) -> Result<JobOutput, JobError> {
+        self.runs.lock().unwrap().push(self.name);
+        if let Some(hold) = &self.hold {
+            hold.notified().await;
+        }
+        Ok(state.into())
+    }
+}
+
+fn repo(test: &str) -> PickleDbRepo {
+    let path = std::env::temp_dir().join(format!("ply-jobs-{}-{}.db", test, std::process::id()));
+    let db = PickleDb::new(
+        path,
+        PickleDbDumpPolicy::NeverDump,
+        SerializationMethod::Json,
+    );
+    PickleDbRepo::new(db)
+}
+
+fn schedule(expression: &str) -> Schedule {
+    Schedule::from_str(expression).unwrap()
+}
+
+fn yearly() -> Schedule {
+    schedule("0 0 0 1 1 *")
+}
+
+async fn eventually(what: &str, condition: impl Fn() -> bool) {
+    let waited = tokio::time::timeout(Duration::from_secs(10), async {
+        while !condition() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await;
+    assert!(waited.is_ok(), "timed out waiting for {}", what);
+}
+
+fn noop(config: JobConfig) -> (JobConfig, Box<dyn Job + Send>) {
+    (config, Box::new(Noop))
+}
+
+fn midnight() -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2026, 1, 1, 0, 0, 0).unwrap()
+}
+
+#[tokio::test(start_paused = true)]
+async fn simulate_uses_the_schedule_kept_in_the_repo() {
+    let repo = repo("manager-simulate");
+    // Another instance created the job with an hourly schedule.
+    let hourly = JobConfig::new("report", schedule("0 0 * * * *"));
+    let mut harness = ExecutorHarness::new("instance-a", hourly, Noop, repo.clone(), midnight());
+    harness.run_until("sleeping", 10).await;
+
+    let mut manager = JobManager::new("instance-b", repo);
+    manager.register(JobConfig::new("report", schedule("0 0 0 * * *")), Noop);
+    manager.register(JobConfig::new("cleanup", schedule("0 0 12 * * *")), Noop);
+    let day = midnight()..midnight() + ChronoDuration::days(1);
+    let runs = manager.simulate(day.clone()).await.unwrap();
+    let report = JobName("report".into());
+    assert_eq!(runs.iter().filter(|(name, _)| *name == report).count(), 23);
+    assert!(runs.contains(&(
+        JobName("cleanup".into()),
+        midnight() + ChronoDuration::hours(12)
+    )));
+
+    manager.pause(report.clone()).await.unwrap();
+    let runs = manager.simulate(day).await.unwrap();
+    assert!(runs.iter().all(|(name, _)| *name != report));
+}
+
+#[test]
+fn register_all_reports_every_problem_and_registers_none() {
+    let mut manager = JobManager::new("instance-a", repo("manager-register-all"));
+    manager.register(JobConfig::new("report", yearly()), Noop);
+    let result = manager.register_all([
+        noop(JobConfig::new("", yearly())),
+        noop(JobConfig::new("report", yearly())),
+        noop(
+            JobConfig::new("cleanup", yearly())
+                .with_check_interval(Duration::ZERO)
+                .with_lock_ttl(Duration::ZERO),
+        ),
+        noop(JobConfig::new("export", yearly())),
+        noop(JobConfig::new("export", yearly())),
+    ]);
+    let Err(Error::InvalidJobs(invalid)) = result else {
+        panic!("expected invalid jobs, got {:?}", result);
+    };
+    let problem = |name: &str, problem: &str| (JobName(name.into()), problem.to_string());
+    assert_eq!(
+        invalid,
+        [
+            problem("", "has an empty name"),
+            problem("report", "is registered more than once"),
+            problem("cleanup", "has a zero check interval"),
+            problem("cleanup", "has a zero lock TTL"),
+            problem("export", "is registered more than once"),
+        ]
+    );
+
+    // None of the valid jobs was registered either.
+    manager
+        .register_all([
+            noop(JobConfig::new("cleanup", yearly())),
+            noop(JobConfig::new("export", yearly())),
+        ])
+        .unwrap();
+}
+
+#[tokio::test]
+async fn freed_quota_slots_go_to_the_highest_priority_waiting() {
+    let runs = Arc::new(Mutex::new(Vec::new()));
+    let hold = Arc::new(Notify::new());
+    let job = |name, priority| {
+        JobConfig::new(name, yearly())
+            .with_group("reports")
+            .with_priority(priority)
+            .with_check_interval(Duration::from_secs(1))
+    };
+    let recorded = |name, hold: Option<Arc<Notify>>| Recorded {
+        name,
+        runs: runs.clone(),
+        hold,
+    };
+    let mut manager =
+        JobManager::new("instance-a", repo("manager-priority")).with_group_quota("reports", 1);
+    manager.register(job("slow", 0), recorded("slow", Some(hold.clone())));
+    manager.register(job("low", 1), recorded("low", None));
+    manager.register(job("high", 9), recorded("high", None));
+    // The first runs of the new jobs pass, the slow one with the hold given ahead.
+    hold.notify_one();
+    manager.start_all();
+    eventually("the first runs", || runs.lock().unwrap().len() == 3).await;
+    runs.lock().unwrap().clear();
+
+    manager.trigger(JobName("slow".into())).await.unwrap();
+    eventually("the slow run", || runs.lock().unwrap().len() == 1).await;
+    // Both wait for the slot the slow run holds, the low priority job first.
+    manager.trigger(JobName("low".into())).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    manager.trigger(JobName("high".into())).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    assert_eq!(*runs.lock().unwrap(), ["slow"]);
+
+    hold.notify_one();
+    eventually("the waiting runs", || runs.lock().unwrap().len() == 3).await;
+    assert_eq!(*runs.lock().unwrap(), ["slow", "high", "low"]);
+}
+
+#[tokio::test]
+async fn saturated_quota_sheds_due_runs_of_low_priority() {
+    let runs = Arc::new(Mutex::new(Vec::new()));
+    let hold = Arc::new(Notify::new());
+    let job = |name, schedule, priority| {
+        JobConfig::new(name, schedule)
+            .with_group("reports")
+            .with_priority(priority)
+            .with_check_interval(Duration::from_secs(1))
+    };
+    let mut manager = JobManager::new("instance-a", repo("manager-shedding"))
+        .with_group_quota("reports", 1)
+        .with_load_shedding("reports", Duration::from_secs(1), 5);
+    let critical = Recorded {
+        name: "critical",
+        runs: runs.clone(),
+        hold: Some(hold.clone()),
+    };
+    let batch = Recorded {
+        name: "batch",
+        runs: runs.clone(),
+        hold: None,
+    };
+    manager.register(job("critical", yearly(), 9), critical);
+    manager.register(job("batch", schedule("* * * * * *"), 1), batch);
+    let skips = Arc::new(Mutex::new(Vec::new()));
+    let mut events = Box::pin(manager.events());
+    tokio::spawn({
+        let skips = skips.clone();
+        async move {
+            while let Some(event) = events.next().await {
+                if let JobEvent::RunSkipped { name, reason } = event {
+                    skips.lock().unwrap().push((name, reason));
+                }
+            }
+        }
+    });
+    hold.notify_one();
+    manager.start_all();
+    eventually("the first runs", || runs.lock().unwrap().len() >= 2).await;
+
+    manager.trigger(JobName("critical".into())).await.unwrap();
+    eventually("the run holding the slot", || {
+        runs.lock()
+            .unwrap()
+            .iter()
+            .filter(|name| **name == "critical")
+            .count()
+            == 2
+    })
+    .await;
+    runs.lock().unwrap().clear();
+    let shed = (JobName("batch".into()), SkipReason::Shed);
+    eventually("a shed run", || skips.lock().unwrap().contains(&shed)).await;
+    assert!(runs.lock().unwrap().is_empty());
+
+    hold.notify_one();
+    eventually("the batch run", || runs.lock().unwrap().contains(&"batch")).await;
+}
@@ -0,0 +1,77 @@
+use mongodb::Client;
+use ply_jobs::{conformance, DocumentFormat, MongoRepo};
+
+// Start MongoDB with `docker-compose -f examples/counter/docker-compose.yml up -d` and run
+// `cargo test --features mongodb,test-util -- --ignored`.
+#[tokio::test]
+#[ignore = "requires a MongoDB server on localhost:27017"]
+async fn mongo_repo_conforms() {
+    let client = Client::with_uri_str("mongodb://localhost:27017")
+        .await
+        .unwrap();
+    let collection = format!("conformance_{}", std::process::id());
+    let repo = MongoRepo::new(client.clone(), "test", collection.as_str());
+    repo.create_indexes().await.unwrap();
+    conformance::run_all(repo.clone(), "conformance").await;
+    conformance::last_run_millis(
+        repo.with_document_format(DocumentFormat::Structured),
+        "conformance",
+    )
+    .await;
+    client
+        .database("test")
+        .collection::<mongodb::bson::Document>(collection.as_str())
+        .drop(None)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+#[ignore = "requires a MongoDB server on localhost:27017"]
+async fn mixed_format_mongo_repo_conforms() {
+    let client = Client::with_uri_str("mongodb://localhost:27017")
+        .await
+        .unwrap();
+    let collection = format!("conformance_mixed_{}", std::process::id());
+    let repo = MongoRepo::new(client.clone(), "test", collection.as_str())
+        .with_document_format(DocumentFormat::Structured);
+    conformance::run_all(repo.clone(), "conformance").await;
+    // Documents of both formats are read, and all of them end up in the structured format.
+    conformance::run_all(
+        MongoRepo::new(client.clone(), "test", collection.as_str()),
+        "legacy",
+    )
+    .await;
+    assert!(repo.migrate_documents().await.unwrap() > 0);
+    assert_eq!(repo.migrate_documents().await.unwrap(), 0);
+    client
+        .database("test")
+        .collection::<mongodb::bson::Document>(collection.as_str())
+        .drop(None)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+#[ignore = "requires a MongoDB server on localhost:27017"]
+async fn spilling_mongo_repo_conforms() {
+    let client = Client::with_uri_str("mongodb://localhost:27017")
+        .await
+        .unwrap();
+    let collection = format!("conformance_spilling_{}", std::process::id());
+    // Spill every state that is not empty.
+    let repo = MongoRepo::new(client.clone(), "test", collection.as_str()).with_state_spillover(0);
+    conformance::run_all(repo, "conformance").await;
+    let database = client.database("test");
+    for collection in [
+        collection.clone(),
+        format!("{}_state.files", collection),
+        format!("{}_state.chunks", collection),
+    ] {
+        database
+            .collection::<mongodb::bson::Document>(collection.as_str())
+            .drop(None)
+            .await
+            .unwrap();
+    }
+}
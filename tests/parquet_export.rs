@@ -0,0 +1,63 @@
+use chrono::{TimeZone, Utc};
+use parquet::file::reader::{FileReader, SerializedFileReader};
+use ply_jobs::{JobName, RunId, RunOutcome, RunRecord, Snapshot};
+use std::fs::File;
+
+#[test]
+fn runs_round_trip() {
+    let started = Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap();
+    let run = |outcome| RunRecord {
+        run_id: RunId("0123456789abcdef".into()),
+        name: JobName("report".into()),
+        instance: "a".into(),
+        started,
+        finished: started + chrono::Duration::milliseconds(1500),
+        outcome,
+        log: String::new(),
+        log_truncated: false,
+        dry_run: false,
+        as_of: None,
+        trace_parent: None,
+        events: Vec::new(),
+        result: None,
+    };
+    let snapshot = Snapshot {
+        jobs: Vec::new(),
+        runs: vec![
+            run(RunOutcome::Succeeded),
+            run(RunOutcome::Failed("boom".into())),
+        ],
+    };
+    let path = std::env::temp_dir().join(format!("ply-jobs-runs-{}.parquet", std::process::id()));
+    snapshot
+        .write_runs_parquet(File::create(&path).unwrap())
+        .unwrap();
+
+    let reader = SerializedFileReader::new(File::open(&path).unwrap()).unwrap();
+    let metadata = reader.metadata();
+    assert_eq!(metadata.file_metadata().num_rows(), 2);
+    let columns: Vec<&str> = metadata
+        .file_metadata()
+        .schema_descr()
+        .columns()
+        .iter()
+        .map(|column| column.name())
+        .collect();
+    assert_eq!(
+        columns,
+        [
+            "run_id",
+            "name",
+            "instance",
+            "started",
+            "finished",
+            "duration_ms",
+            "outcome",
+            "reason",
+            "dry_run",
+            "as_of",
+            "trace_parent"
+        ]
+    );
+    std::fs::remove_file(path).unwrap();
+}
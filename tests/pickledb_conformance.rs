@@ -0,0 +1,38 @@
+use pickledb::{PickleDb, PickleDbDumpPolicy, SerializationMethod};
+use ply_jobs::{conformance, PickleDbRepo};
+
+#[tokio::test]
+async fn pickledb_repo_conforms() {
+    let path = std::env::temp_dir().join(format!("ply-jobs-conformance-{}.db", std::process::id()));
+    let db = PickleDb::new(
+        path,
+        PickleDbDumpPolicy::NeverDump,
+        SerializationMethod::Json,
+    );
+    conformance::run_all(PickleDbRepo::new(db), "conformance").await;
+}
+
+#[tokio::test]
+async fn pickledb_key_prefixes_separate_tenants() {
+    let path = std::env::temp_dir().join(format!("ply-jobs-prefixes-{}.db", std::process::id()));
+    let db = PickleDb::new(
+        path,
+        PickleDbDumpPolicy::NeverDump,
+        SerializationMethod::Json,
+    );
+    let repo = PickleDbRepo::new(db);
+    let billing = repo.clone().with_key_prefix("billing:");
+    let reports = repo.with_key_prefix("reports:");
+    // Same job names in both keyspaces, creating them twice would fail without the prefixes.
+    conformance::run_all(billing.clone(), "conformance").await;
+    conformance::run_all(reports.clone(), "conformance").await;
+
+    let jobs = billing.list_prefix("conformance-").await;
+    assert!(!jobs.is_empty());
+    assert_eq!(
+        billing.delete_prefix("conformance-").await.unwrap(),
+        jobs.len() as u64
+    );
+    assert!(billing.list_prefix("conformance-").await.is_empty());
+    assert_eq!(reports.list_prefix("conformance-").await.len(), jobs.len());
+}
@@ -0,0 +1,23 @@
+use pickledb::{PickleDb, PickleDbDumpPolicy, SerializationMethod};
+use ply_jobs::{conformance, PickleDbRepo, RedlockRepo};
+
+// Start Redis with `docker run -d -p 6379:6379 redis` and run
+// `cargo test --features pickledb,test-util,redlock -- --ignored`. The databases of the
+// server stand in for independent nodes.
+#[tokio::test]
+#[ignore = "requires a Redis server on localhost:6379"]
+async fn redlock_repo_conforms() {
+    let path = std::env::temp_dir().join(format!("ply-jobs-redlock-{}.db", std::process::id()));
+    let db = PickleDb::new(
+        path,
+        PickleDbDumpPolicy::NeverDump,
+        SerializationMethod::Json,
+    );
+    let nodes = [
+        "redis://localhost:6379/0",
+        "redis://localhost:6379/1",
+        "redis://localhost:6379/2",
+    ];
+    let repo = RedlockRepo::new(PickleDbRepo::new(db), &nodes).unwrap();
+    conformance::run_all(repo, "redlock").await;
+}
@@ -0,0 +1,219 @@
+use async_trait::async_trait;
+use chrono::{TimeZone, Utc};
+use pickledb::{PickleDb, PickleDbDumpPolicy, SerializationMethod};
+use ply_jobs::{
+    Error, Job, JobContext, JobDefinition, JobError, JobFilter, JobManager, JobName, JobOutput,
+    JobRegistry, JobTemplate, PickleDbRepo, TEMPLATE_LABEL,
+};
+use serde_json::json;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// Records the customer it was constructed for on every run.
+struct Report {
+    customer: String,
+    runs: Arc<Mutex<Vec<String>>>,
+}
+
+#[async_trait]
+impl Job for Report {
+    async fn call(&mut self, _ctx: &JobContext, state: Vec<u8>) -> Result<JobOutput, JobError> {
+        self.runs.lock().unwrap().push(self.customer.clone());
+        Ok(state.into())
+    }
+}
+
+fn registry(runs: &Arc<Mutex<Vec<String>>>) -> JobRegistry {
+    let runs = runs.clone();
+    JobRegistry::new().with_job("report", move |params| {
+        let customer = params["customer"].as_str().ok_or("customer missing")?;
+        Ok(Report {
+            customer: customer.to_owned(),
+            runs: runs.clone(),
+        })
+    })
+}
+
+fn repo(test: &str) -> PickleDbRepo {
+    let path = std::env::temp_dir().join(format!("ply-jobs-{}-{}.db", test, std::process::id()));
+    let db = PickleDb::new(
+        path,
+        PickleDbDumpPolicy::NeverDump,
+        SerializationMethod::Json,
+    );
+    PickleDbRepo::new(db)
+}
+
+fn definition(json: &str) -> JobDefinition {
+    serde_json::from_str(json).unwrap()
+}
+
+fn template() -> JobTemplate {
+    serde_json::from_str(
+        r#"{"name": "report", "kind": "report", "schedule": "0 0 2 * * *",
+            "metadata": {"labels": {"tier": "gold"}}}"#,
+    )
+    .unwrap()
+}
+
+async fn eventually(what: &str, condition: impl Fn() -> bool) {
+    let waited = tokio::time::timeout(Duration::from_secs(10), async {
+        while !condition() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await;
+    assert!(waited.is_ok(), "timed out waiting for {}", what);
+}
+
+#[test]
+fn definition_config() {
+    let config = definition(
+        r#"{"name": "report-acme", "kind": "report", "schedule": "0 0 2 * * *",
+            "lock_ttl_secs": 120, "metadata": {"owner_team": "billing", "labels": {}}}"#,
+    )
+    .config()
+    .unwrap();
+    assert_eq!(config.name, JobName("report-acme".into()));
+    assert!(config.enabled);
+    assert_eq!(config.lock_ttl, Duration::from_secs(120));
+    assert_eq!(config.check_interval, Duration::from_secs(60));
+    assert_eq!(config.metadata.owner_team.as_deref(), Some("billing"));
+    let midnight = Utc.with_ymd_and_hms(2026, 1, 1, 0, 0, 0).unwrap();
+    assert_eq!(
+        config.schedule.next_after(&midnight),
+        Some(Utc.with_ymd_and_hms(2026, 1, 1, 2, 0, 0).unwrap())
+    );
+
+    let invalid = definition(r#"{"name": "report-acme", "kind": "report", "schedule": "daily"}"#);
+    assert!(invalid.config().is_err());
+}
+
+#[test]
+fn kinds_in_alphabetical_order() {
+    let runs = Arc::new(Mutex::new(Vec::new()));
+    let registry = registry(&runs).with_job("cleanup", |_| {
+        Ok(Report {
+            customer: String::new(),
+            runs: Arc::default(),
+        })
+    });
+    assert_eq!(registry.kinds().collect::<Vec<_>>(), ["cleanup", "report"]);
+}
+
+#[test]
+fn unknown_kinds_and_failing_factories_are_not_registered() {
+    let runs = Arc::new(Mutex::new(Vec::new()));
+    let registry = registry(&runs);
+    let mut manager = JobManager::new("instance-a", repo("registry-invalid"));
+
+    let unknown = definition(r#"{"name": "sync", "kind": "sync", "schedule": "0 0 2 * * *"}"#);
+    let result = manager.register_definition(&registry, unknown);
+    assert!(matches!(result, Err(Error::UnknownJobKind(kind)) if kind == "sync"));
+
+    let no_customer =
+        definition(r#"{"name": "report-acme", "kind": "report", "schedule": "0 0 2 * * *"}"#);
+    let result = manager.register_definition(&registry, no_customer);
+    assert!(matches!(
+        result,
+        Err(Error::JobFactoryFailed(name, error))
+            if name == JobName("report-acme".into()) && error.contains("customer missing")
+    ));
+}
+
+#[tokio::test]
+async fn registered_definitions_run_the_constructed_job() {
+    let runs = Arc::new(Mutex::new(Vec::new()));
+    let registry = registry(&runs);
+    let mut manager = JobManager::new("instance-a", repo("registry-run"));
+    let acme = definition(
+        r#"{"name": "report-acme", "kind": "report", "schedule": "0 0 2 * * *",
+            "params": {"customer": "acme"}}"#,
+    );
+    manager.register_definition(&registry, acme).unwrap();
+    manager.start_all();
+    // New jobs are due right away.
+    eventually("the first run", || !runs.lock().unwrap().is_empty()).await;
+    assert_eq!(*runs.lock().unwrap(), ["acme"]);
+}
+
+#[test]
+fn template_instances() {
+    let acme = template().instance("acme", json!({"customer": "acme"}));
+    assert_eq!(acme.name, "report.acme");
+    assert_eq!(acme.kind, "report");
+    assert_eq!(acme.schedule, "0 0 2 * * *");
+    assert_eq!(acme.params, json!({"customer": "acme"}));
+    assert_eq!(
+        acme.metadata.labels.get("tier").map(String::as_str),
+        Some("gold")
+    );
+    assert_eq!(
+        acme.metadata.labels.get(TEMPLATE_LABEL).map(String::as_str),
+        Some("report")
+    );
+}
+
+#[test]
+fn invalid_template_instances_register_none() {
+    let runs = Arc::new(Mutex::new(Vec::new()));
+    let registry = registry(&runs);
+    let mut manager = JobManager::new("instance-a", repo("registry-template-invalid"));
+    let acme = ("acme", json!({"customer": "acme"}));
+
+    let result = manager.register_template(
+        &registry,
+        &template(),
+        [acme.clone(), ("initech", json!({}))],
+    );
+    assert!(matches!(
+        result,
+        Err(Error::JobFactoryFailed(name, _)) if name == JobName("report.initech".into())
+    ));
+    let result = manager.register_template(&registry, &template(), [acme.clone(), acme.clone()]);
+    let Err(Error::InvalidJobs(invalid)) = result else {
+        panic!("expected invalid jobs, got {:?}", result);
+    };
+    assert_eq!(
+        invalid,
+        [(
+            JobName("report.acme".into()),
+            "is registered more than once".to_string()
+        )]
+    );
+
+    // Neither attempt registered the valid instance.
+    manager
+        .register_template(&registry, &template(), [acme])
+        .unwrap();
+}
+
+#[tokio::test]
+async fn template_instances_run_as_a_group() {
+    let runs = Arc::new(Mutex::new(Vec::new()));
+    let registry = registry(&runs);
+    let mut manager = JobManager::new("instance-a", repo("registry-template-run"));
+    let instances = [
+        ("acme", json!({"customer": "acme"})),
+        ("globex", json!({"customer": "globex", "region": "eu"})),
+    ];
+    manager
+        .register_template(&registry, &template(), instances)
+        .unwrap();
+    manager.start_all();
+    eventually("the first runs", || runs.lock().unwrap().len() == 2).await;
+    let mut customers = runs.lock().unwrap().clone();
+    customers.sort();
+    assert_eq!(customers, ["acme", "globex"]);
+
+    let group = JobFilter::default().with_label(TEMPLATE_LABEL, "report");
+    let mut names: Vec<_> = manager
+        .statuses_matching(&group)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|status| status.name.0)
+        .collect();
+    names.sort();
+    assert_eq!(names, ["report.acme", "report.globex"]);
+}
@@ -0,0 +1,207 @@
+use chrono::{DateTime, Datelike, TimeZone, Utc, Weekday};
+use ply_jobs::schedule::{Schedule, ScheduleLint, Tz};
+use std::str::FromStr;
+
+// Weekdays on which a daily crontab expression with the given weekday field fires within a
+// week starting on a Monday.
+fn weekdays(field: &str) -> Vec<Weekday> {
+    let schedule = Schedule::from_str(&format!("0 0 * * {}", field)).unwrap();
+    let mut last: DateTime<Utc> = Utc.with_ymd_and_hms(2024, 1, 7, 12, 0, 0).unwrap();
+    let end = last + chrono::Duration::days(7);
+    let mut days = Vec::new();
+    while let Some(next) = schedule.next_after(&last).filter(|next| *next < end) {
+        days.push(next.weekday());
+        last = next;
+    }
+    days
+}
+
+#[test]
+fn crontab_weekdays() {
+    use Weekday::*;
+    assert_eq!(weekdays("0"), vec![Sun]);
+    assert_eq!(weekdays("7"), vec![Sun]);
+    assert_eq!(weekdays("1-5"), vec![Mon, Tue, Wed, Thu, Fri]);
+    assert_eq!(weekdays("5-7"), vec![Fri, Sat, Sun]);
+    assert_eq!(weekdays("5-0"), vec![Fri, Sat, Sun]);
+    assert_eq!(weekdays("0-7"), vec![Mon, Tue, Wed, Thu, Fri, Sat, Sun]);
+    assert_eq!(weekdays("*/2"), vec![Tue, Thu, Sat, Sun]);
+    assert_eq!(weekdays("1-7/2"), vec![Mon, Wed, Fri, Sun]);
+    assert_eq!(weekdays("2-7/2"), vec![Tue, Thu, Sat]);
+}
+
+// Times the schedule fires within the day of the given date in UTC.
+fn fires_on(schedule: &Schedule, year: i32, month: u32, day: u32) -> Vec<DateTime<Utc>> {
+    let start = Utc.with_ymd_and_hms(year, month, day, 0, 0, 0).unwrap();
+    schedule
+        .between(start..start + chrono::Duration::days(1))
+        .collect()
+}
+
+#[test]
+fn time_zone_across_daylight_saving_time() {
+    let schedule = Schedule::from_str("CRON_TZ=Europe/Berlin 0 9 * * *").unwrap();
+    assert_eq!(schedule.time_zone(), Some(Tz::Europe__Berlin));
+    let utc = |month, day, hour| Utc.with_ymd_and_hms(2024, month, day, hour, 0, 0).unwrap();
+    // 09:00 is 08:00 UTC in winter and 07:00 UTC in summer.
+    assert_eq!(fires_on(&schedule, 2024, 3, 30), [utc(3, 30, 8)]);
+    assert_eq!(fires_on(&schedule, 2024, 3, 31), [utc(3, 31, 7)]);
+    assert_eq!(fires_on(&schedule, 2024, 10, 26), [utc(10, 26, 7)]);
+    assert_eq!(fires_on(&schedule, 2024, 10, 27), [utc(10, 27, 8)]);
+
+    let written: String = schedule.into();
+    let reread = Schedule::from_str(&written).unwrap();
+    assert_eq!(reread.time_zone(), Some(Tz::Europe__Berlin));
+    assert_eq!(fires_on(&reread, 2024, 3, 31), [utc(3, 31, 7)]);
+
+    let nightly = Schedule::from_str("0 30 2 * * *")
+        .unwrap()
+        .in_time_zone(Tz::Europe__Berlin);
+    let half_past = |month, day, hour| utc(month, day, hour) + chrono::Duration::minutes(30);
+    // 02:30 does not exist on the day summer time starts, it fires at 03:00 instead.
+    assert_eq!(fires_on(&nightly, 2024, 3, 30), [half_past(3, 30, 1)]);
+    assert_eq!(fires_on(&nightly, 2024, 3, 31), [utc(3, 31, 1)]);
+    assert_eq!(fires_on(&nightly, 2024, 4, 1), [half_past(4, 1, 0)]);
+    // 02:30 exists twice on the day summer time ends, it fires at the first one only.
+    assert_eq!(fires_on(&nightly, 2024, 10, 27), [half_past(10, 27, 0)]);
+    assert_eq!(fires_on(&nightly, 2024, 10, 28), [half_past(10, 28, 1)]);
+    // Looking back from within the repeated hour finds the first 02:30.
+    assert_eq!(
+        nightly.previous_before(&half_past(10, 27, 1)),
+        Some(half_past(10, 27, 0))
+    );
+    assert_eq!(nightly.previous_before(&utc(3, 31, 2)), Some(utc(3, 31, 1)));
+
+    // Times within the skipped hour all fire at once after the skip, and times within the
+    // repeated hour fire during its first pass only.
+    let minutely = Schedule::from_str("0 * 2 * * *")
+        .unwrap()
+        .in_time_zone(Tz::Europe__Berlin);
+    assert_eq!(fires_on(&minutely, 2024, 3, 31), [utc(3, 31, 1)]);
+    let repeated: Vec<_> = minutely.between(utc(10, 26, 23)..utc(10, 27, 3)).collect();
+    assert_eq!(repeated.len(), 60);
+    assert_eq!(repeated.first(), Some(&utc(10, 27, 0)));
+    assert_eq!(
+        repeated.last(),
+        Some(&(utc(10, 27, 0) + chrono::Duration::minutes(59)))
+    );
+}
+
+#[test]
+fn combined_schedules() {
+    let at = |day, hour, minute| Utc.with_ymd_and_hms(2024, 1, day, hour, minute, 0).unwrap();
+    // Friday January 5th and Saturday January 6th 2024.
+    let any = Schedule::from_str("any(0 8 * * 1-5; 0 10 * * 6,0)").unwrap();
+    assert_eq!(fires_on(&any, 2024, 1, 5), [at(5, 8, 0)]);
+    assert_eq!(fires_on(&any, 2024, 1, 6), [at(6, 10, 0)]);
+    assert_eq!(any.previous_before(&at(6, 9, 0)), Some(at(5, 8, 0)));
+
+    let all = Schedule::from_str("all(*/20 * * * *; * 9-10 * * *)").unwrap();
+    assert_eq!(
+        fires_on(&all, 2024, 1, 5),
+        [
+            at(5, 9, 0),
+            at(5, 9, 20),
+            at(5, 9, 40),
+            at(5, 10, 0),
+            at(5, 10, 20),
+            at(5, 10, 40)
+        ]
+    );
+    assert_eq!(all.previous_before(&at(5, 12, 0)), Some(at(5, 10, 40)));
+
+    // Combined schedules nest, and the nested ones may have time zones of their own.
+    let nested =
+        Schedule::from_str("any(all(0 * * * *; * 6 * * *); CRON_TZ=Europe/Berlin 0 12 * * *)")
+            .unwrap();
+    assert_eq!(fires_on(&nested, 2024, 1, 5), [at(5, 6, 0), at(5, 11, 0)]);
+
+    // Built schedules are written as parsed ones, and are read back as they were.
+    let built = Schedule::from_str("0 8 * * 1-5")
+        .unwrap()
+        .or(Schedule::from_str("0 10 * * 6,0").unwrap());
+    let written: String = built.into();
+    assert_eq!(written, String::from(any));
+    for schedule in [written, String::from(nested.clone())] {
+        let reread = Schedule::from_str(&schedule).unwrap();
+        assert_eq!(String::from(reread), schedule);
+    }
+    let written: String = nested.into();
+    assert_eq!(
+        fires_on(&Schedule::from_str(&written).unwrap(), 2024, 1, 5),
+        [at(5, 6, 0), at(5, 11, 0)]
+    );
+
+    for invalid in [
+        "any(0 8 * * *",
+        "all()",
+        "any(0 8 * * *; (0 9 * * *)",
+        "all(0 8 * *)",
+    ] {
+        assert!(Schedule::from_str(invalid).is_err(), "{}", invalid);
+    }
+}
+
+#[test]
+fn disjoint_intersection_gives_up() {
+    // Second 0 and second 30 of the first minute of every hour never coincide, which the
+    // search gives up on after a bounded number of steps instead of looping forever.
+    let never = Schedule::from_str("all(0 0 * * * *; 30 0 * * * *)").unwrap();
+    let start = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+    assert_eq!(never.next_after(&start), None);
+    assert_eq!(never.previous_before(&start), None);
+    assert_eq!(
+        never
+            .between(start..start + chrono::Duration::days(1))
+            .count(),
+        0
+    );
+}
+
+#[test]
+fn describe() {
+    let described = |expression: &str| Schedule::from_str(expression).unwrap().describe();
+    assert_eq!(described("0 2 * * 1"), "at 02:00 every Monday");
+    assert_eq!(described("30 15 9 * * *"), "at 09:15:30 every day");
+    assert_eq!(described("*/5 * * * *"), "every 5 minutes");
+    assert_eq!(
+        described("0 9-17 * * 1-5"),
+        "at minute 0 of hours 9, 10, 11, 12, 13, 14, 15, 16, 17 every Monday, Tuesday, \
+         Wednesday, Thursday, Friday"
+    );
+    assert_eq!(
+        described("0 0 1 1,7 *"),
+        "at 00:00 on day 1 of the month in January, July"
+    );
+    assert_eq!(
+        described("any(0 8 * * *; CRON_TZ=Europe/Berlin 0 10 * * 6)"),
+        "whenever any of (at 08:00 every day; at 10:00 every Saturday (Europe/Berlin)) fires"
+    );
+    assert_eq!(
+        described("all(*/20 * * * *; * 9 * * *)"),
+        "whenever all of (every 20 minutes; every minute of hour 9) fire"
+    );
+}
+
+#[test]
+fn lint() {
+    let linted = |expression: &str| Schedule::from_str(expression).unwrap().lint();
+    assert_eq!(linted("0 0 2 * * *"), []);
+    assert_eq!(linted("* * * * * *"), [ScheduleLint::EverySecond]);
+    assert_eq!(linted("0 0 0 30 2 *"), [ScheduleLint::NeverFires]);
+    assert_eq!(linted("0 0 0 1 1 * 2000"), [ScheduleLint::NeverFires]);
+    // Combined schedules fire every second if any of them does, or if all of them do.
+    assert_eq!(
+        linted("any(0 0 2 * * *; * * * * * *)"),
+        [ScheduleLint::EverySecond]
+    );
+    assert_eq!(
+        linted("all(* * * * * *; * * 9 * * *)"),
+        [ScheduleLint::EverySecond]
+    );
+    assert_eq!(linted("all(* * * * * *; 0 * * * * *)"), []);
+    assert_eq!(
+        linted("all(0 0 0 1 1 *; 0 0 0 30 2 *)"),
+        [ScheduleLint::NeverFires]
+    );
+}